@@ -0,0 +1,290 @@
+//! Several jobs in one HTTP request, to save the overhead of a request per
+//! small tile.
+//!
+//! A batch is sent as plain text with one job per line, in the path form or
+//! the query string form. Browsers post plain text without a CORS preflight.
+//! With the `serde` feature, a batch is also read from and written as a list
+//! of jobs. The worker answers with the results in the order of the jobs,
+//! each in the binary form of `RenderResult` prefixed by its length as
+//! big-endian u32.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::{
+    JobValidationError, Limits, RenderJob, RenderJobParseError, RenderResult, RenderResultError,
+};
+
+/// Jobs rendered by one request, answered in the same order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct RenderBatch {
+    pub jobs: Vec<RenderJob>,
+}
+
+impl RenderBatch {
+    /// Media type of the request body.
+    pub const CONTENT_TYPE: &'static str = "text/plain";
+    /// Media type of the response body.
+    pub const RESULTS_CONTENT_TYPE: &'static str = "application/x-clumsy-results";
+
+    pub fn new(jobs: Vec<RenderJob>) -> Self {
+        Self { jobs }
+    }
+
+    /// Checks the batch size and every job against the default limits.
+    pub fn validate(&self) -> Result<(), RenderBatchError> {
+        self.validate_with(&Limits::default())
+    }
+
+    /// Checks the batch size, every job and the rays of all jobs together
+    /// against `limits`.
+    pub fn validate_with(&self, limits: &Limits) -> Result<(), RenderBatchError> {
+        if self.jobs.is_empty() {
+            return Err(RenderBatchError::Empty);
+        }
+        if self.jobs.len() > limits.max_batch_jobs {
+            return Err(RenderBatchError::TooManyJobs {
+                jobs: self.jobs.len(),
+                max: limits.max_batch_jobs,
+            });
+        }
+        for (index, job) in self.jobs.iter().enumerate() {
+            job.validate_with(limits)
+                .map_err(|source| RenderBatchError::InvalidJob { index, source })?;
+        }
+        let rays = self.max_rays();
+        if rays > limits.max_batch_rays {
+            return Err(RenderBatchError::RayBudgetExceeded {
+                rays,
+                max: limits.max_batch_rays,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rays cast by all jobs together, as counted by `RenderJob::max_rays`.
+    pub fn max_rays(&self) -> u64 {
+        self.jobs
+            .iter()
+            .fold(0, |rays, job| rays.saturating_add(job.max_rays()))
+    }
+
+    /// Appends the response body with `results` to `out`.
+    pub fn encode_results(results: &[RenderResult], out: &mut Vec<u8>) {
+        for result in results {
+            let start = out.len();
+            out.extend_from_slice(&[0; 4]);
+            result.encode(out);
+            let len = (out.len() - start - 4) as u32;
+            out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        }
+    }
+
+    /// Reads the response to this batch and checks that it holds one result
+    /// for each job, in order.
+    pub fn decode_results(&self, mut buf: &[u8]) -> Result<Vec<RenderResult>, RenderBatchError> {
+        let mut results = Vec::with_capacity(self.jobs.len());
+        while !buf.is_empty() {
+            let index = results.len();
+            let result_error = |source| RenderBatchError::Result { index, source };
+            let len = buf
+                .get(..4)
+                .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+                .ok_or(result_error(RenderResultError::Truncated))?;
+            let bytes = buf
+                .get(4..4 + len)
+                .ok_or(result_error(RenderResultError::Truncated))?;
+            let result = RenderResult::decode(bytes).map_err(result_error)?;
+            if let Some(job) = self.jobs.get(index) {
+                result.check_job(job).map_err(result_error)?;
+            }
+            results.push(result);
+            buf = &buf[4 + len..];
+        }
+        if results.len() != self.jobs.len() {
+            return Err(RenderBatchError::WrongCount {
+                expected: self.jobs.len(),
+                actual: results.len(),
+            });
+        }
+        Ok(results)
+    }
+}
+
+/// The request body, one job per line.
+impl Display for RenderBatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for job in &self.jobs {
+            writeln!(f, "{job}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RenderBatch {
+    type Err = RenderBatchError;
+
+    /// Reads one job per line, ignoring empty lines. Lines without a `/` are
+    /// in the query string form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let jobs = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(index, line)| {
+                let job = if line.contains('/') {
+                    line.parse()
+                } else {
+                    RenderJob::from_query(line)
+                };
+                job.map_err(|source| RenderBatchError::Job { index, source })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { jobs })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RenderBatchError {
+    #[error("batch contains no jobs")]
+    Empty,
+    #[error("batch of {jobs} jobs exceeds the maximum of {max}")]
+    TooManyJobs { jobs: usize, max: usize },
+    #[error("batch casts up to {rays} rays, more than the maximum of {max}")]
+    RayBudgetExceeded { rays: u64, max: u64 },
+    #[error("job {index} of the batch: {source}")]
+    Job {
+        index: usize,
+        source: RenderJobParseError,
+    },
+    #[error("job {index} of the batch: {source}")]
+    InvalidJob {
+        index: usize,
+        source: JobValidationError,
+    },
+    #[error("result {index} of the batch: {source}")]
+    Result {
+        index: usize,
+        source: RenderResultError,
+    },
+    #[error("expected {expected} results but got {actual}")]
+    WrongCount { expected: usize, actual: usize },
+}
+
+#[cfg(test)]
+fn example() -> RenderBatch {
+    RenderBatch::new(vec![
+        RenderJob::new(0, 0, 8, 8, 16, 8, 1, 1),
+        RenderJob::new(8, 0, 8, 8, 16, 8, 1, 1).with_seed(3),
+    ])
+}
+
+#[test]
+fn batch_request_round_trips() {
+    let batch = example();
+    let body = batch.to_string();
+    assert_eq!(body, "0/0/8/8/16/8/1/1\nv1/8/0/8/8/16/8/1/1/seed=3\n");
+    assert_eq!(body.parse::<RenderBatch>().unwrap(), batch);
+    assert_eq!(
+        "\r\n0/0/8/8/16/8/1/1\r\n\r\n"
+            .parse::<RenderBatch>()
+            .unwrap(),
+        RenderBatch::new(vec![batch.jobs[0].clone()])
+    );
+    assert!(matches!(
+        "0/0/8/8/16/8/1/1\n0/0/8".parse::<RenderBatch>(),
+        Err(RenderBatchError::Job { index: 1, .. })
+    ));
+
+    let mixed = format!("{}\n{}", batch.jobs[0], batch.jobs[1].to_query());
+    assert_eq!(mixed.parse::<RenderBatch>().unwrap(), batch);
+    assert!(matches!(
+        "x=0&y=0".parse::<RenderBatch>(),
+        Err(RenderBatchError::Job {
+            index: 0,
+            source: RenderJobParseError::MissingField("w")
+        })
+    ));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn batch_is_a_json_list_of_jobs() {
+    let batch = example();
+    let json = serde_json::to_string(&batch).unwrap();
+    assert!(json.starts_with(r#"[{"x":0,"#));
+    assert_eq!(serde_json::from_str::<RenderBatch>(&json).unwrap(), batch);
+}
+
+#[test]
+fn batches_are_validated() {
+    let batch = example();
+    assert!(batch.validate().is_ok());
+    assert!(matches!(
+        RenderBatch::default().validate(),
+        Err(RenderBatchError::Empty)
+    ));
+    assert!(matches!(
+        batch.validate_with(&Limits::default().with_max_batch_jobs(1)),
+        Err(RenderBatchError::TooManyJobs { jobs: 2, max: 1 })
+    ));
+    // each job fits, both together do not
+    let rays = batch.jobs[0].max_rays();
+    assert_eq!(batch.max_rays(), 2 * rays);
+    let limits = Limits::default()
+        .with_max_rays(rays)
+        .with_max_batch_rays(2 * rays - 1);
+    assert!(matches!(
+        batch.validate_with(&limits),
+        Err(RenderBatchError::RayBudgetExceeded { max, .. }) if max == 2 * rays - 1
+    ));
+    let mut outside = batch.clone();
+    outside.jobs[1].x = 9;
+    assert!(matches!(
+        outside.validate(),
+        Err(RenderBatchError::InvalidJob {
+            index: 1,
+            source: JobValidationError::OutsideCamera { .. }
+        })
+    ));
+}
+
+#[test]
+fn batch_results_round_trip_in_order() {
+    let batch = example();
+    let results: Vec<_> = batch
+        .jobs
+        .iter()
+        .map(|job| RenderResult::new(job.clone(), vec![1, 2, 3]).with_worker("test"))
+        .collect();
+    let mut body = vec![];
+    RenderBatch::encode_results(&results, &mut body);
+    assert_eq!(batch.decode_results(&body).unwrap(), results);
+
+    assert!(matches!(
+        batch.decode_results(&body[..body.len() - 1]),
+        Err(RenderBatchError::Result { index: 1, .. })
+    ));
+    let mut reversed = vec![];
+    RenderBatch::encode_results(&[results[1].clone(), results[0].clone()], &mut reversed);
+    assert!(matches!(
+        batch.decode_results(&reversed),
+        Err(RenderBatchError::Result {
+            index: 0,
+            source: RenderResultError::JobMismatch { .. }
+        })
+    ));
+    let mut short = vec![];
+    RenderBatch::encode_results(&results[..1], &mut short);
+    assert!(matches!(
+        batch.decode_results(&short),
+        Err(RenderBatchError::WrongCount {
+            expected: 2,
+            actual: 1
+        })
+    ));
+}
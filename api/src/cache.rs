@@ -0,0 +1,190 @@
+//! Identity of jobs by content and caches of their results.
+//!
+//! Jobs with equal settings produce equal images, the renderer is
+//! deterministic for a given seed. A result can therefore be reused for any
+//! job with the same content hash, as long as the renderer is the same.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::{RenderJob, RenderResult};
+
+/// Version of the images the renderer produces, part of every `JobHash`.
+/// Bump it with every change that alters the image of some job, such as
+/// re-blessed golden images, so that persisted caches miss instead of serving
+/// outdated images.
+pub const RENDER_VERSION: u64 = 1;
+
+/// 128-bit hash of all fields of a `RenderJob` and of `RENDER_VERSION`. The
+/// same on every platform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JobHash(pub u128);
+
+impl fmt::Display for JobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl RenderJob {
+    /// FNV-1a over `RENDER_VERSION` and every field, including scene and
+    /// seed, as big-endian u64s.
+    pub fn content_hash(&self) -> JobHash {
+        const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+        const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+        let fields = [
+            RENDER_VERSION,
+            self.x as u64,
+            self.y as u64,
+            self.w as u64,
+            self.h as u64,
+            self.camera_w as u64,
+            self.camera_h as u64,
+            self.n_samples as u64,
+            self.n_recursion as u64,
+            self.integrator as u64,
+            self.projection as u64,
+            self.frame as u64,
+            self.shutter_open as u64,
+            self.shutter as u64,
+            self.filter as u64,
+            self.seed,
+            self.scene as u64,
+            self.format as u64,
+        ];
+        let hash = fields
+            .iter()
+            .flat_map(|field| field.to_be_bytes())
+            .fold(OFFSET, |hash, byte| {
+                (hash ^ byte as u128).wrapping_mul(PRIME)
+            });
+        JobHash(hash)
+    }
+}
+
+/// Storage of finished results, looked up by job.
+///
+/// Implementations key entries by `RenderJob::content_hash` and must only
+/// return results whose job equals the requested one. Caches are best effort,
+/// failing to store a result is not an error.
+pub trait ResultCache {
+    fn get(&self, job: &RenderJob) -> Option<RenderResult>;
+    fn put(&self, result: &RenderResult);
+}
+
+/// In-memory cache of the most recently used results.
+pub struct LruCache {
+    capacity: usize,
+    inner: Mutex<LruInner>,
+}
+
+#[derive(Default)]
+struct LruInner {
+    /// Incremented on every access, entries with the lowest value are evicted.
+    clock: u64,
+    entries: HashMap<JobHash, (u64, RenderResult)>,
+}
+
+impl LruCache {
+    /// A cache holding up to `capacity` results.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ResultCache for LruCache {
+    fn get(&self, job: &RenderJob) -> Option<RenderResult> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let (last_used, result) = inner.entries.get_mut(&job.content_hash())?;
+        if result.job != *job {
+            return None;
+        }
+        *last_used = clock;
+        Some(result.clone())
+    }
+
+    fn put(&self, result: &RenderResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let hash = result.job.content_hash();
+        if !inner.entries.contains_key(&hash) && inner.entries.len() >= self.capacity {
+            // a linear scan, caches hold a few hundred tiles at most
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(hash, (clock, result.clone()));
+    }
+}
+
+#[test]
+fn content_hash_covers_all_fields() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8);
+    assert_eq!(job.content_hash(), job.clone().content_hash());
+    assert_eq!(job.content_hash().to_string().len(), 32);
+
+    let variants = [
+        RenderJob::new(0, 2, 3, 4, 5, 6, 7, 8),
+        RenderJob::new(1, 0, 3, 4, 5, 6, 7, 8),
+        RenderJob::new(1, 2, 0, 4, 5, 6, 7, 8),
+        RenderJob::new(1, 2, 3, 0, 5, 6, 7, 8),
+        RenderJob::new(1, 2, 3, 4, 0, 6, 7, 8),
+        RenderJob::new(1, 2, 3, 4, 5, 0, 7, 8),
+        RenderJob::new(1, 2, 3, 4, 5, 6, 0, 8),
+        RenderJob::new(1, 2, 3, 4, 5, 6, 7, 0),
+        job.clone().with_integrator(crate::IntegratorKind::Normals),
+        job.clone().with_projection(crate::ProjectionKind::Fisheye),
+        job.clone().with_frame(1),
+        job.clone().with_shutter(0, 500),
+        job.clone().with_shutter(500, 0),
+        job.clone().with_filter(crate::FilterKind::Tent),
+        job.clone().with_seed(1),
+        job.clone().with_scene(crate::SceneKind::Hazy),
+        job.clone().with_format(crate::OutputFormat::Ppm),
+    ];
+    let mut hashes: Vec<_> = variants.iter().map(RenderJob::content_hash).collect();
+    hashes.push(job.content_hash());
+    hashes.sort();
+    hashes.dedup();
+    assert_eq!(hashes.len(), variants.len() + 1);
+}
+
+#[test]
+fn lru_cache_evicts_least_recently_used() {
+    let result = |x| RenderResult::new(RenderJob::new(x, 0, 1, 1, 4, 1, 1, 1), vec![x as u8]);
+    let cache = LruCache::new(2);
+    cache.put(&result(0));
+    cache.put(&result(1));
+    // touch 0, so that 1 is evicted next
+    assert_eq!(cache.get(&result(0).job), Some(result(0)));
+    cache.put(&result(2));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&result(1).job), None);
+    assert_eq!(cache.get(&result(0).job), Some(result(0)));
+    assert_eq!(cache.get(&result(2).job), Some(result(2)));
+    // other settings for the same tile are a miss
+    assert_eq!(cache.get(&result(2).job.with_seed(3)), None);
+}
@@ -0,0 +1,336 @@
+//! What a worker can render, announced by HTTP workers at `/capabilities`.
+//!
+//! Coordinators read it before handing out jobs, so that a worker of another
+//! version only gets jobs it understands. Values of the enum lists that this
+//! version does not know are skipped when reading, newer workers stay usable.
+//! Missing fields are read as the least a worker can do: the first job
+//! encoding, only the default of each enum, default limits, one core and no
+//! batches.
+
+use thiserror::Error;
+
+use crate::{
+    FilterKind, IntegratorKind, JobValidationError, Limits, OutputFormat, ProjectionKind,
+    RenderJob, SceneKind,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// Free-form name of the worker, as in `RenderResult::worker`.
+    pub worker: String,
+    /// Version of the worker build.
+    pub version: String,
+    /// Versions of the job encoding the worker reads.
+    #[cfg_attr(feature = "serde", serde(default = "first_job_version"))]
+    pub job_versions: Vec<u32>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "only_default", deserialize_with = "known_values")
+    )]
+    pub integrators: Vec<IntegratorKind>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "only_default", deserialize_with = "known_values")
+    )]
+    pub projections: Vec<ProjectionKind>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "only_default", deserialize_with = "known_values")
+    )]
+    pub filters: Vec<FilterKind>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "only_default", deserialize_with = "known_values")
+    )]
+    pub scenes: Vec<SceneKind>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "only_default", deserialize_with = "known_values")
+    )]
+    pub formats: Vec<OutputFormat>,
+    /// Largest jobs and batches the worker accepts.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: Limits,
+    /// Threads each job is rendered with.
+    #[cfg_attr(feature = "serde", serde(default = "one_core"))]
+    pub cores: u32,
+    /// Whether the worker serves `/batch`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub batch: bool,
+}
+
+impl Capabilities {
+    /// A worker that renders everything this crate can describe, with the
+    /// default limits, on one core and without batches.
+    pub fn new(worker: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            worker: worker.into(),
+            version: version.into(),
+            job_versions: (0..=RenderJob::VERSION).collect(),
+            integrators: IntegratorKind::ALL.to_vec(),
+            projections: ProjectionKind::ALL.to_vec(),
+            filters: FilterKind::ALL.to_vec(),
+            scenes: SceneKind::ALL.to_vec(),
+            formats: OutputFormat::ALL.to_vec(),
+            limits: Limits::default(),
+            cores: 1,
+            batch: false,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_cores(mut self, cores: u32) -> Self {
+        self.cores = cores;
+        self
+    }
+
+    pub fn with_batch(mut self, batch: bool) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Tiles to send per request, at most `preferred`.
+    pub fn batch_size(&self, preferred: usize) -> usize {
+        if self.batch {
+            preferred.min(self.limits.max_batch_jobs).max(1)
+        } else {
+            1
+        }
+    }
+
+    /// Checks that the worker understands `job` and accepts its size.
+    pub fn check_job(&self, job: &RenderJob) -> Result<(), IncompatibleWorker> {
+        let version = job.version();
+        if !self.job_versions.contains(&version) {
+            return Err(IncompatibleWorker::JobVersion(version));
+        }
+        if !self.integrators.contains(&job.integrator) {
+            return Err(IncompatibleWorker::Integrator(job.integrator));
+        }
+        if !self.projections.contains(&job.projection) {
+            return Err(IncompatibleWorker::Projection(job.projection));
+        }
+        if !self.filters.contains(&job.filter) {
+            return Err(IncompatibleWorker::Filter(job.filter));
+        }
+        if !self.scenes.contains(&job.scene) {
+            return Err(IncompatibleWorker::Scene(job.scene));
+        }
+        if !self.formats.contains(&job.format) {
+            return Err(IncompatibleWorker::Format(job.format));
+        }
+        job.validate_with(&self.limits)?;
+        Ok(())
+    }
+}
+
+impl IntegratorKind {
+    pub const ALL: [IntegratorKind; 6] = [
+        IntegratorKind::PathTracer,
+        IntegratorKind::Whitted,
+        IntegratorKind::AmbientOcclusion,
+        IntegratorKind::Normals,
+        IntegratorKind::Depth,
+        IntegratorKind::Bounces,
+    ];
+}
+
+impl ProjectionKind {
+    pub const ALL: [ProjectionKind; 4] = [
+        ProjectionKind::Perspective,
+        ProjectionKind::Orthographic,
+        ProjectionKind::Fisheye,
+        ProjectionKind::Equirectangular,
+    ];
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 4] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+    ];
+}
+
+impl SceneKind {
+    pub const ALL: [SceneKind; 4] = [
+        SceneKind::Cool,
+        SceneKind::Hazy,
+        SceneKind::Simple,
+        SceneKind::Lights,
+    ];
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 2] = [OutputFormat::Png, OutputFormat::Ppm];
+}
+
+#[cfg(feature = "serde")]
+fn first_job_version() -> Vec<u32> {
+    vec![0]
+}
+
+#[cfg(feature = "serde")]
+fn only_default<T: Default>() -> Vec<T> {
+    vec![T::default()]
+}
+
+#[cfg(feature = "serde")]
+fn one_core() -> u32 {
+    1
+}
+
+/// Reads a list of enum values, leaving out those this version does not know.
+#[cfg(feature = "serde")]
+fn known_values<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    use serde::de::{value::Error, IntoDeserializer};
+    use serde::Deserialize;
+
+    let names = Vec::<String>::deserialize(deserializer)?;
+    Ok(names
+        .into_iter()
+        .filter_map(|name| T::deserialize(IntoDeserializer::<Error>::into_deserializer(name)).ok())
+        .collect())
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum IncompatibleWorker {
+    #[error("worker does not read job encoding version {0}")]
+    JobVersion(u32),
+    #[error("worker does not support the {0:?} integrator")]
+    Integrator(IntegratorKind),
+    #[error("worker does not support the {0:?} projection")]
+    Projection(ProjectionKind),
+    #[error("worker does not support the {0:?} filter")]
+    Filter(FilterKind),
+    #[error("worker does not have the {0:?} scene")]
+    Scene(SceneKind),
+    #[error("worker does not produce {0:?} images")]
+    Format(OutputFormat),
+    #[error("worker does not accept the job: {0}")]
+    Limits(#[from] JobValidationError),
+}
+
+#[test]
+fn all_variants_are_listed() {
+    for (i, kind) in IntegratorKind::ALL.into_iter().enumerate() {
+        assert_eq!(IntegratorKind::try_from(i as u32).unwrap(), kind);
+    }
+    for (i, kind) in ProjectionKind::ALL.into_iter().enumerate() {
+        assert_eq!(ProjectionKind::try_from(i as u32).unwrap(), kind);
+    }
+    for (i, kind) in FilterKind::ALL.into_iter().enumerate() {
+        assert_eq!(FilterKind::try_from(i as u32).unwrap(), kind);
+    }
+    for (i, kind) in SceneKind::ALL.into_iter().enumerate() {
+        assert_eq!(SceneKind::try_from(i as u32).unwrap(), kind);
+    }
+    for (i, kind) in OutputFormat::ALL.into_iter().enumerate() {
+        assert_eq!(OutputFormat::try_from(i as u32).unwrap(), kind);
+    }
+    assert!(SceneKind::try_from(SceneKind::ALL.len() as u32).is_err());
+    assert!(IntegratorKind::try_from(IntegratorKind::ALL.len() as u32).is_err());
+}
+
+#[test]
+fn jobs_are_checked_against_capabilities() {
+    let job = RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1);
+    let mut caps = Capabilities::new("test", "0.1.0");
+    assert_eq!(caps.check_job(&job), Ok(()));
+    assert_eq!(caps.check_job(&job.clone().with_seed(1)), Ok(()));
+
+    caps.job_versions = vec![0];
+    assert_eq!(caps.check_job(&job), Ok(()));
+    assert_eq!(
+        caps.check_job(&job.clone().with_seed(1)),
+        Err(IncompatibleWorker::JobVersion(1))
+    );
+    caps.job_versions = vec![0, 1];
+    caps.scenes = vec![SceneKind::Cool];
+    assert_eq!(
+        caps.check_job(&job.clone().with_scene(SceneKind::Lights)),
+        Err(IncompatibleWorker::Scene(SceneKind::Lights))
+    );
+    caps.formats = vec![OutputFormat::Ppm];
+    assert_eq!(
+        caps.check_job(&job),
+        Err(IncompatibleWorker::Format(OutputFormat::Png))
+    );
+    caps = caps.with_limits(Limits::default().with_max_samples(1));
+    assert_eq!(
+        caps.check_job(&job.clone().with_format(OutputFormat::Ppm)),
+        Ok(())
+    );
+    let mut heavy = job.with_format(OutputFormat::Ppm);
+    heavy.n_samples = 2;
+    assert!(matches!(
+        caps.check_job(&heavy),
+        Err(IncompatibleWorker::Limits(
+            JobValidationError::TooManySamples { .. }
+        ))
+    ));
+}
+
+#[test]
+fn batch_size_respects_worker() {
+    let caps = Capabilities::new("test", "0.1.0");
+    assert_eq!(caps.batch_size(8), 1);
+    let caps = caps
+        .with_batch(true)
+        .with_limits(Limits::default().with_max_batch_jobs(4));
+    assert_eq!(caps.batch_size(8), 4);
+    assert_eq!(caps.batch_size(2), 2);
+    assert_eq!(caps.batch_size(0), 1);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_form_skips_unknown_values() {
+    let caps = Capabilities::new("test", "0.1.0")
+        .with_cores(8)
+        .with_batch(true);
+    let json = serde_json::to_string(&caps).unwrap();
+    assert!(json.contains(r#""scenes":["cool","hazy","simple","lights"]"#));
+    assert!(json.contains(r#""max_batch_jobs":256"#));
+    assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), caps);
+
+    // a newer worker with more scenes and fields
+    let json = json
+        .replace(r#""lights""#, r#""lights","city""#)
+        .replace(r#""batch":true"#, r#""batch":true,"gpu":true"#)
+        .replace(
+            r#""max_batch_jobs":256"#,
+            r#""max_batch_jobs":256,"max_frames":9"#,
+        );
+    assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), caps);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_form_defaults_missing_fields() {
+    let caps: Capabilities =
+        serde_json::from_str(r#"{"worker":"old","version":"0.0.1","batch":true}"#).unwrap();
+    assert_eq!(caps.job_versions, vec![0]);
+    assert_eq!(caps.integrators, vec![IntegratorKind::default()]);
+    assert_eq!(caps.scenes, vec![SceneKind::default()]);
+    assert_eq!(caps.formats, vec![OutputFormat::Png]);
+    assert_eq!(caps.limits, Limits::default());
+    assert_eq!(caps.cores, 1);
+    assert!(caps.batch);
+    assert_eq!(
+        caps.check_job(&RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1)),
+        Ok(())
+    );
+
+    assert!(serde_json::from_str::<Capabilities>(r#"{"worker":"old"}"#).is_err());
+}
@@ -0,0 +1,716 @@
+//! Binary and text forms of `RenderJob`.
+//!
+//! Version 0 is the original positional form: the eight numbers from `x` to
+//! `n_recursion`, optionally followed by integrator, projection, frame,
+//! shutter open, shutter and filter. Version 1 starts with a version tag,
+//! then the same eight numbers and then tagged fields, each of which is left
+//! out when it has its default.
+//!
+//! In binary, a tagged field is `[id, n, value_1, .., value_n]`, in text it
+//! is `name=value`. Decoders skip unknown fields, unless they are marked as
+//! required by the top bit of the id, or a `!` before the name. Encoders
+//! produce version 0 when all fields have their default, so that workers
+//! deployed before version 1 can still serve plain jobs.
+//!
+//! For HTTP clients, there is also a query string form with named fields,
+//! `x=0&y=0&w=64&...&integrator=whitted`, where the order does not matter and
+//! optional fields take their default if left out.
+
+use crate::*;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// Binary version tags are this marker with the version in the low bits.
+const VERSION_MARKER: u32 = 0xFFFF_0000;
+/// Set on binary field ids that decoders must understand.
+const REQUIRED: u32 = 1 << 31;
+/// Put before text field names that decoders must understand.
+pub(crate) const REQUIRED_PREFIX: char = '!';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Integrator = 1,
+    Projection = 2,
+    Frame = 3,
+    Filter = 4,
+    ShutterOpen = 5,
+    Shutter = 6,
+    Seed = 7,
+    Scene = 8,
+    Format = 9,
+}
+
+/// Tagged fields, in the order they are written.
+const FIELDS: [Field; 9] = [
+    Field::Integrator,
+    Field::Projection,
+    Field::Frame,
+    Field::Filter,
+    Field::Seed,
+    Field::Scene,
+    Field::Format,
+    Field::ShutterOpen,
+    Field::Shutter,
+];
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::Integrator => "integrator",
+            Field::Projection => "projection",
+            Field::Frame => "frame",
+            Field::Filter => "filter",
+            Field::Seed => "seed",
+            Field::Scene => "scene",
+            Field::Format => "format",
+            Field::ShutterOpen => "shutter_open",
+            Field::Shutter => "shutter",
+        }
+    }
+
+    fn from_id(id: u32) -> Option<Field> {
+        FIELDS.into_iter().find(|&field| field as u32 == id)
+    }
+
+    fn from_name(name: &str) -> Option<Field> {
+        FIELDS.into_iter().find(|field| field.name() == name)
+    }
+
+    /// A worker that ignores the field would render a different image than
+    /// asked for. A different seed only changes the noise, which is fine.
+    fn required(self) -> bool {
+        self != Field::Seed
+    }
+
+    /// Number of u32 values in the binary form.
+    fn len(self) -> usize {
+        match self {
+            Field::Seed => 2,
+            _ => 1,
+        }
+    }
+
+    /// Names of the values of enum fields, in the order of their codes.
+    fn value_names(self) -> &'static [&'static str] {
+        match self {
+            Field::Integrator => &[
+                "path_tracer",
+                "whitted",
+                "ambient_occlusion",
+                "normals",
+                "depth",
+                "bounces",
+            ],
+            Field::Projection => &["perspective", "orthographic", "fisheye", "equirectangular"],
+            Field::Filter => &["box", "tent", "gaussian", "mitchell"],
+            Field::Scene => &["cool", "hazy", "simple", "lights"],
+            Field::Format => &["png", "ppm"],
+            Field::Frame | Field::Seed | Field::ShutterOpen | Field::Shutter => &[],
+        }
+    }
+
+    fn malformed(self) -> RenderJobParseError {
+        RenderJobParseError::MalformedField(self.name().to_owned())
+    }
+}
+
+impl RenderJob {
+    /// Highest encoding version this crate reads and writes.
+    pub const VERSION: u32 = 1;
+
+    /// Numbers in the version 0 form. Jobs with only the first
+    /// `MIN_NUM_FIELDS` numbers use defaults for the rest.
+    pub const NUM_FIELDS: usize = 14;
+    pub const MIN_NUM_FIELDS: usize = 8;
+
+    /// Lowest encoding version that can describe this job, 0 if all
+    /// optional fields have their default.
+    pub fn version(&self) -> u32 {
+        if self.tagged_fields().is_empty() {
+            0
+        } else {
+            Self::VERSION
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u32> {
+        let mut vec = self.base_fields().to_vec();
+        let tagged = self.tagged_fields();
+        if tagged.is_empty() {
+            return vec;
+        }
+        vec.insert(0, VERSION_MARKER | Self::VERSION);
+        for (field, value) in tagged {
+            let required = if field.required() { REQUIRED } else { 0 };
+            vec.extend([field as u32 | required, field.len() as u32]);
+            match field.len() {
+                2 => vec.extend([(value >> 32) as u32, value as u32]),
+                _ => vec.push(value as u32),
+            }
+        }
+        vec
+    }
+
+    pub fn try_from_slice(data: &[u32]) -> Result<RenderJob, RenderJobParseError> {
+        match data.first() {
+            Some(&tag) if tag & VERSION_MARKER == VERSION_MARKER => {
+                let version = tag & !VERSION_MARKER;
+                if version == 0 || version > Self::VERSION {
+                    return Err(RenderJobParseError::UnsupportedVersion(version));
+                }
+                let base = data.get(1..9).ok_or(RenderJobParseError::Truncated)?;
+                let mut job = Self::from_base_fields(base);
+                let mut rest = &data[9..];
+                while let [id, n, ..] = *rest {
+                    let end = (n as usize)
+                        .checked_add(2)
+                        .filter(|&end| end <= rest.len())
+                        .ok_or(RenderJobParseError::Truncated)?;
+                    let values = &rest[2..end];
+                    rest = &rest[end..];
+                    match Field::from_id(id & !REQUIRED) {
+                        Some(field) if values.len() == field.len() => {
+                            let value = values
+                                .iter()
+                                .fold(0u64, |acc, &word| (acc << 32) | word as u64);
+                            job.set(field, value)?;
+                        }
+                        Some(field) => return Err(field.malformed()),
+                        None if id & REQUIRED != 0 => {
+                            return Err(RenderJobParseError::UnknownRequiredField(
+                                (id & !REQUIRED).to_string(),
+                            ))
+                        }
+                        None => {}
+                    }
+                }
+                if !rest.is_empty() {
+                    return Err(RenderJobParseError::Truncated);
+                }
+                Ok(job)
+            }
+            _ => Self::from_version_0(data),
+        }
+    }
+
+    fn from_version_0(data: &[u32]) -> Result<RenderJob, RenderJobParseError> {
+        if data.len() < Self::MIN_NUM_FIELDS || data.len() > Self::NUM_FIELDS {
+            return Err(RenderJobParseError::IncorrectLength {
+                min: Self::MIN_NUM_FIELDS,
+                max: Self::NUM_FIELDS,
+                actual: data.len(),
+            });
+        }
+        let mut job = Self::from_base_fields(&data[..Self::MIN_NUM_FIELDS]);
+        let positional = [
+            Field::Integrator,
+            Field::Projection,
+            Field::Frame,
+            Field::ShutterOpen,
+            Field::Shutter,
+            Field::Filter,
+        ];
+        for (&field, &value) in positional.iter().zip(&data[Self::MIN_NUM_FIELDS..]) {
+            job.set(field, value as u64)?;
+        }
+        Ok(job)
+    }
+
+    fn base_fields(&self) -> [u32; 8] {
+        [
+            self.x,
+            self.y,
+            self.w,
+            self.h,
+            self.camera_w,
+            self.camera_h,
+            self.n_samples,
+            self.n_recursion,
+        ]
+    }
+
+    fn from_base_fields(data: &[u32]) -> RenderJob {
+        Self::new(
+            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+        )
+    }
+
+    /// Fields that differ from their default, with their value.
+    fn tagged_fields(&self) -> Vec<(Field, u64)> {
+        let default = Self::new(0, 0, 0, 0, 0, 0, 0, 0);
+        FIELDS
+            .into_iter()
+            .map(|field| (field, self.get(field)))
+            .filter(|&(field, value)| value != default.get(field))
+            .collect()
+    }
+
+    fn get(&self, field: Field) -> u64 {
+        match field {
+            Field::Integrator => self.integrator as u64,
+            Field::Projection => self.projection as u64,
+            Field::Frame => self.frame as u64,
+            Field::Filter => self.filter as u64,
+            Field::Seed => self.seed,
+            Field::Scene => self.scene as u64,
+            Field::Format => self.format as u64,
+            Field::ShutterOpen => self.shutter_open as u64,
+            Field::Shutter => self.shutter as u64,
+        }
+    }
+
+    fn set(&mut self, field: Field, value: u64) -> Result<(), RenderJobParseError> {
+        if field == Field::Seed {
+            self.seed = value;
+            return Ok(());
+        }
+        let value = u32::try_from(value).map_err(|_| field.malformed())?;
+        match field {
+            Field::Integrator => self.integrator = IntegratorKind::try_from(value)?,
+            Field::Projection => self.projection = ProjectionKind::try_from(value)?,
+            Field::Frame => self.frame = value,
+            Field::Filter => self.filter = FilterKind::try_from(value)?,
+            Field::Scene => self.scene = SceneKind::try_from(value)?,
+            Field::Format => self.format = OutputFormat::try_from(value)?,
+            Field::ShutterOpen => self.shutter_open = value,
+            Field::Shutter => self.shutter = value,
+            Field::Seed => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Names of the fields that the query string form requires.
+const BASE_FIELD_NAMES: [&str; 8] = [
+    "x",
+    "y",
+    "w",
+    "h",
+    "camera_w",
+    "camera_h",
+    "samples",
+    "recursion",
+];
+
+/// Whether `name` is a field of the named forms, the query and JSON.
+#[cfg(feature = "serde")]
+pub(crate) fn is_field_name(name: &str) -> bool {
+    BASE_FIELD_NAMES.contains(&name) || Field::from_name(name).is_some()
+}
+
+impl RenderJob {
+    /// Parses the query string form, with or without the leading `?`. Enum
+    /// fields take names, like `integrator=whitted`, or their numeric codes.
+    /// Names and values may be percent-encoded, with `+` for a space.
+    pub fn from_query(query: &str) -> Result<RenderJob, RenderJobParseError> {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        let mut base = [None; 8];
+        let mut tagged = vec![];
+        let mut seen = vec![];
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (name, value) = (percent_decode(name), percent_decode(value));
+            let (name, value) = (name.as_str(), value.as_str());
+            if seen.iter().any(|seen| seen == name) {
+                return Err(RenderJobParseError::DuplicateField(name.to_owned()));
+            }
+            seen.push(name.to_owned());
+            let invalid = || RenderJobParseError::InvalidValue {
+                field: name.to_owned(),
+                value: value.to_owned(),
+            };
+            if let Some(i) = BASE_FIELD_NAMES.iter().position(|&base| base == name) {
+                base[i] = Some(value.parse::<u32>().map_err(|_| invalid())?);
+            } else if let Some(field) = Field::from_name(name) {
+                let code = match field.value_names().iter().position(|&n| n == value) {
+                    Some(code) => code as u64,
+                    None => value.parse::<u64>().map_err(|_| invalid())?,
+                };
+                tagged.push((field, code));
+            } else {
+                return Err(RenderJobParseError::UnknownField(name.to_owned()));
+            }
+        }
+        let mut values = [0; 8];
+        for ((value, parsed), name) in values.iter_mut().zip(base).zip(BASE_FIELD_NAMES) {
+            *value = parsed.ok_or(RenderJobParseError::MissingField(name))?;
+        }
+        let mut job = Self::from_base_fields(&values);
+        for (field, code) in tagged {
+            job.set(field, code)?;
+        }
+        Ok(job)
+    }
+
+    /// The query string form, without the leading `?`, leaving out optional
+    /// fields that have their default.
+    pub fn to_query(&self) -> String {
+        let base = BASE_FIELD_NAMES
+            .iter()
+            .zip(self.base_fields())
+            .map(|(name, value)| format!("{name}={value}"));
+        let tagged = self.tagged_fields().into_iter().map(|(field, code)| {
+            match field.value_names().get(code as usize) {
+                Some(value) => format!("{}={value}", field.name()),
+                None => format!("{}={code}", field.name()),
+            }
+        });
+        base.chain(tagged).collect::<Vec<_>>().join("&")
+    }
+}
+
+/// Decodes `%xx` escapes and `+` in a query string name or value. Malformed
+/// escapes are kept as they are, bytes that are not UTF-8 are replaced.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// An enum value in the serialized form, given by name or by numeric code.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum NameOrCode {
+    Code(u32),
+    Name(String),
+}
+
+#[cfg(feature = "serde")]
+impl NameOrCode {
+    fn code(self, field: Field) -> Result<u32, RenderJobParseError> {
+        match self {
+            NameOrCode::Code(code) => Ok(code),
+            NameOrCode::Name(name) => match field.value_names().iter().position(|&n| n == name) {
+                Some(code) => Ok(code as u32),
+                None => Err(RenderJobParseError::InvalidValue {
+                    field: field.name().to_owned(),
+                    value: name,
+                }),
+            },
+        }
+    }
+}
+
+/// Implements `Deserialize` for enum fields through `NameOrCode`.
+#[cfg(feature = "serde")]
+macro_rules! deserialize_name_or_code {
+    ($($kind:ty => $field:expr),* $(,)?) => {$(
+        impl<'de> serde::Deserialize<'de> for $kind {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <NameOrCode as serde::Deserialize>::deserialize(deserializer)?
+                    .code($field)
+                    .and_then(Self::try_from)
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "serde")]
+deserialize_name_or_code!(
+    IntegratorKind => Field::Integrator,
+    ProjectionKind => Field::Projection,
+    FilterKind => Field::Filter,
+    SceneKind => Field::Scene,
+    OutputFormat => Field::Format,
+);
+
+impl Display for RenderJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tagged = self.tagged_fields();
+        if !tagged.is_empty() {
+            write!(f, "v{}/", Self::VERSION)?;
+        }
+        let [first, rest @ ..] = self.base_fields();
+        write!(f, "{first}")?;
+        for num in rest {
+            write!(f, "/{num}")?;
+        }
+        for (field, value) in tagged {
+            let prefix = if field.required() { "!" } else { "" };
+            write!(f, "/{prefix}{}={value}", field.name())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RenderJob {
+    type Err = RenderJobParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/').filter(|s| !s.is_empty()).peekable();
+        let version = match parts.peek().and_then(|part| part.strip_prefix('v')) {
+            Some(version) => version.parse::<u32>()?,
+            None => {
+                let vec: Vec<u32> = parts
+                    .map(u32::from_str)
+                    .collect::<Result<_, ParseIntError>>()?;
+                return Self::from_version_0(&vec);
+            }
+        };
+        if version == 0 || version > Self::VERSION {
+            return Err(RenderJobParseError::UnsupportedVersion(version));
+        }
+        parts.next();
+
+        let base = parts
+            .by_ref()
+            .take(Self::MIN_NUM_FIELDS)
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, ParseIntError>>()?;
+        if base.len() < Self::MIN_NUM_FIELDS {
+            return Err(RenderJobParseError::Truncated);
+        }
+        let mut job = Self::from_base_fields(&base);
+        for part in parts {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| RenderJobParseError::MalformedField(part.to_owned()))?;
+            let (required, name) = match name.strip_prefix(REQUIRED_PREFIX) {
+                Some(name) => (true, name),
+                None => (false, name),
+            };
+            match Field::from_name(name) {
+                Some(field) => {
+                    let value = value.parse::<u64>().map_err(|_| field.malformed())?;
+                    job.set(field, value)?;
+                }
+                None if required => {
+                    return Err(RenderJobParseError::UnknownRequiredField(name.to_owned()))
+                }
+                None => {}
+            }
+        }
+        Ok(job)
+    }
+}
+
+#[test]
+fn version_0_jobs_still_parse() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8);
+    assert_eq!(RenderJob::from_str("1/2/3/4/5/6/7/8").unwrap(), job);
+    assert_eq!(RenderJob::from_str("/1/2/3/4/5/6/7/8").unwrap(), job);
+    assert_eq!(
+        RenderJob::try_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+        job
+    );
+    // plain jobs are still written in the old form
+    assert_eq!(job.to_string(), "1/2/3/4/5/6/7/8");
+    assert_eq!(job.to_vec(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+}
+
+#[test]
+fn tagged_fields_round_trip() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8)
+        .with_filter(FilterKind::Gaussian)
+        .with_seed(0x1234_5678_9abc)
+        .with_scene(SceneKind::Lights)
+        .with_format(OutputFormat::Ppm);
+    assert_eq!(
+        job.to_string(),
+        "v1/1/2/3/4/5/6/7/8/!filter=2/seed=20015998343868/!scene=3/!format=1"
+    );
+    assert_eq!(RenderJob::from_str(&job.to_string()).unwrap(), job);
+    assert_eq!(RenderJob::try_from_slice(&job.to_vec()).unwrap(), job);
+
+    // fields can come in any order and be marked as required or not
+    let job = RenderJob::from_str("v1/1/2/3/4/5/6/7/8/seed=9/!frame=3/integrator=1").unwrap();
+    assert_eq!((job.seed, job.frame), (9, 3));
+    assert_eq!(job.integrator, IntegratorKind::Whitted);
+}
+
+#[test]
+fn unknown_fields_are_skipped_unless_required() {
+    let tag = VERSION_MARKER | 1;
+    let job =
+        RenderJob::try_from_slice(&[tag, 1, 2, 3, 4, 5, 6, 7, 8, 99, 2, 0, 0, 3, 1, 7]).unwrap();
+    assert_eq!(job.frame, 7);
+    assert!(matches!(
+        RenderJob::try_from_slice(&[tag, 1, 2, 3, 4, 5, 6, 7, 8, 99 | REQUIRED, 1, 0]),
+        Err(RenderJobParseError::UnknownRequiredField(id)) if id == "99"
+    ));
+
+    let job = RenderJob::from_str("v1/1/2/3/4/5/6/7/8/lens=3/!frame=7").unwrap();
+    assert_eq!(job.frame, 7);
+    let err = RenderJob::from_str("v1/1/2/3/4/5/6/7/8/!lens=3").unwrap_err();
+    assert!(matches!(&err, RenderJobParseError::UnknownRequiredField(name) if name == "lens"));
+    assert_eq!(
+        err.to_string(),
+        "job requires field `lens`, which this worker does not know, it may be outdated"
+    );
+}
+
+#[test]
+fn malformed_jobs_are_rejected() {
+    let tag = VERSION_MARKER | 1;
+    assert!(matches!(
+        RenderJob::from_str("v2/1/2/3/4/5/6/7/8"),
+        Err(RenderJobParseError::UnsupportedVersion(2))
+    ));
+    assert!(matches!(
+        RenderJob::try_from_slice(&[VERSION_MARKER | 7, 1, 2, 3, 4, 5, 6, 7, 8]),
+        Err(RenderJobParseError::UnsupportedVersion(7))
+    ));
+    assert!(matches!(
+        RenderJob::from_str("v1/1/2/3/4/5/6/7"),
+        Err(RenderJobParseError::Truncated)
+    ));
+    assert!(matches!(
+        RenderJob::try_from_slice(&[tag, 1, 2, 3, 4, 5, 6, 7, 8, 3, 2, 7]),
+        Err(RenderJobParseError::Truncated)
+    ));
+    assert!(matches!(
+        RenderJob::try_from_slice(&[tag, 1, 2, 3, 4, 5, 6, 7, 8, 7, 1, 7]),
+        Err(RenderJobParseError::MalformedField(name)) if name == "seed"
+    ));
+    assert!(matches!(
+        RenderJob::from_str("v1/1/2/3/4/5/6/7/8/frame"),
+        Err(RenderJobParseError::MalformedField(_))
+    ));
+    assert!(matches!(
+        RenderJob::from_str("v1/1/2/3/4/5/6/7/8/!scene=9"),
+        Err(RenderJobParseError::UnknownScene(9))
+    ));
+}
+
+#[test]
+fn query_form_round_trips() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8);
+    assert_eq!(
+        job.to_query(),
+        "x=1&y=2&w=3&h=4&camera_w=5&camera_h=6&samples=7&recursion=8"
+    );
+    assert_eq!(RenderJob::from_query(&job.to_query()).unwrap(), job);
+
+    let job = job
+        .with_integrator(IntegratorKind::AmbientOcclusion)
+        .with_seed(42)
+        .with_format(OutputFormat::Ppm);
+    assert_eq!(
+        job.to_query(),
+        "x=1&y=2&w=3&h=4&camera_w=5&camera_h=6&samples=7&recursion=8\
+         &integrator=ambient_occlusion&seed=42&format=ppm"
+    );
+    assert_eq!(RenderJob::from_query(&job.to_query()).unwrap(), job);
+
+    // any order, codes instead of names
+    let parsed = RenderJob::from_query(
+        "?format=1&recursion=8&samples=7&seed=42&camera_h=6&camera_w=5&h=4&w=3&y=2&x=1&integrator=2",
+    )
+    .unwrap();
+    assert_eq!(parsed, job);
+
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_shutter(250, 500);
+    assert!(job.to_query().ends_with("&shutter_open=250&shutter=500"));
+    assert_eq!(RenderJob::from_query(&job.to_query()).unwrap(), job);
+}
+
+#[test]
+fn query_errors_name_the_field() {
+    let base = "x=1&y=2&w=3&h=4&camera_w=5&camera_h=6&samples=7";
+    let err = RenderJob::from_query(base).unwrap_err();
+    assert_eq!(err.to_string(), "job lacks field `recursion`");
+
+    let err = RenderJob::from_query(&format!("{base}&recursion=deep")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid value `deep` for field `recursion`"
+    );
+    let err = RenderJob::from_query(&format!("{base}&recursion=1&filter=blurry")).unwrap_err();
+    assert_eq!(err.to_string(), "invalid value `blurry` for field `filter`");
+    let err = RenderJob::from_query(&format!("{base}&recursion=1&lens=wide")).unwrap_err();
+    assert_eq!(err.to_string(), "unknown field `lens`");
+    let err = RenderJob::from_query(&format!("{base}&recursion=1&x=2")).unwrap_err();
+    assert_eq!(err.to_string(), "field `x` is given twice");
+    assert!(matches!(
+        RenderJob::from_query(&format!("{base}&recursion=1&scene=9")),
+        Err(RenderJobParseError::UnknownScene(9))
+    ));
+}
+
+#[test]
+fn query_values_are_percent_decoded() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_integrator(IntegratorKind::Whitted);
+    let parsed = RenderJob::from_query(
+        "x=%31&y=2&w=3&h=4&camera_w=5&camera_h=6&samples=7&recursion=8&%69ntegrator=whitt%65d",
+    )
+    .unwrap();
+    assert_eq!(parsed, job);
+
+    let base = "x=1&y=2&w=3&h=4&camera_w=5&camera_h=6&samples=7";
+    let err = RenderJob::from_query(&format!("{base}&recursion=a+b%2")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid value `a b%2` for field `recursion`"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_form_uses_query_names() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8)
+        .with_integrator(IntegratorKind::AmbientOcclusion)
+        .with_seed(42);
+    let json = serde_json::to_string(&job).unwrap();
+    assert!(json.contains(r#""samples":7,"recursion":8,"integrator":"ambient_occlusion""#));
+    assert_eq!(serde_json::from_str::<RenderJob>(&json).unwrap(), job);
+
+    let minimal =
+        r#"{"x":1,"y":2,"w":3,"h":4,"camera_w":5,"camera_h":6,"samples":7,"recursion":8}"#;
+    assert_eq!(
+        serde_json::from_str::<RenderJob>(minimal).unwrap(),
+        RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8)
+    );
+    let newer = minimal.replace('}', r#","lens":"wide"}"#);
+    assert_eq!(
+        serde_json::from_str::<RenderJob>(&newer).unwrap(),
+        RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8)
+    );
+    let err = serde_json::from_str::<RenderJob>(r#"{"x":1}"#).unwrap_err();
+    assert!(err.to_string().contains("missing field `y`"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_form_takes_enum_names_or_codes() {
+    let base = r#""x":0,"y":0,"w":8,"h":8,"camera_w":8,"camera_h":8,"samples":1,"recursion":1"#;
+    let parse = |fields: &str| serde_json::from_str::<RenderJob>(&format!("{{{base},{fields}}}"));
+    let expected = RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1)
+        .with_integrator(IntegratorKind::Normals)
+        .with_scene(SceneKind::Lights)
+        .with_format(OutputFormat::Ppm);
+    assert_eq!(
+        parse(r#""integrator":"normals","scene":"lights","format":"ppm""#).unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse(r#""integrator":3,"scene":3,"format":1"#).unwrap(),
+        expected
+    );
+    let err = parse(r#""scene":9"#).unwrap_err();
+    assert!(err.to_string().contains("unknown scene 9"), "{err}");
+    let err = parse(r#""filter":"blurry""#).unwrap_err();
+    assert!(err.to_string().contains("blurry"), "{err}");
+}
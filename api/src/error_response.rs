@@ -0,0 +1,292 @@
+//! How HTTP workers answer failed requests.
+//!
+//! All workers map errors to the same status codes and bodies, so that a
+//! coordinator can treat them alike: malformed jobs are bad requests, jobs for
+//! unknown scenes and unknown routes are not found, and jobs above the limits
+//! of the worker are too large.
+
+use crate::{JobValidationError, RenderBatchError, RenderJobParseError};
+
+/// A failed request, answered with `status` and, with the `serde` feature, a
+/// JSON body like `{"status": 400, "error": "invalid job: ..."}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorResponse {
+    pub status: u16,
+    #[cfg_attr(feature = "serde", serde(rename = "error"))]
+    pub message: String,
+    /// Methods of the route, sent in the `Allow` header of 405 responses.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub allow: Option<&'static str>,
+}
+
+impl ErrorResponse {
+    /// `Content-Type` of the body.
+    pub const CONTENT_TYPE: &'static str = "application/json";
+
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            allow: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    /// A route that does not exist, or a job for a scene the worker lacks.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, message)
+    }
+
+    /// A method other than those in `allow`, like `"GET, POST"`.
+    pub fn method_not_allowed(allow: &'static str) -> Self {
+        Self {
+            allow: Some(allow),
+            ..Self::new(405, "method not allowed")
+        }
+    }
+
+    /// A failure of the worker itself. Its details belong in the log of the
+    /// worker, not in the response.
+    pub fn internal() -> Self {
+        Self::new(500, "internal error")
+    }
+
+    /// Answer for a path that is no route: an invalid job if it looks like a
+    /// job, starting with a number or a version tag like `v1`, otherwise not
+    /// found.
+    pub fn for_path(path: &str, err: RenderJobParseError) -> Self {
+        let first = path
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let digits = first.strip_prefix('v').unwrap_or(first);
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            err.into()
+        } else {
+            Self::not_found(format!("no route for {path}"))
+        }
+    }
+}
+
+impl From<RenderJobParseError> for ErrorResponse {
+    fn from(err: RenderJobParseError) -> Self {
+        Self::new(parse_error_status(&err), format!("invalid job: {err}"))
+    }
+}
+
+impl From<JobValidationError> for ErrorResponse {
+    fn from(err: JobValidationError) -> Self {
+        Self::new(validation_error_status(&err), format!("invalid job: {err}"))
+    }
+}
+
+impl From<RenderBatchError> for ErrorResponse {
+    fn from(err: RenderBatchError) -> Self {
+        let status = match &err {
+            RenderBatchError::TooManyJobs { .. } | RenderBatchError::RayBudgetExceeded { .. } => {
+                413
+            }
+            RenderBatchError::Job { source, .. } => parse_error_status(source),
+            RenderBatchError::InvalidJob { source, .. } => validation_error_status(source),
+            _ => 400,
+        };
+        Self::new(status, format!("invalid batch: {err}"))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl crate::RenderJob {
+    /// Reads a job from its JSON form, as sent to `POST /job`.
+    pub fn from_json(body: &[u8]) -> Result<Self, ErrorResponse> {
+        let value = serde_json::from_slice(body)
+            .map_err(|err| ErrorResponse::bad_request(format!("invalid job: {err}")))?;
+        Self::from_json_value(value)
+    }
+
+    /// Reads a job object. Unknown scenes are told apart from other invalid
+    /// fields, they are answered with 404 like in the other forms.
+    pub fn from_json_value(value: serde_json::Value) -> Result<Self, ErrorResponse> {
+        job_from_value(value)
+            .map_err(|(status, err)| ErrorResponse::new(status, format!("invalid job: {err}")))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl crate::RenderBatch {
+    /// Reads a batch from a JSON list of jobs.
+    pub fn from_json(body: &[u8]) -> Result<Self, ErrorResponse> {
+        let values: Vec<serde_json::Value> = serde_json::from_slice(body)
+            .map_err(|err| ErrorResponse::bad_request(format!("invalid batch: {err}")))?;
+        let jobs = values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                job_from_value(value).map_err(|(status, err)| {
+                    ErrorResponse::new(
+                        status,
+                        format!("invalid batch: job {index} of the batch: {err}"),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(jobs))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn job_from_value(
+    mut value: serde_json::Value,
+) -> Result<crate::RenderJob, (u16, serde_json::Error)> {
+    use crate::encoding::{is_field_name, REQUIRED_PREFIX};
+    use serde::de::Error;
+
+    if let Some(object) = value.as_object_mut() {
+        let marked: Vec<String> = object
+            .keys()
+            .filter(|name| name.starts_with(REQUIRED_PREFIX))
+            .cloned()
+            .collect();
+        for marked in marked {
+            let name = &marked[REQUIRED_PREFIX.len_utf8()..];
+            let err = if !is_field_name(name) {
+                RenderJobParseError::UnknownRequiredField(name.to_owned())
+            } else if object.contains_key(name) {
+                RenderJobParseError::DuplicateField(name.to_owned())
+            } else {
+                let field = object.remove(&marked).unwrap_or_default();
+                object.insert(name.to_owned(), field);
+                continue;
+            };
+            return Err((400, serde_json::Error::custom(err)));
+        }
+    }
+    let unknown_scene = value
+        .get("scene")
+        .is_some_and(|scene| serde_json::from_value::<crate::SceneKind>(scene.clone()).is_err());
+    serde_json::from_value(value).map_err(|err| {
+        let status = if unknown_scene { 404 } else { 400 };
+        (status, err)
+    })
+}
+
+/// Jobs for scenes this worker does not have are not found, any other
+/// malformed job is a bad request.
+fn parse_error_status(err: &RenderJobParseError) -> u16 {
+    match err {
+        RenderJobParseError::UnknownScene(_) => 404,
+        RenderJobParseError::InvalidValue { field, .. } if field == "scene" => 404,
+        _ => 400,
+    }
+}
+
+/// Jobs above the limits are too large, other invalid jobs are bad requests.
+fn validation_error_status(err: &JobValidationError) -> u16 {
+    match err {
+        JobValidationError::ResolutionTooHigh { .. }
+        | JobValidationError::TooManySamples { .. }
+        | JobValidationError::RecursionTooDeep { .. }
+        | JobValidationError::RayBudgetExceeded { .. } => 413,
+        JobValidationError::EmptyCamera { .. }
+        | JobValidationError::EmptyTile { .. }
+        | JobValidationError::OutsideCamera { .. }
+        | JobValidationError::NoSamples
+        | JobValidationError::ShutterOutsideFrame { .. } => 400,
+    }
+}
+
+#[test]
+fn errors_map_to_statuses() {
+    use crate::RenderJob;
+    use std::str::FromStr;
+
+    let parse = |path: &str| ErrorResponse::for_path(path, RenderJob::from_str(path).unwrap_err());
+    assert_eq!(parse("/1/2/3").status, 400);
+    assert_eq!(parse("/v1/0/0/8/8/8/8/1/1/!scene=99").status, 404);
+    assert_eq!(parse("/favicon.ico").status, 404);
+    assert_eq!(parse("/favicon.ico").message, "no route for /favicon.ico");
+
+    let too_large = RenderJob::new(0, 0, 8, 8, 8, 8, 1_000_000, 1).validate();
+    assert_eq!(ErrorResponse::from(too_large.unwrap_err()).status, 413);
+    let outside = RenderJob::new(4, 0, 8, 8, 8, 8, 1, 1).validate();
+    assert_eq!(ErrorResponse::from(outside.unwrap_err()).status, 400);
+
+    let batch = "0/0/8/8/8/8/1/1\n0/0/8/8/8/8/1000000/1".parse::<crate::RenderBatch>();
+    assert_eq!(
+        ErrorResponse::from(batch.unwrap().validate().unwrap_err()).status,
+        413
+    );
+
+    let not_allowed = ErrorResponse::method_not_allowed("GET, POST");
+    assert_eq!(
+        (not_allowed.status, not_allowed.allow),
+        (405, Some("GET, POST"))
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn body_is_json_without_allow() {
+    let body = serde_json::to_value(ErrorResponse::method_not_allowed("GET")).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"status": 405, "error": "method not allowed"})
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_errors_map_to_statuses() {
+    use crate::{RenderBatch, RenderJob};
+
+    let job = r#"{"x":0,"y":0,"w":8,"h":8,"camera_w":8,"camera_h":8,"samples":1,"recursion":1}"#;
+    assert_eq!(
+        RenderJob::from_json(job.as_bytes()).unwrap(),
+        RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1)
+    );
+    assert_eq!(RenderJob::from_json(b"{").unwrap_err().status, 400);
+    let missing = RenderJob::from_json(br#"{"x":0}"#).unwrap_err();
+    assert_eq!(missing.status, 400);
+    assert!(missing.message.starts_with("invalid job: missing field"));
+
+    let newer = job.replace('}', r#","lens":"wide"}"#);
+    assert_eq!(
+        RenderJob::from_json(newer.as_bytes()).unwrap(),
+        RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1)
+    );
+    let required = RenderJob::from_json(newer.replace("lens", "!lens").as_bytes()).unwrap_err();
+    assert_eq!(required.status, 400);
+    assert!(required.message.contains("`lens`"));
+    let marked = job.replace('}', r#","!scene":"lights"}"#);
+    assert_eq!(
+        RenderJob::from_json(marked.as_bytes()).unwrap().scene,
+        crate::SceneKind::Lights
+    );
+    let invalid = job.replace('}', r#","filter":"blurry"}"#);
+    assert_eq!(
+        RenderJob::from_json(invalid.as_bytes()).unwrap_err().status,
+        400
+    );
+
+    let unknown = job.replace('}', r#","scene":99}"#);
+    assert_eq!(
+        RenderJob::from_json(unknown.as_bytes()).unwrap_err().status,
+        404
+    );
+    let marked = unknown.replace("scene", "!scene");
+    assert_eq!(
+        RenderJob::from_json(marked.as_bytes()).unwrap_err().status,
+        404
+    );
+
+    let batch = RenderBatch::from_json(format!("[{job},{unknown}]").as_bytes()).unwrap_err();
+    assert_eq!(batch.status, 404);
+    assert!(batch
+        .message
+        .starts_with("invalid batch: job 1 of the batch: "));
+    assert_eq!(RenderBatch::from_json(b"[").unwrap_err().status, 400);
+}
@@ -1,9 +1,36 @@
-use std::fmt::Display;
+use std::convert::TryFrom;
 use std::num::ParseIntError;
-use std::str::FromStr;
 use thiserror::Error;
 
-#[derive(Debug)]
+#[cfg(test)]
+use std::str::FromStr;
+
+mod batch;
+mod cache;
+mod capabilities;
+mod encoding;
+mod error_response;
+mod render_result;
+mod row_block;
+mod tiling;
+mod validate;
+
+pub use batch::{RenderBatch, RenderBatchError};
+pub use cache::{JobHash, LruCache, ResultCache, RENDER_VERSION};
+pub use capabilities::{Capabilities, IncompatibleWorker};
+pub use error_response::ErrorResponse;
+pub use render_result::{RenderResult, RenderResultError, RenderStats};
+pub use row_block::RowBlock;
+pub use tiling::{TileOrder, TilePlanner};
+pub use validate::{JobValidationError, Limits};
+
+/// With the `serde` feature, jobs serialize as objects with the field names of
+/// the query string form, where optional fields may be left out. Enum fields
+/// are written by name and read by name or numeric code, like in the query.
+/// Unknown fields are skipped like in the versioned forms, `from_json` also
+/// rejects unknown fields marked as required by a `!` before the name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RenderJob {
     /// Start x of job output in camera coordinates.
     pub x: u32,
@@ -18,12 +45,122 @@ pub struct RenderJob {
     /// Height of entire view of all jobs, in camera coordinates.
     pub camera_h: u32,
     /// How many rays to cast per output pixel.
+    #[cfg_attr(feature = "serde", serde(rename = "samples"))]
     pub n_samples: u32,
     /// How many times to bounce each ray.
+    #[cfg_attr(feature = "serde", serde(rename = "recursion"))]
     pub n_recursion: u32,
+    /// Rendering algorithm, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub integrator: IntegratorKind,
+    /// Camera projection, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub projection: ProjectionKind,
+    /// Animation frame to render, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub frame: u32,
+    /// When the shutter opens, in thousandths of a frame after the start of
+    /// the frame, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shutter_open: u32,
+    /// How long the shutter stays open, in thousandths of a frame, for
+    /// motion blur. 0 for a sharp image, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shutter: u32,
+    /// Pixel reconstruction filter, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub filter: FilterKind,
+    /// Seed of all random decisions, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seed: u64,
+    /// Sample scene to render, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub scene: SceneKind,
+    /// Image format of the result, optional in the serialized form.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub format: OutputFormat,
+}
+
+/// Selects how light is computed for each ray.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum IntegratorKind {
+    /// Full global illumination, the default.
+    #[default]
+    PathTracer = 0,
+    /// Direct lighting and perfect mirrors only, no random bounces.
+    Whitted = 1,
+    /// Gray scale ambient occlusion, cheap enough for previews.
+    AmbientOcclusion = 2,
+    /// Surface normals as colors.
+    Normals = 3,
+    /// Distance to the first hit in gray scale.
+    Depth = 4,
+    /// Heatmap of how often each path bounced.
+    Bounces = 5,
+}
+
+/// Selects how the camera maps pixels to ray directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ProjectionKind {
+    /// Pinhole camera, the default.
+    #[default]
+    Perspective = 0,
+    /// Parallel rays, no foreshortening.
+    Orthographic = 1,
+    /// Equidistant fisheye with a 180° field of view across the image width.
+    Fisheye = 2,
+    /// Full 360° x 180° panorama.
+    Equirectangular = 3,
+}
+
+/// Selects how samples are weighted into pixels. Filters other than the box
+/// also use samples of neighboring pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FilterKind {
+    /// Average of the samples inside each pixel, the default.
+    #[default]
+    Box = 0,
+    Tent = 1,
+    Gaussian = 2,
+    Mitchell = 3,
+}
+
+/// Selects one of the sample scenes built into the workers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SceneKind {
+    /// Animated spheres and rings around a die, the default.
+    #[default]
+    Cool = 0,
+    /// The same scene in fog, with a glowing volume.
+    Hazy = 1,
+    /// A few spheres on a plane.
+    Simple = 2,
+    /// Spheres lit by point, spot and directional lights at night.
+    Lights = 3,
+}
+
+/// Selects how the rendered image is encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OutputFormat {
+    /// 8-bit RGB PNG, the default.
+    #[default]
+    Png = 0,
+    /// Plain text PPM (P3), uncompressed.
+    Ppm = 1,
 }
 
 impl RenderJob {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         x: u32,
         y: u32,
@@ -43,60 +180,132 @@ impl RenderJob {
             camera_h,
             n_samples,
             n_recursion,
+            integrator: IntegratorKind::default(),
+            projection: ProjectionKind::default(),
+            frame: 0,
+            shutter_open: 0,
+            shutter: 0,
+            filter: FilterKind::default(),
+            seed: 0,
+            scene: SceneKind::default(),
+            format: OutputFormat::default(),
         }
     }
 
-    pub fn to_vec(&self) -> Vec<u32> {
-        vec![
-            self.x,
-            self.y,
-            self.w,
-            self.h,
-            self.camera_w,
-            self.camera_h,
-            self.n_samples,
-            self.n_recursion,
-        ]
+    /// Thousandths in a frame, the latest time the shutter may close.
+    pub const MAX_SHUTTER: u32 = 1000;
+
+    pub fn with_integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: ProjectionKind) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_frame(mut self, frame: u32) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    /// Keep the shutter open from `open` for `shutter` thousandths of a
+    /// frame.
+    pub fn with_shutter(mut self, open: u32, shutter: u32) -> Self {
+        self.shutter_open = open;
+        self.shutter = shutter;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterKind) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_scene(mut self, scene: SceneKind) -> Self {
+        self.scene = scene;
+        self
     }
 
-    pub fn try_from_slice(data: &[u32]) -> Result<RenderJob, RenderJobParseError> {
-        const EXPECTED_LEN: usize = 8;
-        (data.len() == EXPECTED_LEN)
-            .then(|| {
-                Self::new(
-                    data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-                )
-            })
-            .ok_or_else(|| RenderJobParseError::IncorrectLength {
-                expected: EXPECTED_LEN,
-                actual: data.len(),
-            })
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
     }
 }
 
-impl Display for RenderJob {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut vec = self.to_vec();
-        let mut nums = vec.drain(..);
-        write!(f, "{}", nums.next().unwrap())?;
-        for num in nums {
-            write!(f, "/{num}")?;
-        }
-        Ok(())
+impl TryFrom<u32> for IntegratorKind {
+    type Error = RenderJobParseError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => IntegratorKind::PathTracer,
+            1 => IntegratorKind::Whitted,
+            2 => IntegratorKind::AmbientOcclusion,
+            3 => IntegratorKind::Normals,
+            4 => IntegratorKind::Depth,
+            5 => IntegratorKind::Bounces,
+            other => return Err(RenderJobParseError::UnknownIntegrator(other)),
+        })
+    }
+}
+
+impl TryFrom<u32> for ProjectionKind {
+    type Error = RenderJobParseError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => ProjectionKind::Perspective,
+            1 => ProjectionKind::Orthographic,
+            2 => ProjectionKind::Fisheye,
+            3 => ProjectionKind::Equirectangular,
+            other => return Err(RenderJobParseError::UnknownProjection(other)),
+        })
+    }
+}
+
+impl TryFrom<u32> for FilterKind {
+    type Error = RenderJobParseError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => FilterKind::Box,
+            1 => FilterKind::Tent,
+            2 => FilterKind::Gaussian,
+            3 => FilterKind::Mitchell,
+            other => return Err(RenderJobParseError::UnknownFilter(other)),
+        })
     }
 }
 
-impl FromStr for RenderJob {
-    type Err = RenderJobParseError;
+impl TryFrom<u32> for SceneKind {
+    type Error = RenderJobParseError;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let vec: Vec<u32> = s
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .map(u32::from_str)
-            .collect::<Result<_, ParseIntError>>()?;
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => SceneKind::Cool,
+            1 => SceneKind::Hazy,
+            2 => SceneKind::Simple,
+            3 => SceneKind::Lights,
+            other => return Err(RenderJobParseError::UnknownScene(other)),
+        })
+    }
+}
+
+impl TryFrom<u32> for OutputFormat {
+    type Error = RenderJobParseError;
 
-        Self::try_from_slice(&vec)
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => OutputFormat::Png,
+            1 => OutputFormat::Ppm,
+            other => return Err(RenderJobParseError::UnknownFormat(other)),
+        })
     }
 }
 
@@ -104,6 +313,111 @@ impl FromStr for RenderJob {
 pub enum RenderJobParseError {
     #[error("could not parse integer")]
     InvalidInt(#[from] ParseIntError),
-    #[error("job contains wrong amount of numbers, expected {expected} but was {actual}")]
-    IncorrectLength { expected: usize, actual: usize },
+    #[error("job contains wrong amount of numbers, expected {min} to {max} but was {actual}")]
+    IncorrectLength {
+        min: usize,
+        max: usize,
+        actual: usize,
+    },
+    #[error("unknown integrator {0}")]
+    UnknownIntegrator(u32),
+    #[error("unknown projection {0}")]
+    UnknownProjection(u32),
+    #[error("unknown filter {0}")]
+    UnknownFilter(u32),
+    #[error("unknown scene {0}")]
+    UnknownScene(u32),
+    #[error("unknown output format {0}")]
+    UnknownFormat(u32),
+    #[error("job encoding version {0} is not supported, expected at most {max}", max = RenderJob::VERSION)]
+    UnsupportedVersion(u32),
+    #[error("job requires field `{0}`, which this worker does not know, it may be outdated")]
+    UnknownRequiredField(String),
+    #[error("malformed value of field `{0}`")]
+    MalformedField(String),
+    #[error("job ends in the middle of a field")]
+    Truncated,
+    #[error("job lacks field `{0}`")]
+    MissingField(&'static str),
+    #[error("unknown field `{0}`")]
+    UnknownField(String),
+    #[error("field `{0}` is given twice")]
+    DuplicateField(String),
+    #[error("invalid value `{value}` for field `{field}`")]
+    InvalidValue { field: String, value: String },
+}
+
+#[test]
+fn integrator_is_optional() {
+    let job = RenderJob::from_str("1/2/3/4/5/6/7/8").unwrap();
+    assert_eq!(job.integrator, IntegratorKind::PathTracer);
+
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_integrator(IntegratorKind::Depth);
+    let parsed = RenderJob::from_str(&job.to_string()).unwrap();
+    assert_eq!(parsed.integrator, IntegratorKind::Depth);
+
+    assert!(matches!(
+        RenderJob::from_str("1/2/3/4/5/6/7/8/99"),
+        Err(RenderJobParseError::UnknownIntegrator(99))
+    ));
+    assert!(matches!(
+        RenderJob::from_str("1/2/3/4/5/6/7"),
+        Err(RenderJobParseError::IncorrectLength { actual: 7, .. })
+    ));
+}
+
+#[test]
+fn projection_is_optional() {
+    let job = RenderJob::from_str("1/2/3/4/5/6/7/8/1").unwrap();
+    assert_eq!(job.integrator, IntegratorKind::Whitted);
+    assert_eq!(job.projection, ProjectionKind::Perspective);
+
+    let job =
+        RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_projection(ProjectionKind::Equirectangular);
+    let parsed = RenderJob::try_from_slice(&job.to_vec()).unwrap();
+    assert_eq!(parsed.projection, ProjectionKind::Equirectangular);
+    assert_eq!(parsed.integrator, IntegratorKind::PathTracer);
+
+    assert!(matches!(
+        RenderJob::from_str("1/2/3/4/5/6/7/8/0/4"),
+        Err(RenderJobParseError::UnknownProjection(4))
+    ));
+}
+
+#[test]
+fn frame_round_trips() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_frame(42);
+    assert_eq!(job.to_string(), "v1/1/2/3/4/5/6/7/8/!frame=42");
+    assert_eq!(RenderJob::from_str(&job.to_string()).unwrap().frame, 42);
+    assert_eq!(RenderJob::from_str("1/2/3/4/5/6/7/8/0/0").unwrap().frame, 0);
+}
+
+#[test]
+fn shutter_round_trips() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_shutter(250, 500);
+    assert_eq!(
+        job.to_string(),
+        "v1/1/2/3/4/5/6/7/8/!shutter_open=250/!shutter=500"
+    );
+    assert_eq!(RenderJob::from_str(&job.to_string()).unwrap(), job);
+    assert_eq!(RenderJob::try_from_slice(&job.to_vec()).unwrap(), job);
+    assert_eq!(
+        RenderJob::from_str("1/2/3/4/5/6/7/8/0/0/0/250/500").unwrap(),
+        job
+    );
+    let parsed = RenderJob::from_str("1/2/3/4/5/6/7/8/0/0/42").unwrap();
+    assert_eq!((parsed.shutter_open, parsed.shutter), (0, 0));
+}
+
+#[test]
+fn filter_is_optional() {
+    let job = RenderJob::new(1, 2, 3, 4, 5, 6, 7, 8).with_filter(FilterKind::Mitchell);
+    let parsed = RenderJob::from_str(&job.to_string()).unwrap();
+    assert_eq!(parsed.filter, FilterKind::Mitchell);
+    let job = RenderJob::from_str("1/2/3/4/5/6/7/8/0/0/42").unwrap();
+    assert_eq!(job.filter, FilterKind::Box);
+    assert!(matches!(
+        RenderJob::from_str("1/2/3/4/5/6/7/8/0/0/0/0/0/7"),
+        Err(RenderJobParseError::UnknownFilter(7))
+    ));
 }
@@ -0,0 +1,378 @@
+//! Result of a render job, the same on every transport.
+//!
+//! Workers answer with the image and metadata about how it was produced. The
+//! metadata travels in a binary header in front of the image, used between
+//! web workers and peers, or in HTTP headers, used by the HTTP workers.
+
+use std::fmt::Write as _;
+use thiserror::Error;
+
+use crate::{OutputFormat, RenderJob, RenderJobParseError};
+
+/// A finished job, echoing the job it belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderResult {
+    /// The job as the worker understood it.
+    pub job: RenderJob,
+    /// Encoding of `image`, the format requested by `job`. Decoded results
+    /// that claim another one are rejected.
+    pub format: OutputFormat,
+    pub image: Vec<u8>,
+    /// Wall time spent rendering, in milliseconds.
+    pub duration_ms: u32,
+    pub stats: RenderStats,
+    /// Free-form name of the worker, for display and debugging.
+    pub worker: String,
+}
+
+/// Work done for a job.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Camera samples traced, over all pixels.
+    pub samples: u64,
+    /// Threads the worker rendered with.
+    pub threads: u32,
+}
+
+impl RenderResult {
+    /// First bytes of the binary form, including its version.
+    pub const MAGIC: [u8; 4] = *b"CRR1";
+
+    pub const JOB_HEADER: &'static str = "X-Clumsy-Job";
+    pub const DURATION_HEADER: &'static str = "X-Clumsy-Duration-Ms";
+    pub const SAMPLES_HEADER: &'static str = "X-Clumsy-Samples";
+    pub const THREADS_HEADER: &'static str = "X-Clumsy-Threads";
+    pub const WORKER_HEADER: &'static str = "X-Clumsy-Worker";
+    /// Value of `Access-Control-Expose-Headers`, without it browsers hide the
+    /// headers above from cross-origin clients.
+    pub const EXPOSED_HEADERS: &'static str =
+        "X-Clumsy-Job, X-Clumsy-Duration-Ms, X-Clumsy-Samples, X-Clumsy-Threads, X-Clumsy-Worker";
+
+    /// A result in the format requested by `job`, without stats.
+    pub fn new(job: RenderJob, image: Vec<u8>) -> Self {
+        Self {
+            format: job.format,
+            job,
+            image,
+            duration_ms: 0,
+            stats: RenderStats::default(),
+            worker: String::new(),
+        }
+    }
+
+    pub fn with_duration_ms(mut self, duration_ms: u32) -> Self {
+        self.duration_ms = duration_ms;
+        self
+    }
+
+    pub fn with_stats(mut self, stats: RenderStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn with_worker(mut self, worker: impl Into<String>) -> Self {
+        self.worker = worker.into();
+        self
+    }
+
+    /// Checks that this is the result of `expected`, in the requested format.
+    pub fn check_job(&self, expected: &RenderJob) -> Result<(), RenderResultError> {
+        if self.job != *expected || self.format != expected.format {
+            return Err(RenderResultError::JobMismatch {
+                expected: expected.to_string(),
+                actual: self.job.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Appends the binary form to `out`: the magic bytes, the job as length
+    /// prefixed u32s, format, duration, samples (u64), threads, the worker
+    /// name and the image, each of the last two prefixed by its length. All
+    /// numbers are big-endian.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let job = self.job.to_vec();
+        out.reserve(48 + 4 * job.len() + self.worker.len() + self.image.len());
+        out.extend_from_slice(&Self::MAGIC);
+        out.extend_from_slice(&(job.len() as u32).to_be_bytes());
+        for word in job {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out.extend_from_slice(&(self.format as u32).to_be_bytes());
+        out.extend_from_slice(&self.duration_ms.to_be_bytes());
+        out.extend_from_slice(&self.stats.samples.to_be_bytes());
+        out.extend_from_slice(&self.stats.threads.to_be_bytes());
+        out.extend_from_slice(&(self.worker.len() as u32).to_be_bytes());
+        out.extend_from_slice(self.worker.as_bytes());
+        out.extend_from_slice(&(self.image.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.image);
+    }
+
+    /// Reads a result in the binary form, which must span all of `buf`.
+    pub fn decode(buf: &[u8]) -> Result<RenderResult, RenderResultError> {
+        let mut reader = Reader(buf);
+        if reader.take(4)? != Self::MAGIC {
+            return Err(RenderResultError::NotAResult);
+        }
+        let job_len = reader.u32()? as usize;
+        let job = (0..job_len)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let job = RenderJob::try_from_slice(&job)?;
+        let format = OutputFormat::try_from(reader.u32()?)?;
+        if format != job.format {
+            return Err(RenderResultError::FormatMismatch {
+                expected: job.format,
+                actual: format,
+            });
+        }
+        let duration_ms = reader.u32()?;
+        let samples = u64::from_be_bytes(reader.take(8)?.try_into().unwrap());
+        let threads = reader.u32()?;
+        let worker_len = reader.u32()? as usize;
+        let worker = std::str::from_utf8(reader.take(worker_len)?)
+            .map_err(|_| RenderResultError::MalformedWorker)?
+            .to_owned();
+        let image_len = reader.u32()? as usize;
+        let image = reader.take(image_len)?.to_vec();
+        if !reader.0.is_empty() {
+            return Err(RenderResultError::TrailingBytes(reader.0.len()));
+        }
+        Ok(RenderResult {
+            job,
+            format,
+            image,
+            duration_ms,
+            stats: RenderStats { samples, threads },
+            worker,
+        })
+    }
+
+    /// Headers of an HTTP response with the image as body.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![("Content-Type", self.format.content_type().to_owned())];
+        headers.extend(self.metadata_headers());
+        headers
+    }
+
+    /// The `X-Clumsy-*` headers alone, for responses whose body is not the
+    /// image itself, like the row blocks of `/rows`.
+    pub fn metadata_headers(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (Self::JOB_HEADER, self.job.to_string()),
+            (Self::DURATION_HEADER, self.duration_ms.to_string()),
+            (Self::SAMPLES_HEADER, self.stats.samples.to_string()),
+            (Self::THREADS_HEADER, self.stats.threads.to_string()),
+            (Self::WORKER_HEADER, self.worker.clone()),
+        ]
+    }
+
+    /// Reads a result from an HTTP response, given a lookup of its headers
+    /// by name and its body. Only the job header is required.
+    pub fn from_headers(
+        header: impl Fn(&str) -> Option<String>,
+        image: Vec<u8>,
+    ) -> Result<RenderResult, RenderResultError> {
+        let job: RenderJob = header(Self::JOB_HEADER)
+            .ok_or(RenderResultError::MissingHeader(Self::JOB_HEADER))?
+            .parse()?;
+        if let Some(content_type) = header("Content-Type") {
+            if content_type != job.format.content_type() {
+                return Err(RenderResultError::UnexpectedContentType(content_type));
+            }
+        }
+        let number = |name: &'static str| -> Result<u64, RenderResultError> {
+            match header(name) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .map_err(|_| RenderResultError::MalformedHeader(name)),
+                None => Ok(0),
+            }
+        };
+        let to_u32 =
+            |name, n: u64| u32::try_from(n).map_err(|_| RenderResultError::MalformedHeader(name));
+        let duration_ms = to_u32(Self::DURATION_HEADER, number(Self::DURATION_HEADER)?)?;
+        let threads = to_u32(Self::THREADS_HEADER, number(Self::THREADS_HEADER)?)?;
+        let stats = RenderStats {
+            samples: number(Self::SAMPLES_HEADER)?,
+            threads,
+        };
+        let worker = header(Self::WORKER_HEADER).unwrap_or_default();
+        Ok(RenderResult::new(job, image)
+            .with_duration_ms(duration_ms)
+            .with_stats(stats)
+            .with_worker(worker))
+    }
+
+    /// Short description for logs, without the image.
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "{} by {} in {}ms",
+            self.job,
+            if self.worker.is_empty() {
+                "unknown worker"
+            } else {
+                &self.worker
+            },
+            self.duration_ms
+        );
+        if self.stats.threads > 1 {
+            let _ = write!(out, " on {} threads", self.stats.threads);
+        }
+        out
+    }
+}
+
+impl OutputFormat {
+    /// Media type of images in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Ppm => "image/x-portable-pixmap",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RenderResultError {
+    #[error("data is not a render result")]
+    NotAResult,
+    #[error("render result ends early")]
+    Truncated,
+    #[error("render result is followed by {0} unexpected bytes")]
+    TrailingBytes(usize),
+    #[error("invalid job in render result: {0}")]
+    Job(#[from] RenderJobParseError),
+    #[error("worker name is not UTF-8")]
+    MalformedWorker,
+    #[error("response lacks the {0} header")]
+    MissingHeader(&'static str),
+    #[error("malformed {0} header")]
+    MalformedHeader(&'static str),
+    #[error("unexpected content type {0}")]
+    UnexpectedContentType(String),
+    #[error("job asks for {expected:?} but the image is {actual:?}")]
+    FormatMismatch {
+        expected: OutputFormat,
+        actual: OutputFormat,
+    },
+    #[error("expected the result of job {expected} but got {actual}")]
+    JobMismatch { expected: String, actual: String },
+}
+
+/// Consumes `buf` from the front.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], RenderResultError> {
+        if self.0.len() < n {
+            return Err(RenderResultError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, RenderResultError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+fn example() -> RenderResult {
+    let job = RenderJob::new(0, 16, 32, 16, 64, 64, 4, 2).with_seed(7);
+    RenderResult::new(job, vec![1, 2, 3])
+        .with_duration_ms(1500)
+        .with_stats(RenderStats {
+            samples: 2048,
+            threads: 4,
+        })
+        .with_worker("test")
+}
+
+#[test]
+fn binary_form_round_trips() {
+    let result = example();
+    let mut buf = vec![];
+    result.encode(&mut buf);
+    assert_eq!(RenderResult::decode(&buf).unwrap(), result);
+
+    assert!(matches!(
+        RenderResult::decode(&buf[..buf.len() - 1]),
+        Err(RenderResultError::Truncated)
+    ));
+    buf.push(0);
+    assert!(matches!(
+        RenderResult::decode(&buf),
+        Err(RenderResultError::TrailingBytes(1))
+    ));
+    assert!(matches!(
+        RenderResult::decode(b"\x89PNG\r\n\x1a\n"),
+        Err(RenderResultError::NotAResult)
+    ));
+
+    let mut buf = vec![];
+    RenderResult {
+        format: OutputFormat::Ppm,
+        ..result
+    }
+    .encode(&mut buf);
+    assert!(matches!(
+        RenderResult::decode(&buf),
+        Err(RenderResultError::FormatMismatch {
+            expected: OutputFormat::Png,
+            actual: OutputFormat::Ppm,
+        })
+    ));
+}
+
+#[test]
+fn header_form_round_trips() {
+    let result = example();
+    let headers = result.headers();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+    let parsed = RenderResult::from_headers(header, result.image.clone()).unwrap();
+    assert_eq!(parsed, result);
+    let metadata = result.metadata_headers();
+    assert_eq!(metadata[..], headers[1..]);
+
+    // missing stats are zero, a missing job is an error
+    let job = result.job.to_string();
+    let parsed = RenderResult::from_headers(
+        |name| (name == RenderResult::JOB_HEADER).then(|| job.clone()),
+        vec![],
+    )
+    .unwrap();
+    assert_eq!(parsed.stats, RenderStats::default());
+    assert!(matches!(
+        RenderResult::from_headers(|_| None, vec![]),
+        Err(RenderResultError::MissingHeader(RenderResult::JOB_HEADER))
+    ));
+    assert!(matches!(
+        RenderResult::from_headers(
+            |name| match name {
+                "Content-Type" => Some("image/x-portable-pixmap".to_owned()),
+                _ => Some(job.clone()),
+            },
+            vec![]
+        ),
+        Err(RenderResultError::UnexpectedContentType(_))
+    ));
+}
+
+#[test]
+fn results_of_other_jobs_are_detected() {
+    let result = example();
+    assert!(result.check_job(&result.job).is_ok());
+    let other = result.job.clone().with_seed(8);
+    assert!(matches!(
+        result.check_job(&other),
+        Err(RenderResultError::JobMismatch { .. })
+    ));
+}
@@ -0,0 +1,76 @@
+//! Framing of tiles that are sent as blocks of rows.
+//!
+//! A response is a sequence of row blocks, each a 12 byte header followed by
+//! a PNG of the rows. Blocks are in order from top to bottom and together
+//! cover the tile. A client that reads the body as it arrives can show each
+//! block once its bytes are complete.
+
+/// Finished rows of a tile, as PNG.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowBlock {
+    /// First row of the block, counted from the top of the tile.
+    pub y: u32,
+    /// Number of rows in the block.
+    pub h: u32,
+    pub png: Vec<u8>,
+}
+
+impl RowBlock {
+    /// Bytes before the PNG: `y`, `h` and the PNG length as big-endian u32.
+    pub const HEADER_LEN: usize = 12;
+
+    /// Media type of a sequence of row blocks.
+    pub const CONTENT_TYPE: &'static str = "application/x-clumsy-row-blocks";
+
+    /// Appends the framed block to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.reserve(Self::HEADER_LEN + self.png.len());
+        out.extend_from_slice(&self.y.to_be_bytes());
+        out.extend_from_slice(&self.h.to_be_bytes());
+        out.extend_from_slice(&(self.png.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.png);
+    }
+
+    /// Reads the block at the start of `buf`, returning it together with the
+    /// number of bytes it took. `None` if `buf` does not hold a full block
+    /// yet, so that a partially received body can be retried later.
+    pub fn decode(buf: &[u8]) -> Option<(RowBlock, usize)> {
+        let word = |i: usize| {
+            let bytes = buf.get(4 * i..4 * i + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let (y, h, len) = (word(0)?, word(1)?, word(2)? as usize);
+        let end = Self::HEADER_LEN.checked_add(len)?;
+        let png = buf.get(Self::HEADER_LEN..end)?.to_vec();
+        Some((RowBlock { y, h, png }, end))
+    }
+}
+
+#[test]
+fn row_blocks_round_trip_in_a_stream() {
+    let blocks = [
+        RowBlock {
+            y: 0,
+            h: 16,
+            png: vec![1, 2, 3],
+        },
+        RowBlock {
+            y: 16,
+            h: 4,
+            png: vec![],
+        },
+    ];
+    let mut stream = vec![];
+    for block in &blocks {
+        block.encode(&mut stream);
+    }
+    assert_eq!(stream.len(), 2 * RowBlock::HEADER_LEN + 3);
+
+    // incomplete blocks are not decoded
+    assert_eq!(RowBlock::decode(&stream[..RowBlock::HEADER_LEN + 2]), None);
+    let (first, len) = RowBlock::decode(&stream).unwrap();
+    assert_eq!(first, blocks[0]);
+    let (second, rest) = RowBlock::decode(&stream[len..]).unwrap();
+    assert_eq!(second, blocks[1]);
+    assert_eq!(len + rest, stream.len());
+}
@@ -0,0 +1,376 @@
+//! Splitting a camera frame into render jobs.
+//!
+//! Tiles form a grid with integer boundaries, so neighbouring tiles share an
+//! edge exactly and together cover every pixel of the frame once. Only the
+//! order in which the tiles are handed out differs between strategies.
+
+use std::cmp::Reverse;
+
+use crate::RenderJob;
+
+/// Order of the tiles returned by a `TilePlanner`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Left to right, top to bottom, the default.
+    #[default]
+    RowMajor,
+    /// Outwards from the centre of the frame, where the subject usually is.
+    Spiral,
+    /// Along a Hilbert curve, consecutive tiles are neighbours.
+    Hilbert,
+    /// A coarse grid spread over the frame first, then the tiles in between,
+    /// so that a preview of the whole frame appears early.
+    Progressive,
+}
+
+/// Splits a frame into tiles. Everything but the position and size of the
+/// frame job is copied into each tile.
+#[derive(Clone, Debug)]
+pub struct TilePlanner {
+    frame: RenderJob,
+    order: TileOrder,
+}
+
+/// Columns and rows of tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Grid {
+    cols: u32,
+    rows: u32,
+}
+
+impl TilePlanner {
+    /// Plans tiles of the camera frame of `frame`, its own tile is ignored.
+    pub fn new(frame: RenderJob) -> Self {
+        Self {
+            frame,
+            order: TileOrder::default(),
+        }
+    }
+
+    pub fn with_order(mut self, order: TileOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Splits the frame into a grid of at least `n` tiles, fewer only if the
+    /// frame has fewer pixels in a direction than the grid has tiles.
+    pub fn split_into(&self, n: u32) -> Vec<RenderJob> {
+        self.tiles(self.grid(n as u64))
+    }
+
+    /// Splits the frame into tiles where each casts at most `max_rays` rays,
+    /// as counted by `RenderJob::max_rays`. Tiles are about square and as
+    /// large as the budget allows. If a single pixel exceeds that, each
+    /// pixel becomes a tile.
+    pub fn split_by_cost(&self, max_rays: u64) -> Vec<RenderJob> {
+        let per_pixel = self.pixel_job().max_rays().max(1);
+        let (w, h) = (
+            self.frame.camera_w.max(1) as u64,
+            self.frame.camera_h.max(1) as u64,
+        );
+        let pixels = (max_rays / per_pixel).max(1);
+        let tile_w = ((pixels as f64).sqrt() as u64).clamp(1, w);
+        let tile_h = (pixels / tile_w).clamp(1, h);
+        // a tile as high as the frame may be wider instead
+        let tile_w = (pixels / tile_h).clamp(1, w);
+        self.tiles(Grid {
+            cols: w.div_ceil(tile_w) as u32,
+            rows: h.div_ceil(tile_h) as u32,
+        })
+    }
+
+    /// A one pixel job with the settings of the frame.
+    fn pixel_job(&self) -> RenderJob {
+        let mut job = self.frame.clone();
+        (job.w, job.h) = (1, 1);
+        job
+    }
+
+    /// Grid of at least `n` cells that are about square in pixels, limited
+    /// to one pixel per cell.
+    fn grid(&self, n: u64) -> Grid {
+        let n = n.max(1);
+        let (w, h) = (
+            self.frame.camera_w.max(1) as u64,
+            self.frame.camera_h.max(1) as u64,
+        );
+        let cols = ((n as f64 * w as f64 / h as f64).sqrt().round() as u64).clamp(1, n.min(w));
+        let rows = n.div_ceil(cols).min(h);
+        // rounding up the rows may leave a column to spare, and a grid as
+        // high as the frame needs more columns
+        let cols = n.div_ceil(rows).min(w);
+        Grid {
+            cols: cols as u32,
+            rows: rows as u32,
+        }
+    }
+
+    fn tiles(&self, grid: Grid) -> Vec<RenderJob> {
+        let (camera_w, camera_h) = (self.frame.camera_w, self.frame.camera_h);
+        if camera_w == 0 || camera_h == 0 {
+            return vec![];
+        }
+        // exact integer boundaries, the i-th of `n` cells starts here
+        let bound = |i: u32, n: u32, pixels: u32| (i as u64 * pixels as u64 / n as u64) as u32;
+        self.order
+            .cells(grid)
+            .into_iter()
+            .map(|(col, row)| {
+                let mut job = self.frame.clone();
+                job.x = bound(col, grid.cols, camera_w);
+                job.y = bound(row, grid.rows, camera_h);
+                job.w = bound(col + 1, grid.cols, camera_w) - job.x;
+                job.h = bound(row + 1, grid.rows, camera_h) - job.y;
+                job
+            })
+            .collect()
+    }
+}
+
+impl TileOrder {
+    /// All `(col, row)` cells of the grid, in this order.
+    fn cells(self, grid: Grid) -> Vec<(u32, u32)> {
+        let row_major = (0..grid.rows).flat_map(|row| (0..grid.cols).map(move |col| (col, row)));
+        match self {
+            TileOrder::RowMajor => row_major.collect(),
+            TileOrder::Spiral => spiral(grid),
+            TileOrder::Hilbert => hilbert(grid),
+            TileOrder::Progressive => {
+                // cells on coarser sub-grids first, row-major within a level
+                let level = |(col, row): (u32, u32)| col.trailing_zeros().min(row.trailing_zeros());
+                let mut cells: Vec<_> = row_major.collect();
+                cells.sort_by_key(|&cell| Reverse(level(cell)));
+                cells
+            }
+        }
+    }
+}
+
+/// Walks right, down, left, up with growing legs from the centre cell and
+/// keeps the cells inside the grid.
+fn spiral(grid: Grid) -> Vec<(u32, u32)> {
+    let total = grid.cols as usize * grid.rows as usize;
+    let mut cells = Vec::with_capacity(total);
+    let (mut col, mut row) = (((grid.cols - 1) / 2) as i64, ((grid.rows - 1) / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let visit = |cells: &mut Vec<_>, col: i64, row: i64| {
+        if (0..grid.cols as i64).contains(&col) && (0..grid.rows as i64).contains(&row) {
+            cells.push((col as u32, row as u32));
+        }
+    };
+    visit(&mut cells, col, row);
+    'walk: loop {
+        for (turn, (dx, dy)) in directions.into_iter().enumerate() {
+            for _ in 0..leg {
+                if cells.len() == total {
+                    break 'walk;
+                }
+                col += dx;
+                row += dy;
+                visit(&mut cells, col, row);
+            }
+            // legs grow after every second turn
+            if turn % 2 == 1 {
+                leg += 1;
+            }
+        }
+    }
+    cells
+}
+
+/// Cells along a Hilbert curve over the smallest power of two square
+/// containing the grid, skipping those outside of it.
+fn hilbert(grid: Grid) -> Vec<(u32, u32)> {
+    let side = grid.cols.max(grid.rows).next_power_of_two() as u64;
+    (0..side * side)
+        .map(|d| hilbert_cell(side, d))
+        .filter(|&(col, row)| col < grid.cols && row < grid.rows)
+        .collect()
+}
+
+/// Position of the `d`-th cell on a Hilbert curve through a `side` x `side`
+/// square, `side` being a power of two.
+fn hilbert_cell(side: u64, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x as u32, y as u32)
+}
+
+#[cfg(test)]
+const ORDERS: [TileOrder; 4] = [
+    TileOrder::RowMajor,
+    TileOrder::Spiral,
+    TileOrder::Hilbert,
+    TileOrder::Progressive,
+];
+
+/// Panics unless `tiles` cover the frame of `frame` exactly once.
+#[cfg(test)]
+fn assert_exact_cover(frame: &RenderJob, tiles: &[RenderJob]) {
+    let (w, h) = (frame.camera_w as usize, frame.camera_h as usize);
+    let mut covered = vec![0u8; w * h];
+    for tile in tiles {
+        assert!(tile.w > 0 && tile.h > 0, "empty tile {tile}");
+        assert_eq!(tile.validate_with(&crate::Limits::default()), Ok(()));
+        for y in tile.y..tile.y + tile.h {
+            for x in tile.x..tile.x + tile.w {
+                covered[y as usize * w + x as usize] += 1;
+            }
+        }
+    }
+    assert!(
+        covered.iter().all(|&n| n == 1),
+        "{} tiles do not cover {w}x{h} exactly once",
+        tiles.len()
+    );
+}
+
+/// Deterministic pseudo random numbers for the property tests.
+#[cfg(test)]
+struct XorShift(u64);
+
+#[cfg(test)]
+impl XorShift {
+    fn below(&mut self, n: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as u32
+    }
+}
+
+#[test]
+fn tiles_cover_the_frame_exactly_once() {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..300 {
+        let (w, h) = (1 + rng.below(97), 1 + rng.below(67));
+        let frame = RenderJob::new(0, 0, w, h, w, h, 1 + rng.below(8), rng.below(4));
+        let n = rng.below(200);
+        let max_rays = 1 + rng.below(20_000) as u64;
+        for order in ORDERS {
+            let planner = TilePlanner::new(frame.clone()).with_order(order);
+
+            let tiles = planner.split_into(n);
+            assert_exact_cover(&frame, &tiles);
+            if w >= n && h >= n {
+                assert!(tiles.len() >= n.max(1) as usize);
+            }
+
+            let tiles = planner.split_by_cost(max_rays);
+            assert_exact_cover(&frame, &tiles);
+            let per_pixel = frame.max_rays() / (w as u64 * h as u64);
+            if per_pixel <= max_rays {
+                assert!(tiles.iter().all(|tile| tile.max_rays() <= max_rays));
+            }
+        }
+    }
+}
+
+#[test]
+fn orders_visit_the_same_tiles() {
+    let frame = RenderJob::new(0, 0, 640, 480, 640, 480, 4, 3).with_seed(9);
+    let mut row_major = TilePlanner::new(frame.clone()).split_into(30);
+    assert_eq!(row_major.len(), 30);
+    assert!(row_major.iter().all(|tile| tile.seed == 9));
+    row_major.sort_by_key(|tile| (tile.y, tile.x));
+    for order in ORDERS {
+        let mut tiles = TilePlanner::new(frame.clone())
+            .with_order(order)
+            .split_into(30);
+        tiles.sort_by_key(|tile| (tile.y, tile.x));
+        assert_eq!(tiles, row_major, "{order:?}");
+    }
+}
+
+#[test]
+fn orders_start_where_expected() {
+    let frame = RenderJob::new(0, 0, 50, 50, 50, 50, 1, 1);
+    let planner = TilePlanner::new(frame).with_order(TileOrder::Spiral);
+    let tiles = planner.split_into(25);
+    // centre first, then its right neighbour
+    assert_eq!((tiles[0].x, tiles[0].y), (20, 20));
+    assert_eq!((tiles[1].x, tiles[1].y), (30, 20));
+
+    let tiles = planner.with_order(TileOrder::Hilbert).split_into(16);
+    for pair in tiles.windows(2) {
+        let dx = pair[0].x.abs_diff(pair[1].x);
+        let dy = pair[0].y.abs_diff(pair[1].y);
+        assert!(
+            dx + dy <= 13,
+            "{} and {} are not neighbours",
+            pair[0],
+            pair[1]
+        );
+    }
+
+    let frame = RenderJob::new(0, 0, 80, 80, 80, 80, 1, 1);
+    let planner = TilePlanner::new(frame).with_order(TileOrder::Progressive);
+    let starts: Vec<_> = planner
+        .split_into(64)
+        .iter()
+        .take(4)
+        .map(|tile| (tile.x, tile.y))
+        .collect();
+    assert_eq!(starts, [(0, 0), (40, 0), (0, 40), (40, 40)]);
+}
+
+#[test]
+fn degenerate_frames() {
+    let empty = RenderJob::new(0, 0, 0, 0, 0, 10, 1, 1);
+    assert!(TilePlanner::new(empty).split_into(4).is_empty());
+
+    // more tiles than pixels in one direction
+    let thin = RenderJob::new(0, 0, 3, 100, 3, 100, 1, 1);
+    let tiles = TilePlanner::new(thin.clone()).split_into(1000);
+    assert_exact_cover(&thin, &tiles);
+    assert!(tiles.iter().all(|tile| tile.w == 1));
+    assert_eq!(tiles.len(), 300);
+
+    // a single pixel over budget still gets rendered
+    let expensive = RenderJob::new(0, 0, 4, 4, 4, 4, 100, 9);
+    let tiles = TilePlanner::new(expensive.clone()).split_by_cost(10);
+    assert_exact_cover(&expensive, &tiles);
+    assert_eq!(tiles.len(), 16);
+}
+
+#[test]
+fn tiles_follow_the_aspect_ratio() {
+    let frame = RenderJob::new(0, 0, 1600, 400, 1600, 400, 4, 3);
+    let planner = TilePlanner::new(frame.clone());
+    let tiles = planner.split_into(16);
+    assert_eq!(tiles.len(), 16);
+    assert!(tiles.iter().all(|tile| (tile.w, tile.h) == (200, 200)));
+
+    let per_pixel = frame.max_rays() / (1600 * 400);
+    let tiles = planner.split_by_cost(200 * 200 * per_pixel);
+    assert_eq!(tiles.len(), 16);
+    assert!(tiles.iter().all(|tile| (tile.w, tile.h) == (200, 200)));
+
+    // a budget above the frame height gives tiles of full height
+    let tiles = planner.split_by_cost(400 * 800 * per_pixel);
+    assert_eq!(tiles.len(), 2);
+    assert!(tiles.iter().all(|tile| (tile.w, tile.h) == (800, 400)));
+
+    // a large frame with a small budget is split right away
+    let frame = RenderJob::new(0, 0, 16384, 16384, 16384, 16384, 1, 0);
+    let per_pixel = frame.max_rays() / (16384 * 16384);
+    let tiles = TilePlanner::new(frame).split_by_cost(64 * 64 * per_pixel);
+    assert_eq!(tiles.len(), 256 * 256);
+}
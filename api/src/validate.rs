@@ -0,0 +1,298 @@
+//! Semantic checks of render jobs, before a worker spends time on them.
+//!
+//! Parsing only checks that a job is well-formed. A job that parses may still
+//! describe a tile outside of the camera or more work than a worker accepts.
+
+use thiserror::Error;
+
+use crate::RenderJob;
+
+/// Upper bounds for jobs a worker accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Limits {
+    /// Maximum width and height of the camera, in pixels.
+    pub max_resolution: u32,
+    /// Maximum samples per pixel.
+    pub max_samples: u32,
+    /// Maximum bounces per ray.
+    pub max_recursion: u32,
+    /// Maximum rays a single job may cast, counting every bounce.
+    pub max_rays: u64,
+    /// Maximum jobs in one `RenderBatch`.
+    pub max_batch_jobs: usize,
+    /// Maximum rays of all jobs in one `RenderBatch` together.
+    pub max_batch_rays: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_resolution: 16_384,
+            max_samples: 4096,
+            max_recursion: 256,
+            max_rays: 1 << 32,
+            max_batch_jobs: 256,
+            max_batch_rays: 1 << 34,
+        }
+    }
+}
+
+impl Limits {
+    pub fn with_max_resolution(mut self, max_resolution: u32) -> Self {
+        self.max_resolution = max_resolution;
+        self
+    }
+
+    pub fn with_max_samples(mut self, max_samples: u32) -> Self {
+        self.max_samples = max_samples;
+        self
+    }
+
+    pub fn with_max_recursion(mut self, max_recursion: u32) -> Self {
+        self.max_recursion = max_recursion;
+        self
+    }
+
+    pub fn with_max_rays(mut self, max_rays: u64) -> Self {
+        self.max_rays = max_rays;
+        self
+    }
+
+    pub fn with_max_batch_jobs(mut self, max_batch_jobs: usize) -> Self {
+        self.max_batch_jobs = max_batch_jobs;
+        self
+    }
+
+    pub fn with_max_batch_rays(mut self, max_batch_rays: u64) -> Self {
+        self.max_batch_rays = max_batch_rays;
+        self
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum JobValidationError {
+    #[error("camera of {w}x{h} pixels is empty")]
+    EmptyCamera { w: u32, h: u32 },
+    #[error("tile of {w}x{h} pixels is empty")]
+    EmptyTile { w: u32, h: u32 },
+    #[error("tile {w}x{h} at ({x}, {y}) reaches outside of the {camera_w}x{camera_h} camera")]
+    OutsideCamera {
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        camera_w: u32,
+        camera_h: u32,
+    },
+    #[error("job needs at least one sample per pixel")]
+    NoSamples,
+    #[error("camera of {w}x{h} pixels exceeds the maximum of {max} pixels per side")]
+    ResolutionTooHigh { w: u32, h: u32, max: u32 },
+    #[error("{samples} samples per pixel exceed the maximum of {max}")]
+    TooManySamples { samples: u32, max: u32 },
+    #[error("recursion depth {recursion} exceeds the maximum of {max}")]
+    RecursionTooDeep { recursion: u32, max: u32 },
+    #[error("job casts up to {rays} rays, more than the maximum of {max}")]
+    RayBudgetExceeded { rays: u64, max: u64 },
+    #[error("shutter open from {open} for {shutter} thousandths closes after the frame")]
+    ShutterOutsideFrame { open: u32, shutter: u32 },
+}
+
+impl RenderJob {
+    /// Checks the job against the default `Limits`.
+    pub fn validate(&self) -> Result<(), JobValidationError> {
+        self.validate_with(&Limits::default())
+    }
+
+    /// Checks that the tile is a non-empty part of the camera and that the
+    /// job stays within `limits`.
+    pub fn validate_with(&self, limits: &Limits) -> Result<(), JobValidationError> {
+        use JobValidationError::*;
+
+        if self.camera_w == 0 || self.camera_h == 0 {
+            return Err(EmptyCamera {
+                w: self.camera_w,
+                h: self.camera_h,
+            });
+        }
+        if self.w == 0 || self.h == 0 {
+            return Err(EmptyTile {
+                w: self.w,
+                h: self.h,
+            });
+        }
+        let fits = |start: u32, len: u32, frame: u32| start as u64 + len as u64 <= frame as u64;
+        if !fits(self.x, self.w, self.camera_w) || !fits(self.y, self.h, self.camera_h) {
+            return Err(OutsideCamera {
+                x: self.x,
+                y: self.y,
+                w: self.w,
+                h: self.h,
+                camera_w: self.camera_w,
+                camera_h: self.camera_h,
+            });
+        }
+        if self.n_samples == 0 {
+            return Err(NoSamples);
+        }
+        if self.shutter_open as u64 + self.shutter as u64 > RenderJob::MAX_SHUTTER as u64 {
+            return Err(ShutterOutsideFrame {
+                open: self.shutter_open,
+                shutter: self.shutter,
+            });
+        }
+        if self.camera_w > limits.max_resolution || self.camera_h > limits.max_resolution {
+            return Err(ResolutionTooHigh {
+                w: self.camera_w,
+                h: self.camera_h,
+                max: limits.max_resolution,
+            });
+        }
+        if self.n_samples > limits.max_samples {
+            return Err(TooManySamples {
+                samples: self.n_samples,
+                max: limits.max_samples,
+            });
+        }
+        if self.n_recursion > limits.max_recursion {
+            return Err(RecursionTooDeep {
+                recursion: self.n_recursion,
+                max: limits.max_recursion,
+            });
+        }
+        let rays = self.max_rays();
+        if rays > limits.max_rays {
+            return Err(RayBudgetExceeded {
+                rays,
+                max: limits.max_rays,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rays cast if every path bounces `n_recursion` times, saturating.
+    pub fn max_rays(&self) -> u64 {
+        [self.h, self.n_samples, self.n_recursion.saturating_add(1)]
+            .into_iter()
+            .fold(self.w as u64, |rays, n| rays.saturating_mul(n as u64))
+    }
+}
+
+#[test]
+fn tiles_must_be_inside_the_camera() {
+    use JobValidationError::*;
+
+    assert_eq!(
+        RenderJob::new(0, 0, 10, 10, 10, 10, 1, 1).validate(),
+        Ok(())
+    );
+    assert_eq!(
+        RenderJob::new(0, 0, 10, 10, 5, 5, 1, 1).validate(),
+        Err(OutsideCamera {
+            x: 0,
+            y: 0,
+            w: 10,
+            h: 10,
+            camera_w: 5,
+            camera_h: 5
+        })
+    );
+    assert!(matches!(
+        RenderJob::new(u32::MAX, 0, 2, 1, 5, 5, 1, 1).validate(),
+        Err(OutsideCamera { .. })
+    ));
+    assert_eq!(
+        RenderJob::new(0, 0, 1, 1, 0, 5, 1, 1).validate(),
+        Err(EmptyCamera { w: 0, h: 5 })
+    );
+    assert_eq!(
+        RenderJob::new(0, 0, 4, 0, 5, 5, 1, 1).validate(),
+        Err(EmptyTile { w: 4, h: 0 })
+    );
+    assert_eq!(
+        RenderJob::new(0, 0, 5, 5, 5, 5, 0, 1).validate(),
+        Err(NoSamples)
+    );
+    assert_eq!(
+        RenderJob::new(0, 0, 5, 5, 5, 5, 1, 1)
+            .with_shutter(250, 750)
+            .validate(),
+        Ok(())
+    );
+    assert_eq!(
+        RenderJob::new(0, 0, 5, 5, 5, 5, 1, 1)
+            .with_shutter(250, 751)
+            .validate(),
+        Err(ShutterOutsideFrame {
+            open: 250,
+            shutter: 751
+        })
+    );
+    assert!(matches!(
+        RenderJob::new(0, 0, 5, 5, 5, 5, 1, 1)
+            .with_shutter(u32::MAX, u32::MAX)
+            .validate(),
+        Err(ShutterOutsideFrame { .. })
+    ));
+}
+
+#[test]
+fn limits_are_enforced() {
+    use JobValidationError::*;
+
+    let limits = Limits::default()
+        .with_max_resolution(100)
+        .with_max_samples(16)
+        .with_max_recursion(4)
+        .with_max_rays(10_000);
+    let job = RenderJob::new(0, 0, 10, 10, 100, 100, 16, 4);
+    assert_eq!(job.max_rays(), 10 * 10 * 16 * 5);
+    assert_eq!(job.validate_with(&limits), Ok(()));
+
+    let job = RenderJob::new(0, 0, 10, 10, 101, 100, 16, 4);
+    assert!(matches!(
+        job.validate_with(&limits),
+        Err(ResolutionTooHigh { max: 100, .. })
+    ));
+    let job = RenderJob::new(0, 0, 10, 10, 100, 100, 17, 4);
+    assert!(matches!(
+        job.validate_with(&limits),
+        Err(TooManySamples { samples: 17, .. })
+    ));
+    let job = RenderJob::new(0, 0, 10, 10, 100, 100, 1, 5);
+    assert!(matches!(
+        job.validate_with(&limits),
+        Err(RecursionTooDeep { recursion: 5, .. })
+    ));
+    let job = RenderJob::new(0, 0, 20, 20, 100, 100, 16, 4);
+    assert_eq!(
+        job.validate_with(&limits),
+        Err(RayBudgetExceeded {
+            rays: 32_000,
+            max: 10_000
+        })
+    );
+
+    let huge = RenderJob::new(
+        0,
+        0,
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+        u32::MAX,
+    );
+    assert_eq!(huge.max_rays(), u64::MAX);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn limits_left_out_keep_their_default() {
+    let limits: Limits = serde_json::from_str(r#"{"max_samples": 64}"#).unwrap();
+    assert_eq!(limits, Limits::default().with_max_samples(64));
+    let limits: Limits = serde_json::from_str("{}").unwrap();
+    assert_eq!(limits, Limits::default());
+}
@@ -0,0 +1,183 @@
+//! Throughput benchmarks for the ray tracer.
+//!
+//! Run with `cargo bench -p clumsy-rt`. Criterion stores machine-readable
+//! results in `target/criterion/<group>/<benchmark>/new/estimates.json`, see
+//! the README for how they map to the job size heuristic of the web-view.
+
+use api::RenderJob;
+use clumsy_rt::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nalgebra::{Point3, Vector3};
+use ncollide3d::pipeline::{CollisionGroups, CollisionWorld, GeometricQueryType};
+use ncollide3d::query::Ray;
+use std::str::FromStr;
+
+const N_RECURSION: usize = 8;
+
+fn sample_scenes() -> Vec<(&'static str, Scene)> {
+    vec![
+        ("simple", sample_scenes::build_simple_scene()),
+        ("cool", sample_scenes::build_cool_scene()),
+    ]
+}
+
+/// Rays through a regular grid on the default viewport.
+fn primary_rays(n: usize) -> Vec<Ray<f32>> {
+    let mut rays = Vec::with_capacity(n * n);
+    for y in 0..n {
+        for x in 0..n {
+            let u = x as f32 / (n - 1) as f32 - 0.5;
+            let v = y as f32 / (n - 1) as f32 - 0.5;
+            let dir = Vector3::new(u * VIEWPORT_WIDTH, v * VIEWPORT_WIDTH * 0.75, -FOCAL_LENGTH);
+            rays.push(Ray::new(Point3::origin(), dir));
+        }
+    }
+    rays
+}
+
+fn cast_ray(c: &mut Criterion) {
+    let rays = primary_rays(16);
+    let mut group = c.benchmark_group("cast_ray");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for (name, scene) in sample_scenes() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &scene, |b, scene| {
+            b.iter(|| {
+                for ray in &rays {
+                    black_box(scene.cast_ray(ray, N_RECURSION));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Closest-hit queries through the scene BVH, compared to the
+/// `CollisionWorld` interference query that was used before.
+fn closest_hit(c: &mut Criterion) {
+    let rays = primary_rays(16);
+    let scenes = vec![
+        ("cool", sample_scenes::build_cool_scene()),
+        (
+            "spheres_10k",
+            sample_scenes::build_sphere_field_scene(10_000),
+        ),
+    ];
+    let mut group = c.benchmark_group("closest_hit");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for (name, scene) in scenes {
+        group.bench_with_input(BenchmarkId::new("bvh", name), &scene, |b, scene| {
+            b.iter(|| {
+                for ray in &rays {
+                    black_box(scene.closest_hit(ray, f32::MAX).map(|hit| hit.toi));
+                }
+            })
+        });
+
+        let groups = CollisionGroups::new();
+        let mut world = CollisionWorld::new(0.0002);
+        scene.for_each_object(|obj, position| {
+            assert_eq!(position.scaling(), 1.0, "collision world needs isometries");
+            world.add(
+                position.isometry,
+                obj.shape.clone(),
+                groups,
+                GeometricQueryType::Contacts(0.2, 0.9),
+                (),
+            );
+        });
+        world.update();
+        group.bench_with_input(
+            BenchmarkId::new("collision_world", name),
+            &world,
+            |b, world| {
+                b.iter(|| {
+                    for ray in &rays {
+                        let mut intersections: Vec<_> = world
+                            .interferences_with_ray(ray, f32::MAX, &groups)
+                            .filter(|(_handle, _obj, collision)| collision.toi > f32::EPSILON)
+                            .collect();
+                        intersections
+                            .sort_unstable_by(|a, b| a.2.toi.partial_cmp(&b.2.toi).unwrap());
+                        black_box(intersections.first().map(|i| i.2.toi));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn render_tile(c: &mut Criterion) {
+    let scene = sample_scenes::build_cool_scene();
+    let mut group = c.benchmark_group("render_tile");
+    group.sample_size(20);
+    for size in [8, 16, 32, 64] {
+        let camera = Camera::new(4, 4, 256, 192);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            let mut img = PixelPlane::new(size, size);
+            b.iter(|| camera.render_tile(&scene, 96, 64, &mut img))
+        });
+    }
+    group.finish();
+}
+
+/// Cost of complete jobs as the web-view submits them, for the quality
+/// settings it selects between.
+fn job_cost(c: &mut Criterion) {
+    let mut group = c.benchmark_group("job_cost");
+    group.sample_size(10);
+    for (samples, recursion) in [(1, 2), (2, 2), (4, 3), (8, 4), (16, 6)] {
+        let job = RenderJob::new(96, 64, 32, 32, 256, 192, samples, recursion);
+        group.throughput(Throughput::Elements((job.w * job.h) as u64));
+        let id = BenchmarkId::new(format!("s{samples}"), format!("r{recursion}"));
+        group.bench_with_input(id, &job, |b, job| b.iter(|| job.render()));
+    }
+    group.finish();
+}
+
+fn write_png(c: &mut Criterion) {
+    let scene = sample_scenes::build_cool_scene();
+    let mut group = c.benchmark_group("write_png");
+    for size in [32, 128, 512] {
+        let camera = Camera::new(1, 2, size, size);
+        let mut img = PixelPlane::new(size, size);
+        camera.render_tile(&scene, 0, 0, &mut img);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &img, |b, img| {
+            let mut buf = Vec::new();
+            b.iter(|| {
+                buf.clear();
+                img.write_png(&mut buf).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn render_job_encoding(c: &mut Criterion) {
+    let job = RenderJob::new(150, 157, 30, 22, 240, 180, 1, 2);
+    let text = job.to_string();
+    let numbers = job.to_vec();
+    let mut group = c.benchmark_group("render_job");
+    group.bench_function("to_string", |b| b.iter(|| black_box(&job).to_string()));
+    group.bench_function("from_str", |b| {
+        b.iter(|| RenderJob::from_str(black_box(&text)).unwrap())
+    });
+    group.bench_function("to_vec", |b| b.iter(|| black_box(&job).to_vec()));
+    group.bench_function("try_from_slice", |b| {
+        b.iter(|| RenderJob::try_from_slice(black_box(&numbers)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    cast_ray,
+    closest_hit,
+    render_tile,
+    job_cost,
+    write_png,
+    render_job_encoding
+);
+criterion_main!(benches);
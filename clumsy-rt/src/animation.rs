@@ -0,0 +1,371 @@
+//! Keyframe animation of transformations.
+//!
+//! Time is measured in frames. A track holds transformations at a few key
+//! times and interpolates between them. Before the first and after the last
+//! key, the transformation stays constant, unless the track repeats.
+
+use crate::bvh::Aabb;
+use nalgebra::{Similarity3, Translation3, UnitQuaternion, Vector3};
+
+/// How a track moves between two keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Constant speed between keys, sharp changes at each key.
+    Linear,
+    /// Catmull-Rom spline through all keys, for smooth motion. Rotations
+    /// are interpolated along the shortest arc in both modes.
+    Spline,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: Similarity3<f32>,
+}
+
+/// A point in time while the shutter is open, `offset` frames after the
+/// start of `frame`. The offset is at most one frame, which keeps the bounds
+/// of objects that only move during the exposure finite.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Time {
+    pub frame: f32,
+    pub offset: f32,
+}
+
+impl Time {
+    /// The start of `frame`.
+    pub fn at_frame(frame: f32) -> Self {
+        Self { frame, offset: 0.0 }
+    }
+
+    /// Frames since frame 0, where tracks are sampled.
+    pub fn frames(&self) -> f32 {
+        self.frame + self.offset
+    }
+}
+
+/// Transformation over time, defined by keyframes.
+#[derive(Clone, Debug)]
+pub struct Track {
+    interpolation: Interpolation,
+    /// Sorted by time.
+    keys: Vec<Keyframe>,
+    /// Starts over at the first key after the last one.
+    repeating: bool,
+}
+
+/// Bounds of animated objects are sampled this many times between keys.
+const BOUNDS_SAMPLES_PER_KEY: usize = 16;
+
+impl Track {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            interpolation,
+            keys: vec![],
+            repeating: false,
+        }
+    }
+
+    /// Repeats the track forever, in both directions. The first and last key
+    /// should hold the same transformation, or the motion jumps at each
+    /// repetition.
+    pub fn repeating(mut self) -> Self {
+        self.repeating = true;
+        self
+    }
+
+    /// Adds a key, replacing an existing key at the same time.
+    pub fn key(mut self, time: f32, transform: Similarity3<f32>) -> Self {
+        let key = Keyframe { time, transform };
+        match self
+            .keys
+            .binary_search_by(|k| k.time.partial_cmp(&time).unwrap())
+        {
+            Ok(i) => self.keys[i] = key,
+            Err(i) => self.keys.insert(i, key),
+        }
+        self
+    }
+
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
+    }
+
+    /// Transformation at the given time. Identity for a track without keys.
+    pub fn sample(&self, time: f32) -> Similarity3<f32> {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Similarity3::identity(),
+        };
+        let period = last.time - first.time;
+        let time = if self.repeating && period > 0.0 && time.is_finite() {
+            first.time + (time - first.time).rem_euclid(period)
+        } else {
+            time
+        };
+        if time <= first.time {
+            return first.transform;
+        }
+        if time >= last.time {
+            return last.transform;
+        }
+        // keys[i].time < time < keys[i + 1].time
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let t = (time - a.time) / (b.time - a.time);
+
+        let rotation = slerp(
+            &a.transform.isometry.rotation,
+            &b.transform.isometry.rotation,
+            t,
+        );
+        let (translation, scaling) = match self.interpolation {
+            Interpolation::Linear => (
+                a.translation().lerp(&b.translation(), t),
+                a.transform.scaling() + (b.transform.scaling() - a.transform.scaling()) * t,
+            ),
+            Interpolation::Spline => {
+                let (before, after) = self.spline_neighbors(i);
+                (
+                    catmull_rom(
+                        before.translation(),
+                        a.translation(),
+                        b.translation(),
+                        after.translation(),
+                        t,
+                    ),
+                    catmull_rom(
+                        before.transform.scaling(),
+                        a.transform.scaling(),
+                        b.transform.scaling(),
+                        after.transform.scaling(),
+                        t,
+                    ),
+                )
+            }
+        };
+        Similarity3::from_parts(Translation3::from(translation), rotation, scaling)
+    }
+
+    /// Keys before and after the keys `i` and `i + 1` that shape the spline
+    /// between them.
+    fn spline_neighbors(&self, i: usize) -> (&Keyframe, &Keyframe) {
+        let n = self.keys.len();
+        // a repeating track continues through its first and last key
+        let before = match i {
+            0 if self.repeating && n > 2 => &self.keys[n - 2],
+            _ => &self.keys[i.saturating_sub(1)],
+        };
+        let after = match i + 2 {
+            j if j >= n && self.repeating && n > 2 => &self.keys[1],
+            j => &self.keys[j.min(n - 1)],
+        };
+        (before, after)
+    }
+
+    /// Bounds of `local` transformed over the entire track. Sampled densely,
+    /// then grown by how far any point of `local` can move between two
+    /// samples, which covers spline overshoots and rotations in between.
+    pub(crate) fn sweep(&self, local: &Aabb) -> Aabb {
+        if local.mins.x > local.maxs.x {
+            return *local;
+        }
+        let mut bounds = local.transformed(&self.sample(f32::NEG_INFINITY));
+        let radius = local.mins.coords.abs().sup(&local.maxs.coords.abs()).norm();
+        let mut margin = 0.0f32;
+        for (i, pair) in self.keys.windows(2).enumerate() {
+            for step in 1..=BOUNDS_SAMPLES_PER_KEY {
+                let t = step as f32 / BOUNDS_SAMPLES_PER_KEY as f32;
+                let time = pair[0].time + (pair[1].time - pair[0].time) * t;
+                bounds = bounds.merged(&local.transformed(&self.sample(time)));
+            }
+            // every time is at most half a step away from a sample
+            let max_step = self.max_speed(i, radius) / (2 * BOUNDS_SAMPLES_PER_KEY) as f32;
+            margin = margin.max(max_step);
+        }
+        let margin = Vector3::repeat(margin);
+        Aabb::new(bounds.mins - margin, bounds.maxs + margin)
+    }
+
+    /// Bound of how fast any point within `radius` of the origin moves
+    /// between the keys `i` and `i + 1`, in distance per unit of `t`.
+    fn max_speed(&self, i: usize, radius: f32) -> f32 {
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let (translation, scaling) = match self.interpolation {
+            Interpolation::Linear => (
+                (b.translation() - a.translation()).norm(),
+                (b.transform.scaling() - a.transform.scaling()).abs(),
+            ),
+            Interpolation::Spline => {
+                let (before, after) = self.spline_neighbors(i);
+                (
+                    catmull_rom_speed(
+                        before.translation(),
+                        a.translation(),
+                        b.translation(),
+                        after.translation(),
+                        |v| v.norm(),
+                    ),
+                    catmull_rom_speed(
+                        before.transform.scaling(),
+                        a.transform.scaling(),
+                        b.transform.scaling(),
+                        after.transform.scaling(),
+                        f32::abs,
+                    ),
+                )
+            }
+        };
+        // slerp turns at a constant rate
+        let angle = a
+            .transform
+            .isometry
+            .rotation
+            .angle_to(&b.transform.isometry.rotation);
+        let max_scaling = a.transform.scaling().abs().max(b.transform.scaling().abs()) + scaling;
+        translation + radius * (scaling + max_scaling * angle)
+    }
+}
+
+impl Keyframe {
+    fn translation(&self) -> Vector3<f32> {
+        self.transform.isometry.translation.vector
+    }
+}
+
+fn slerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+    // opposite rotations have no unique shortest arc
+    a.try_slerp(b, t, 1e-6)
+        .unwrap_or(if t < 0.5 { *a } else { *b })
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+/// Bound of the derivative of `catmull_rom` for `t` in `0..=1`, with `norm`
+/// measuring its coefficients.
+fn catmull_rom_speed<T>(p0: T, p1: T, p2: T, p3: T, norm: impl Fn(T) -> f32) -> f32
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let linear = p2 - p0;
+    let square = p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3;
+    let cube = p1 * 3.0 - p0 - p2 * 3.0 + p3;
+    0.5 * (norm(linear) + 2.0 * norm(square) + 3.0 * norm(cube))
+}
+
+#[test]
+fn tracks_pass_through_their_keys() {
+    let at = |x: f32| {
+        Similarity3::from_parts(
+            Translation3::new(x, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            1.0,
+        )
+    };
+    for interpolation in [Interpolation::Linear, Interpolation::Spline] {
+        let track = Track::new(interpolation)
+            .key(10.0, at(4.0))
+            .key(0.0, at(0.0))
+            .key(5.0, at(1.0));
+        let x = |time: f32| track.sample(time).isometry.translation.vector.x;
+        assert!((x(0.0) - 0.0).abs() < 1e-5);
+        assert!((x(5.0) - 1.0).abs() < 1e-5);
+        assert!((x(10.0) - 4.0).abs() < 1e-5);
+        // clamped outside of the keys
+        assert!((x(-3.0) - 0.0).abs() < 1e-5);
+        assert!((x(20.0) - 4.0).abs() < 1e-5);
+        // moving forward in between
+        assert!(x(2.5) > 0.0 && x(2.5) < 1.0);
+        assert!(x(7.5) > 1.0 && x(7.5) < 4.0);
+    }
+    let linear = Track::new(Interpolation::Linear)
+        .key(0.0, at(0.0))
+        .key(4.0, at(2.0));
+    assert!((linear.sample(1.0).isometry.translation.vector.x - 0.5).abs() < 1e-5);
+}
+
+#[test]
+fn repeating_tracks_start_over_after_the_last_key() {
+    let at = |x: f32| {
+        Similarity3::from_parts(
+            Translation3::new(x, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            1.0,
+        )
+    };
+    for interpolation in [Interpolation::Linear, Interpolation::Spline] {
+        let track = Track::new(interpolation)
+            .key(0.0, at(0.0))
+            .key(2.0, at(1.0))
+            .key(4.0, at(0.0))
+            .repeating();
+        let x = |time: f32| track.sample(time).isometry.translation.vector.x;
+        for time in [0.5, 1.0, 2.0, 3.5] {
+            assert!((x(time) - x(time + 4.0)).abs() < 1e-5);
+            assert!((x(time) - x(time - 8.0)).abs() < 1e-5);
+        }
+        assert!((x(4.0) - 0.0).abs() < 1e-5);
+        assert!((x(6.0) - 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn rotation_is_interpolated_along_the_shortest_arc() {
+    use std::f32::consts::FRAC_PI_2;
+    let turn = |angle: f32| {
+        Similarity3::from_parts(
+            Translation3::identity(),
+            UnitQuaternion::from_scaled_axis(Vector3::y() * angle),
+            1.0,
+        )
+    };
+    let track = Track::new(Interpolation::Spline)
+        .key(0.0, turn(0.0))
+        .key(1.0, turn(FRAC_PI_2));
+    let halfway = track.sample(0.5).isometry.rotation.angle();
+    assert!((halfway - FRAC_PI_2 / 2.0).abs() < 1e-4);
+}
+
+#[test]
+fn sweep_covers_spline_overshoots_between_samples() {
+    use nalgebra::Point3;
+
+    let at = |x: f32| {
+        Similarity3::from_parts(
+            Translation3::new(x, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            1.0,
+        )
+    };
+    // the spline passes x = 10 at its peak between two samples
+    let track = Track::new(Interpolation::Spline)
+        .key(0.0, at(0.0))
+        .key(1.0, at(10.0))
+        .key(2.0, at(3.0));
+    let local = Aabb::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5));
+    let bounds = track.sweep(&local);
+    for step in 0..=2000 {
+        let time = step as f32 / 1000.0;
+        let moved = local.transformed(&track.sample(time));
+        let inside =
+            (0..3).all(|i| moved.mins[i] >= bounds.mins[i] && moved.maxs[i] <= bounds.maxs[i]);
+        assert!(inside, "{moved:?} at {time} outside of {bounds:?}");
+    }
+}
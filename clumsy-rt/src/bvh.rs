@@ -0,0 +1,398 @@
+//! Bounding volume hierarchy (BVH) for ray queries against scene primitives.
+//!
+//! The tree is built once with a binned surface area heuristic (SAH) and then
+//! only read. It knows nothing about the primitives themselves, only their
+//! bounding boxes. Queries hand the index of each candidate primitive to a
+//! closure that performs the exact intersection test.
+
+use nalgebra::{Point3, Similarity3, Vector3};
+use ncollide3d::bounding_volume::AABB;
+use ncollide3d::query::Ray;
+
+/// Number of buckets per axis considered for a split.
+const BINS: usize = 12;
+/// Nodes with this many primitives or less are never split.
+const MAX_LEAF_SIZE: usize = 2;
+/// Limits the traversal stack, deeper nodes become leaves regardless of size.
+const MAX_DEPTH: usize = 60;
+/// Cost of visiting a node, relative to one primitive intersection test.
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub mins: Point3<f32>,
+    pub maxs: Point3<f32>,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, ordered such that each leaf covers a contiguous range.
+    indices: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+#[derive(Clone, Copy)]
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    Inner { left: usize, right: usize },
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+impl Aabb {
+    /// Neutral element for `merged`.
+    pub fn empty() -> Self {
+        let inf = f32::INFINITY;
+        Self::new(Point3::new(inf, inf, inf), Point3::new(-inf, -inf, -inf))
+    }
+
+    pub fn new(mins: Point3<f32>, maxs: Point3<f32>) -> Self {
+        Self { mins, maxs }
+    }
+
+    pub fn merged(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            mins: self.mins.inf(&other.mins),
+            maxs: self.maxs.sup(&other.maxs),
+        }
+    }
+
+    pub fn with_point(&self, p: &Point3<f32>) -> Aabb {
+        Aabb {
+            mins: self.mins.inf(p),
+            maxs: self.maxs.sup(p),
+        }
+    }
+
+    /// Bounds of this box after transformation, which may be larger than
+    /// the transformed content.
+    pub fn transformed(&self, transform: &Similarity3<f32>) -> Aabb {
+        if self.mins.x > self.maxs.x {
+            return *self;
+        }
+        let mut result = Aabb::empty();
+        for corner in 0..8 {
+            let p = Point3::new(
+                if corner & 1 == 0 {
+                    self.mins.x
+                } else {
+                    self.maxs.x
+                },
+                if corner & 2 == 0 {
+                    self.mins.y
+                } else {
+                    self.maxs.y
+                },
+                if corner & 4 == 0 {
+                    self.mins.z
+                } else {
+                    self.maxs.z
+                },
+            );
+            result = result.with_point(&transform.transform_point(&p));
+        }
+        result
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.mins, &self.maxs)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.maxs - self.mins;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Distance along the ray where it enters the box, if it does so before
+    /// `max_toi`. Rays starting inside the box enter at 0.
+    fn ray_entry(&self, origin: &Point3<f32>, inv_dir: &Vector3<f32>, max_toi: f32) -> Option<f32> {
+        let mut t_enter = 0.0f32;
+        let mut t_exit = max_toi;
+        for axis in 0..3 {
+            let t1 = (self.mins[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.maxs[axis] - origin[axis]) * inv_dir[axis];
+            // `max`/`min` ignore the NaN produced by rays parallel to a slab
+            t_enter = t_enter.max(t1.min(t2));
+            t_exit = t_exit.min(t1.max(t2));
+        }
+        (t_enter <= t_exit).then_some(t_enter)
+    }
+}
+
+impl From<AABB<f32>> for Aabb {
+    fn from(aabb: AABB<f32>) -> Self {
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+        Aabb::new(center - half_extents, center + half_extents)
+    }
+}
+
+impl Bvh {
+    /// Builds the hierarchy. The primitive index used in queries is the
+    /// position of its bounding box in `bounds`.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build_node(bounds, 0, bounds.len(), 0);
+        }
+        bvh
+    }
+
+    /// Finds the closest intersection along the ray.
+    ///
+    /// `intersect` is called with a primitive index and the current maximum
+    /// distance. It should return the distance of the intersection and
+    /// whatever information the caller wants to keep about it.
+    pub fn closest_hit<T>(
+        &self,
+        ray: &Ray<f32>,
+        max_toi: f32,
+        mut intersect: impl FnMut(usize, f32) -> Option<(f32, T)>,
+    ) -> Option<(f32, T)> {
+        let mut closest = None;
+        let mut max_toi = max_toi;
+        self.traverse(ray, max_toi, |indices, limit| {
+            for &i in indices {
+                if let Some((toi, hit)) = intersect(i, max_toi) {
+                    if toi < max_toi {
+                        max_toi = toi;
+                        closest = Some((toi, hit));
+                    }
+                }
+            }
+            *limit = max_toi;
+            false
+        });
+        closest
+    }
+
+    /// Checks whether anything intersects the ray before `max_toi`, as needed
+    /// for shadow rays. Stops at the first intersection found.
+    pub fn any_hit(
+        &self,
+        ray: &Ray<f32>,
+        max_toi: f32,
+        mut intersect: impl FnMut(usize, f32) -> bool,
+    ) -> bool {
+        self.traverse(ray, max_toi, |indices, limit| {
+            indices.iter().any(|&i| intersect(i, *limit))
+        })
+    }
+
+    /// Visits leaves in front-to-back order until `visit_leaf` returns true.
+    /// The leaf visitor may lower the maximum distance to prune the search.
+    fn traverse(
+        &self,
+        ray: &Ray<f32>,
+        max_toi: f32,
+        mut visit_leaf: impl FnMut(&[usize], &mut f32) -> bool,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let inv_dir = ray.dir.map(|d| 1.0 / d);
+        let mut max_toi = max_toi;
+        let mut stack = [(0usize, 0.0f32); MAX_DEPTH + 1];
+        let mut len = 0;
+        if let Some(t) = self.nodes[0]
+            .bounds
+            .ray_entry(&ray.origin, &inv_dir, max_toi)
+        {
+            stack[0] = (0, t);
+            len = 1;
+        }
+        while len > 0 {
+            len -= 1;
+            let (node_index, entry) = stack[len];
+            if entry > max_toi {
+                continue;
+            }
+            match self.nodes[node_index].kind {
+                NodeKind::Leaf { first, count } => {
+                    if visit_leaf(&self.indices[first..first + count], &mut max_toi) {
+                        return true;
+                    }
+                }
+                NodeKind::Inner { left, right } => {
+                    let entry_left =
+                        self.nodes[left]
+                            .bounds
+                            .ray_entry(&ray.origin, &inv_dir, max_toi);
+                    let entry_right =
+                        self.nodes[right]
+                            .bounds
+                            .ray_entry(&ray.origin, &inv_dir, max_toi);
+                    // push the farther child first, so the nearer one is visited first
+                    match (entry_left, entry_right) {
+                        (Some(l), Some(r)) if l <= r => {
+                            stack[len] = (right, r);
+                            stack[len + 1] = (left, l);
+                            len += 2;
+                        }
+                        (Some(l), Some(r)) => {
+                            stack[len] = (left, l);
+                            stack[len + 1] = (right, r);
+                            len += 2;
+                        }
+                        (Some(l), None) => {
+                            stack[len] = (left, l);
+                            len += 1;
+                        }
+                        (None, Some(r)) => {
+                            stack[len] = (right, r);
+                            len += 1;
+                        }
+                        (None, None) => (),
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], first: usize, count: usize, depth: usize) -> usize {
+        let range = first..first + count;
+        let node_bounds = self.indices[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.merged(&bounds[i]));
+        let node_index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            kind: NodeKind::Leaf { first, count },
+        });
+        if count <= MAX_LEAF_SIZE || depth >= MAX_DEPTH {
+            return node_index;
+        }
+
+        let centroid_bounds = self.indices[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.with_point(&bounds[i].center()));
+        let (axis, split_bin, split_cost) =
+            match find_split(bounds, &self.indices[range.clone()], &centroid_bounds) {
+                Some(split) => split,
+                None => return node_index,
+            };
+        let leaf_cost = count as f32;
+        let split_cost = TRAVERSAL_COST + split_cost / node_bounds.surface_area().max(f32::EPSILON);
+        if split_cost >= leaf_cost && count <= 4 * MAX_LEAF_SIZE {
+            return node_index;
+        }
+
+        // partition the indices in place, left bins first
+        let slice = &mut self.indices[range];
+        let mut num_left = 0;
+        for k in 0..slice.len() {
+            if bin_of(&bounds[slice[k]].center(), &centroid_bounds, axis) < split_bin {
+                slice.swap(k, num_left);
+                num_left += 1;
+            }
+        }
+
+        let left = self.build_node(bounds, first, num_left, depth + 1);
+        let right = self.build_node(bounds, first + num_left, count - num_left, depth + 1);
+        self.nodes[node_index].kind = NodeKind::Inner { left, right };
+        node_index
+    }
+}
+
+/// Evaluates the SAH for all bin boundaries on all axes.
+///
+/// Returns the axis, the first bin on the right side and the unnormalized
+/// cost of the cheapest split that leaves primitives on both sides.
+fn find_split(
+    bounds: &[Aabb],
+    indices: &[usize],
+    centroid_bounds: &Aabb,
+) -> Option<(usize, usize, f32)> {
+    let mut best: Option<(usize, usize, f32)> = None;
+    for axis in 0..3 {
+        if centroid_bounds.maxs[axis] <= centroid_bounds.mins[axis] {
+            continue;
+        }
+        let mut bins = [Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        }; BINS];
+        for &i in indices {
+            let bin = &mut bins[bin_of(&bounds[i].center(), centroid_bounds, axis)];
+            bin.bounds = bin.bounds.merged(&bounds[i]);
+            bin.count += 1;
+        }
+        // sweep from the right to get the cost of each right side
+        let mut right_area = [0.0; BINS];
+        let mut right_count = [0; BINS];
+        let mut acc = Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        };
+        for b in (1..BINS).rev() {
+            acc.bounds = acc.bounds.merged(&bins[b].bounds);
+            acc.count += bins[b].count;
+            right_area[b] = acc.bounds.surface_area();
+            right_count[b] = acc.count;
+        }
+        let mut left = Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        };
+        for b in 1..BINS {
+            left.bounds = left.bounds.merged(&bins[b - 1].bounds);
+            left.count += bins[b - 1].count;
+            if left.count == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let cost = left.bounds.surface_area() * left.count as f32
+                + right_area[b] * right_count[b] as f32;
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+    best
+}
+
+fn bin_of(centroid: &Point3<f32>, centroid_bounds: &Aabb, axis: usize) -> usize {
+    let extent = centroid_bounds.maxs[axis] - centroid_bounds.mins[axis];
+    let relative = (centroid[axis] - centroid_bounds.mins[axis]) / extent;
+    ((relative * BINS as f32) as usize).min(BINS - 1)
+}
+
+#[test]
+fn closest_hit_matches_brute_force() {
+    // a row of unit boxes along the z axis, the ray travels along -z
+    let bounds: Vec<Aabb> = (0..100)
+        .map(|i| {
+            let z = -2.0 * i as f32;
+            Aabb::new(
+                Point3::new(-0.5, -0.5, z - 0.5),
+                Point3::new(0.5, 0.5, z + 0.5),
+            )
+        })
+        .collect();
+    let bvh = Bvh::build(&bounds);
+    let ray = Ray::new(Point3::new(0.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0));
+    let hit = bvh.closest_hit(&ray, 1000.0, |i, max_toi| {
+        let toi = bounds[i].ray_entry(&ray.origin, &ray.dir.map(|d| 1.0 / d), max_toi)?;
+        Some((toi, i))
+    });
+    assert_eq!(hit.map(|(_, i)| i), Some(0));
+    let miss = Ray::new(Point3::new(2.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0));
+    assert!(!bvh.any_hit(&miss, 1000.0, |_, _| true));
+    assert!(bvh.any_hit(&ray, 1000.0, |_, _| true));
+}
@@ -0,0 +1,181 @@
+//! Reusing results of jobs rendered before, see `api::ResultCache`.
+
+use api::{JobHash, RenderJob, RenderResult, ResultCache};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Results stored as files in a directory, named by the job's content hash.
+/// Survives restarts and can be shared by processes on the same machine.
+pub struct FsCache {
+    dir: PathBuf,
+}
+
+impl FsCache {
+    /// Uses `dir` for the cache, creating it if necessary.
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, hash: JobHash) -> PathBuf {
+        self.dir.join(format!("{}.crr", hash))
+    }
+}
+
+impl ResultCache for FsCache {
+    fn get(&self, job: &RenderJob) -> Option<RenderResult> {
+        let bytes = std::fs::read(self.path(job.content_hash())).ok()?;
+        RenderResult::decode(&bytes)
+            .ok()
+            .filter(|result| result.job == *job)
+    }
+
+    fn put(&self, result: &RenderResult) {
+        let path = self.path(result.job.content_hash());
+        let mut bytes = vec![];
+        result.encode(&mut bytes);
+        // write and rename, so that readers never see a partial file
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        if std::fs::write(&tmp, bytes).is_err() || std::fs::rename(&tmp, &path).is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Serves jobs from a cache and renders each missing job only once, even if
+/// it is requested again while it is still being rendered. Only requests
+/// through the same `CachedRenderer` wait for each other.
+pub struct CachedRenderer<C> {
+    cache: C,
+    in_flight: Mutex<HashMap<JobHash, Arc<InFlight>>>,
+}
+
+/// A job being rendered, waited on by further requests for it.
+#[derive(Default)]
+struct InFlight {
+    /// `None` while rendering, then the result or `None` if rendering failed.
+    outcome: Mutex<Option<Option<RenderResult>>>,
+    done: Condvar,
+}
+
+impl<C: ResultCache> CachedRenderer<C> {
+    pub fn new(cache: C) -> Self {
+        Self {
+            cache,
+            in_flight: Mutex::default(),
+        }
+    }
+
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// Returns the cached result of `job` or the result of an identical job
+    /// in progress, otherwise calls `render` and caches its result. The flag
+    /// tells whether `render` was skipped.
+    pub fn get_or_render(
+        &self,
+        job: &RenderJob,
+        render: impl FnOnce() -> RenderResult,
+    ) -> (RenderResult, bool) {
+        if let Some(result) = self.cache.get(job) {
+            return (result, true);
+        }
+        let hash = job.content_hash();
+        let (in_flight, leader) = {
+            let mut map = self.in_flight.lock().unwrap();
+            match map.get(&hash) {
+                Some(in_flight) => (in_flight.clone(), false),
+                None => {
+                    let in_flight = Arc::new(InFlight::default());
+                    map.insert(hash, in_flight.clone());
+                    (in_flight, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut outcome = in_flight.outcome.lock().unwrap();
+            while outcome.is_none() {
+                outcome = in_flight.done.wait(outcome).unwrap();
+            }
+            match outcome.as_ref().unwrap() {
+                Some(result) if result.job == *job => return (result.clone(), true),
+                // the leader failed or the hash collided, render it here
+                _ => return (render(), false),
+            }
+        }
+
+        // wakes up waiters even if `render` panics
+        struct Finish<'a, C> {
+            renderer: &'a CachedRenderer<C>,
+            hash: JobHash,
+            in_flight: Arc<InFlight>,
+            result: Option<RenderResult>,
+        }
+        impl<C> Drop for Finish<'_, C> {
+            fn drop(&mut self) {
+                self.renderer.in_flight.lock().unwrap().remove(&self.hash);
+                *self.in_flight.outcome.lock().unwrap() = Some(self.result.take());
+                self.in_flight.done.notify_all();
+            }
+        }
+        let mut finish = Finish {
+            renderer: self,
+            hash,
+            in_flight,
+            result: None,
+        };
+        let result = render();
+        self.cache.put(&result);
+        finish.result = Some(result.clone());
+        (result, false)
+    }
+}
+
+#[test]
+fn identical_jobs_are_rendered_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let renderer = CachedRenderer::new(api::LruCache::new(8));
+    let job = RenderJob::new(0, 0, 4, 4, 4, 4, 1, 1);
+    let renders = AtomicUsize::new(0);
+    let render = || {
+        renders.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        RenderResult::new(job.clone(), vec![1, 2, 3])
+    };
+
+    std::thread::scope(|s| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| s.spawn(|| renderer.get_or_render(&job, render)))
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap().0.image, vec![1, 2, 3]);
+        }
+    });
+    assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+    let (_, cached) = renderer.get_or_render(&job, render);
+    assert!(cached);
+    let (_, cached) = renderer.get_or_render(&job.clone().with_seed(1), || {
+        RenderResult::new(job.clone().with_seed(1), vec![])
+    });
+    assert!(!cached);
+}
+
+#[test]
+fn fs_cache_round_trips() {
+    let dir = std::env::temp_dir().join(format!("clumsy-rt-cache-{}", std::process::id()));
+    let cache = FsCache::new(&dir).unwrap();
+    let job = RenderJob::new(0, 0, 2, 2, 2, 2, 1, 1).with_seed(5);
+    assert_eq!(cache.get(&job), None);
+
+    let result = RenderResult::new(job.clone(), vec![4, 5, 6]).with_worker("test");
+    cache.put(&result);
+    assert_eq!(cache.get(&job), Some(result));
+    assert_eq!(cache.get(&job.with_seed(6)), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1,12 +1,42 @@
 use super::*;
+use crate::filter::Filter;
+use crate::integrator::{Integrator, PathTracer};
+use crate::random;
+use api::ProjectionKind;
 use nalgebra::Vector3;
 use ncollide3d::query::Ray;
+use std::f32::consts::PI;
 use std::sync::Arc;
-use std::thread;
 
 pub const VIEWPORT_S: f32 = 0.5;
 pub const VIEWPORT_WIDTH: f32 = 4.0 * VIEWPORT_S;
 pub const FOCAL_LENGTH: f32 = 1.0;
+/// Seed used unless a different one is set with `Camera::with_seed`.
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Maps pixels to ray directions. All projections look along -z, with y up.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    /// Pinhole camera through the viewport at `FOCAL_LENGTH`.
+    Perspective,
+    /// Parallel rays from a plane of the given width.
+    Orthographic { width: f32 },
+    /// Equidistant fisheye, the angle from the view direction grows linearly
+    /// with the distance from the image center. `fov` is the angle across
+    /// the image width, in radians. Pixels outside the image circle are black.
+    Fisheye { fov: f32 },
+    /// 360° longitude across the image width, 180° latitude across its height.
+    Equirectangular,
+}
+
+/// Receives blocks of finished rows while a tile is being rendered.
+pub trait RowSink {
+    /// Rows passed to each call of `rows`, fewer only for the last block.
+    fn rows_per_block(&self) -> usize;
+    /// `block` holds the rows `y..y + block.h`, counted from the top of the
+    /// tile.
+    fn rows(&mut self, y: usize, block: &PixelPlane);
+}
 
 #[derive(Clone)]
 pub struct Camera {
@@ -19,6 +49,14 @@ pub struct Camera {
     w_samples: usize,
     h_samples: usize,
     n_recursion: usize,
+    seed: u64,
+    integrator: Arc<dyn Integrator>,
+    projection: Projection,
+    /// Exposure interval in frames, relative to the time of the scene,
+    /// within `0.0..=1.0`.
+    shutter_open: f32,
+    shutter_close: f32,
+    filter: Filter,
 }
 impl Camera {
     pub fn new(n_samples: usize, n_recursion: usize, camera_w: usize, camera_h: usize) -> Self {
@@ -39,86 +77,397 @@ impl Camera {
             w_samples,
             h_samples,
             n_recursion,
+            seed: DEFAULT_SEED,
+            integrator: Arc::new(PathTracer),
+            projection: Projection::Perspective,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            filter: Filter::Box,
         }
     }
 
+    /// Use a different seed for all random decisions. Rendering the same
+    /// scene with the same seed always produces the same image.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Compute the color of each sample with a different integrator than
+    /// the default path tracer.
+    pub fn with_integrator(mut self, integrator: Arc<dyn Integrator>) -> Self {
+        self.integrator = integrator;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Keep the shutter open from `open` to `close`, in frames after the
+    /// time of the rendered scene, clamped to a single frame. Each sample
+    /// sees moving objects at a random time in between, which blurs them
+    /// along their motion.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Self {
+        self.shutter_open = open.clamp(0.0, 1.0);
+        self.shutter_close = close.clamp(self.shutter_open, 1.0);
+        self
+    }
+
+    /// Weight samples into pixels with a wider filter than the default box.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Number of samples averaged into each pixel. `n_samples` is rounded
+    /// down to a rectangular grid.
+    pub fn samples_per_pixel(&self) -> usize {
+        self.w_samples * self.h_samples
+    }
+
+    /// Renders the full frame, `buffer` must be of the camera's size.
     pub fn render(&self, scene: Scene, buffer: &mut PixelPlane, n_threads: usize) {
-        let (w, h) = (buffer.w, buffer.h);
-        if n_threads == 1 {
-            let mut shard = buffer.into();
-            self.render_shard(&scene, w, h, &mut shard);
-            *buffer = shard.into();
-            return;
-        }
-        let shards = buffer.split_into_shards(n_threads);
-        let mut handles = vec![];
-        let scene = Arc::new(scene);
-        for mut shard in shards {
-            let camera = self.clone();
-            let scene = scene.clone();
-            let handle = thread::spawn(move || {
-                camera.render_shard(&scene, w, h, &mut shard);
-                shard
-            });
-            handles.push(handle);
-        }
-        let finished_shards = handles
-            .into_iter()
-            .map(|h| h.join().unwrap())
-            .collect::<Vec<_>>();
-        buffer.collect_shards(&finished_shards);
+        debug_assert_eq!((buffer.w, buffer.h), (self.camera_w, self.camera_h));
+        self.render_tile_threaded(&scene, 0, 0, buffer, n_threads);
     }
 
-    fn render_shard(&self, scene: &Scene, w: usize, h: usize, shard: &mut PixelPlaneShard) {
-        for y in 0..shard.h {
-            for x in 0..shard.w {
-                let mut col = Vector3::new(0.0, 0.0, 0.0);
-                for xs in 0..self.w_samples {
-                    for ys in 0..self.h_samples {
-                        let xi = (shard.x + x) as f32 + xs as f32 / self.w_samples as f32;
-                        let yi = (shard.y + y) as f32 + ys as f32 / self.h_samples as f32;
-                        let u = xi / (w - 1) as f32;
-                        let v = yi / (h - 1) as f32;
-                        let ray = self.get_ray(u, v);
-                        let ray_col = scene.cast_ray(&ray, self.n_recursion);
-                        col += ray_col;
+    /// Same as `render_tile`, with the work spread over up to `n_threads`
+    /// threads. The result does not depend on the number of threads.
+    pub fn render_tile_threaded(
+        &self,
+        scene: &Scene,
+        start_x: usize,
+        start_y: usize,
+        out: &mut PixelPlane,
+        n_threads: usize,
+    ) {
+        crate::scheduler::render_blocks(
+            self,
+            scene,
+            start_x,
+            start_y,
+            out,
+            n_threads,
+            &mut |_, _| {},
+        );
+    }
+
+    /// Same as `render_tile_threaded`, passing blocks of finished rows to
+    /// `sink` from the top down while the rest of the tile is rendered. All
+    /// rows are also written to `out`.
+    pub fn render_tile_streaming(
+        &self,
+        scene: &Scene,
+        start_x: usize,
+        start_y: usize,
+        out: &mut PixelPlane,
+        n_threads: usize,
+        sink: &mut dyn RowSink,
+    ) {
+        let rows_per_block = sink.rows_per_block().max(1);
+        let mut sent = 0;
+        let mut rows_done = |done: usize, out: &PixelPlane| {
+            // full blocks only, except for the last one
+            while sent < done && (sent + rows_per_block <= done || done == out.h) {
+                let mut block = PixelPlane::new(out.w, rows_per_block.min(done - sent));
+                for row in 0..block.h {
+                    for x in 0..block.w {
+                        block.set_pixel(x, row, out.pixel(x, sent + row));
                     }
                 }
-                col /= (self.w_samples * self.h_samples) as f32;
-                shard.set_pixel(x, shard.h - 1 - y, Pixel::rgb_vec(col));
+                sink.rows(sent, &block);
+                sent += block.h;
             }
-        }
+        };
+        crate::scheduler::render_blocks(
+            self,
+            scene,
+            start_x,
+            start_y,
+            out,
+            n_threads,
+            &mut rows_done,
+        );
     }
 
     pub fn render_tile(&self, scene: &Scene, start_x: usize, start_y: usize, out: &mut PixelPlane) {
+        if let Filter::Box = self.filter {
+            let mut samples = Vec::with_capacity(self.w_samples * self.h_samples);
+            for y in start_y..start_y + out.h {
+                // mirror y axis, output & screen y is top-down, camera view y is bottom-up
+                let camera_y = self.camera_h - y - 1;
+                for x in start_x..start_x + out.w {
+                    samples.clear();
+                    self.pixel_samples(scene, x, camera_y, &mut samples);
+                    let col = samples.iter().sum::<Vector3<f32>>() / samples.len() as f32;
+                    out.set_pixel(x - start_x, y - start_y, Pixel::rgb_vec(col));
+                }
+            }
+            return;
+        }
+
+        // Samples of all pixels the filter reaches, including the apron
+        // around the tile, clipped to the frame.
+        let apron = self.filter.apron();
+        let n = self.w_samples * self.h_samples;
+        let first_y = self.camera_h - start_y - out.h;
+        let xs = start_x.saturating_sub(apron)..(start_x + out.w + apron).min(self.camera_w);
+        let ys = first_y.saturating_sub(apron)..(first_y + out.h + apron).min(self.camera_h);
+        let stride = xs.len();
+        let mut samples = Vec::with_capacity(xs.len() * ys.len() * n);
+        for camera_y in ys.clone() {
+            for x in xs.clone() {
+                self.pixel_samples(scene, x, camera_y, &mut samples);
+            }
+        }
+
         for y in start_y..start_y + out.h {
-            // mirror y axis, output & screen y is top-down, camera view y is bottom-up
             let camera_y = self.camera_h - y - 1;
             for x in start_x..start_x + out.w {
                 let mut col = Vector3::new(0.0, 0.0, 0.0);
-                for xs in 0..self.w_samples {
-                    for ys in 0..self.h_samples {
-                        let xi = x as f32 + xs as f32 / self.w_samples as f32;
-                        let yi = camera_y as f32 + ys as f32 / self.h_samples as f32;
-                        let u = xi / (self.camera_w - 1) as f32;
-                        let v = yi / (self.camera_h - 1) as f32;
-                        let ray = self.get_ray(u, v);
-                        let ray_col = scene.cast_ray(&ray, self.n_recursion);
-                        col += ray_col;
+                let mut total_weight = 0.0;
+                let near_y = camera_y.saturating_sub(apron).max(ys.start)
+                    ..(camera_y + apron + 1).min(ys.end);
+                let near_x = x.saturating_sub(apron).max(xs.start)..(x + apron + 1).min(xs.end);
+                for py in near_y {
+                    for px in near_x.clone() {
+                        let first = ((py - ys.start) * stride + px - xs.start) * n;
+                        let pixel = &samples[first..first + n];
+                        for sx in 0..self.w_samples {
+                            for sy in 0..self.h_samples {
+                                let dx = px as f32 - x as f32 + subpixel(sx, self.w_samples);
+                                let dy = py as f32 - camera_y as f32 + subpixel(sy, self.h_samples);
+                                let weight = self.filter.weight(dx, dy);
+                                col += weight * pixel[sx * self.h_samples + sy];
+                                total_weight += weight;
+                            }
+                        }
                     }
                 }
-                col /= (self.w_samples * self.h_samples) as f32;
+                let col = (col / total_weight).map(|c| c.max(0.0));
                 out.set_pixel(x - start_x, y - start_y, Pixel::rgb_vec(col));
             }
         }
     }
 
+    /// Appends the samples of one pixel, in camera view coordinates. The
+    /// random generator is seeded for the pixel, hence the result is the same
+    /// for every tile that needs this pixel.
+    fn pixel_samples(&self, scene: &Scene, x: usize, camera_y: usize, out: &mut Vec<Vector3<f32>>) {
+        random::seed_pixel(self.seed, x, camera_y);
+        for xs in 0..self.w_samples {
+            for ys in 0..self.h_samples {
+                let xi = x as f32 + self.sample_offset(xs, self.w_samples);
+                let yi = camera_y as f32 + self.sample_offset(ys, self.h_samples);
+                // cameras one pixel wide or high must not divide by zero
+                let u = xi / (self.camera_w - 1).max(1) as f32;
+                let v = yi / (self.camera_h - 1).max(1) as f32;
+                out.push(self.sample(scene, u, v));
+            }
+        }
+    }
+
+    /// Position of sample `i` of `n` along one axis, relative to the pixel.
+    /// The box filter keeps its original layout starting at the pixel, so
+    /// that its images stay the same. Wider filters weight samples by their
+    /// offset from the pixel center, hence their samples are centered.
+    fn sample_offset(&self, i: usize, n: usize) -> f32 {
+        match self.filter {
+            Filter::Box => i as f32 / n as f32,
+            _ => subpixel(i, n),
+        }
+    }
+
+    /// Color of a single sample at the given real pixel coordinates.
+    fn sample(&self, scene: &Scene, u: f32, v: f32) -> Vector3<f32> {
+        let ray = match self.get_ray(u, v) {
+            Some(ray) => ray,
+            None => return Vector3::new(0.0, 0.0, 0.0),
+        };
+        let offset = if self.shutter_close > self.shutter_open {
+            let exposure = self.shutter_close - self.shutter_open;
+            self.shutter_open + random::unit() * exposure
+        } else {
+            0.0
+        };
+        let scene = scene.snapshot(offset);
+        self.integrator
+            .radiance(&scene, &scene.view_ray(&ray), self.n_recursion)
+    }
+
     /// Computes a ray through the viewport with the given real pixel coordinates (ranging from 0.0 to 1.0).
-    fn get_ray(&self, u: f32, v: f32) -> Ray<f32> {
-        Ray::new(
-            self.origin.into(),
-            self.lower_left_corner + u * self.view_port_horizontal + v * self.view_port_vertical
-                - self.origin,
-        )
+    /// Returns `None` for pixels that the projection does not cover.
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray<f32>> {
+        let aspect = self.camera_h as f32 / self.camera_w as f32;
+        let ray = match self.projection {
+            Projection::Perspective => Ray::new(
+                self.origin.into(),
+                self.lower_left_corner
+                    + u * self.view_port_horizontal
+                    + v * self.view_port_vertical
+                    - self.origin,
+            ),
+            Projection::Orthographic { width } => {
+                let offset = Vector3::new((u - 0.5) * width, (v - 0.5) * width * aspect, 0.0);
+                Ray::new((self.origin + offset).into(), -Vector3::z() * FOCAL_LENGTH)
+            }
+            Projection::Fisheye { fov } => {
+                let x = 2.0 * u - 1.0;
+                let y = (2.0 * v - 1.0) * aspect;
+                let radius = (x * x + y * y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let theta = radius * fov / 2.0;
+                let phi = y.atan2(x);
+                let dir = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                Ray::new(self.origin.into(), dir)
+            }
+            Projection::Equirectangular => {
+                let longitude = (u - 0.5) * 2.0 * PI;
+                let latitude = (v - 0.5) * PI;
+                let dir = Vector3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                Ray::new(self.origin.into(), dir)
+            }
+        };
+        Some(ray)
+    }
+}
+
+impl From<ProjectionKind> for Projection {
+    /// Default parameters for projections selected by a render job.
+    fn from(kind: ProjectionKind) -> Self {
+        match kind {
+            ProjectionKind::Perspective => Projection::Perspective,
+            // same size as the perspective view at the center of the sample scenes
+            ProjectionKind::Orthographic => Projection::Orthographic {
+                width: 5.0 * VIEWPORT_WIDTH * VIEWPORT_WIDTH / FOCAL_LENGTH,
+            },
+            ProjectionKind::Fisheye => Projection::Fisheye { fov: PI },
+            ProjectionKind::Equirectangular => Projection::Equirectangular,
+        }
+    }
+}
+
+/// Offset of sample `i` of `n` along one axis from the pixel center, in
+/// pixels. Samples are spread evenly and symmetrically around the center,
+/// where a single sample lands, and wide filters weight them by this offset.
+fn subpixel(i: usize, n: usize) -> f32 {
+    (i as f32 + 0.5) / n as f32 - 0.5
+}
+
+#[test]
+fn motion_blur_is_independent_of_tiling() {
+    let scene = sample_scenes::build_animated_cool_scene().at_time(10.0);
+    let camera = Camera::new(4, 2, 32, 24)
+        .with_seed(35)
+        .with_shutter(0.0, 1.0);
+    let mut whole = PixelPlane::new(32, 24);
+    camera.render_tile(&scene, 0, 0, &mut whole);
+    for (x, y) in [(0, 0), (16, 0), (0, 12), (16, 12)] {
+        let mut tile = PixelPlane::new(16, 12);
+        camera.render_tile(&scene, x, y, &mut tile);
+        for ty in 0..12 {
+            for tx in 0..16 {
+                assert_eq!(tile.pixel(tx, ty).col, whole.pixel(x + tx, y + ty).col);
+            }
+        }
+    }
+}
+
+#[test]
+fn projections_look_forward_at_the_center() {
+    let forward = -Vector3::z();
+    for projection in [
+        Projection::Perspective,
+        Projection::Orthographic { width: 4.0 },
+        Projection::Fisheye { fov: PI },
+        Projection::Equirectangular,
+    ] {
+        let camera = Camera::new(1, 1, 201, 101).with_projection(projection);
+        let ray = camera.get_ray(0.5, 0.5).unwrap();
+        assert!(
+            (ray.dir.normalize() - forward).norm() < 1e-5,
+            "{projection:?} center was {}",
+            ray.dir
+        );
+    }
+}
+
+#[test]
+fn wide_projections_cover_the_sphere() {
+    let fisheye = Camera::new(1, 1, 100, 100).with_projection(Projection::Fisheye { fov: PI });
+    // image border at 90° from the view direction, corners outside the circle
+    let right = fisheye.get_ray(1.0, 0.5).unwrap();
+    assert!((right.dir - Vector3::x()).norm() < 1e-5);
+    assert!(fisheye.get_ray(1.0, 1.0).is_none());
+
+    let panorama = Camera::new(1, 1, 200, 100).with_projection(Projection::Equirectangular);
+    let behind = panorama.get_ray(0.0, 0.5).unwrap();
+    assert!((behind.dir - Vector3::z()).norm() < 1e-5);
+    let up = panorama.get_ray(0.5, 1.0).unwrap();
+    assert!((up.dir - Vector3::y()).norm() < 1e-5);
+    let left = panorama.get_ray(0.25, 0.5).unwrap();
+    assert!((left.dir + Vector3::x()).norm() < 1e-5);
+}
+
+#[test]
+fn symmetric_filters_keep_symmetric_scenes_symmetric() {
+    use crate::integrator::Depth;
+    use nalgebra::Isometry3;
+    use ncollide3d::shape::Ball;
+
+    let mut scene = SceneBuilder::new(100.0, |_| Vector3::zeros());
+    scene.add(
+        Ball::new(1.0),
+        Isometry3::translation(0.0, 0.0, -3.0),
+        Texture::perfect_mirror(),
+    );
+    let scene = scene.build();
+    let (w, h) = (15, 11);
+    // the box filter's samples start at the pixel instead of around its
+    // center, which shifts the image by less than a pixel
+    for filter in [
+        Filter::Tent { radius: 1.0 },
+        Filter::from(api::FilterKind::Mitchell),
+    ] {
+        let camera = Camera::new(4, 1, w, h)
+            .with_integrator(Arc::new(Depth { scale: 4.0 }))
+            .with_filter(filter);
+        let mut img = PixelPlane::new(w, h);
+        camera.render_tile(&scene, 0, 0, &mut img);
+        let close = |a: Pixel, b: Pixel| {
+            let diff = a.col.cast::<i16>() - b.col.cast::<i16>();
+            diff.iter().all(|d| d.abs() <= 1)
+        };
+        for y in 0..h {
+            for x in 0..w {
+                let pixel = img.pixel(x, y);
+                assert!(
+                    close(pixel, img.pixel(w - 1 - x, y)),
+                    "{:?} at ({}, {})",
+                    filter,
+                    x,
+                    y
+                );
+                assert!(
+                    close(pixel, img.pixel(x, h - 1 - y)),
+                    "{:?} at ({}, {})",
+                    filter,
+                    x,
+                    y
+                );
+            }
+        }
     }
 }
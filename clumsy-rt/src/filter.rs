@@ -0,0 +1,121 @@
+//! Pixel reconstruction filters.
+//!
+//! A filter decides how much each sample contributes to the pixels around it,
+//! by its offset from the pixel center. Wider filters than the box reduce
+//! aliasing, but a pixel then depends on samples of its neighbors. Samples
+//! are seeded per pixel, so a tile can recompute the samples of its border
+//! (the apron) and still produce exactly the pixels of a full-frame render.
+
+use api::FilterKind;
+
+/// Separable filter, offsets and radii are in pixels.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Filter {
+    /// Average of the samples inside the pixel, the default.
+    #[default]
+    Box,
+    /// Weight falls off linearly to zero at `radius`.
+    Tent { radius: f32 },
+    /// Gaussian with falloff `alpha`, shifted to reach zero at `radius`.
+    Gaussian { radius: f32, alpha: f32 },
+    /// Mitchell-Netravali cubic with parameters `b` and `c`, stretched to
+    /// `radius`. Sharpens slightly, with small negative lobes.
+    Mitchell { radius: f32, b: f32, c: f32 },
+}
+
+impl Filter {
+    /// Samples further away from the pixel center than this have no weight.
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    /// Neighboring pixels on each side whose samples can contribute.
+    pub fn apron(&self) -> usize {
+        (self.radius() - 0.5).max(0.0).ceil() as usize
+    }
+
+    /// Weight of a sample at offset `(dx, dy)` from the pixel center.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        match *self {
+            Filter::Box => {
+                if d <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (1.0 - d / radius).max(0.0),
+            Filter::Gaussian { radius, alpha } => {
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                // the cubic is defined on [-2, 2]
+                let x = 2.0 * d / radius;
+                let (x2, x3) = (x * x, x * x * x);
+                let w = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else if x < 2.0 {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                } else {
+                    0.0
+                };
+                w / 6.0
+            }
+        }
+    }
+}
+
+impl From<FilterKind> for Filter {
+    /// Commonly used parameters for filters selected by a render job.
+    fn from(kind: FilterKind) -> Self {
+        match kind {
+            FilterKind::Box => Filter::Box,
+            FilterKind::Tent => Filter::Tent { radius: 1.0 },
+            FilterKind::Gaussian => Filter::Gaussian {
+                radius: 1.5,
+                alpha: 2.0,
+            },
+            FilterKind::Mitchell => Filter::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+        }
+    }
+}
+
+#[test]
+fn filters_peak_at_the_center_and_vanish_at_the_radius() {
+    for kind in [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+    ] {
+        let filter = Filter::from(kind);
+        let r = filter.radius();
+        let center = filter.weight(0.0, 0.0);
+        assert!(center > 0.0, "{:?}", filter);
+        assert!(filter.weight(0.3, 0.1) <= center, "{:?}", filter);
+        assert_eq!(filter.weight(r + 0.01, 0.0), 0.0, "{:?}", filter);
+        assert_eq!(filter.weight(0.0, -r - 0.01), 0.0, "{:?}", filter);
+    }
+    assert_eq!(Filter::Box.apron(), 0);
+    assert_eq!(Filter::Tent { radius: 1.0 }.apron(), 1);
+    assert_eq!(Filter::from(FilterKind::Mitchell).apron(), 2);
+}
@@ -0,0 +1,370 @@
+//! Hierarchical scene graph.
+//!
+//! A group holds objects and instances of other groups. Instances only store
+//! a reference to the shared group and a transformation, so a prototype that
+//! is placed many times exists only once in memory. Rays are intersected with
+//! an instance by transforming them into the instance's local space.
+
+use std::sync::Arc;
+
+use crate::animation::{Time, Track};
+use crate::bvh::{Aabb, Bvh};
+use crate::texture::Texture;
+use nalgebra::geometry::*;
+use nalgebra::Vector3;
+use ncollide3d::query::*;
+use ncollide3d::shape::*;
+
+const EPSILON: f32 = f32::EPSILON;
+
+/// A shape placed in the scene.
+pub struct SceneObject {
+    pub shape: ShapeHandle<f32>,
+    pub position: Isometry3<f32>,
+    pub texture: Texture,
+}
+
+/// Closest intersection of a ray with the scene.
+pub struct Hit<'a> {
+    /// Distance along the ray, in multiples of the ray direction.
+    pub toi: f32,
+    pub normal: Vector3<f32>,
+    pub texture: &'a Texture,
+}
+
+/// Collects objects and instances to form a `Prototype`.
+#[derive(Default)]
+pub struct GroupBuilder {
+    objects: Vec<SceneObject>,
+    instances: Vec<Instance>,
+}
+
+/// A finished group that can be instanced any number of times, also inside
+/// other groups. Cloning is cheap, all clones share the same data.
+#[derive(Clone)]
+pub struct Prototype(Arc<Group>);
+
+pub(crate) struct Group {
+    objects: Vec<SceneObject>,
+    instances: Vec<Instance>,
+    /// Objects come first, then instances, in the primitive indexing of the BVH.
+    bvh: Bvh,
+    bounds: Aabb,
+}
+
+struct Instance {
+    group: Arc<Group>,
+    placement: Placement,
+}
+
+enum Placement {
+    Static {
+        /// Local to parent space.
+        transform: Similarity3<f32>,
+        /// Parent to local space.
+        inverse: Similarity3<f32>,
+    },
+    /// Local to parent space, changing over time.
+    Animated(Track),
+    /// At `transform` at the start of each frame, then moving by `velocity`
+    /// per frame while the shutter is open.
+    Moving {
+        transform: Similarity3<f32>,
+        velocity: Vector3<f32>,
+    },
+}
+
+impl GroupBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, obj: impl Shape<f32>, position: Isometry3<f32>, texture: Texture) {
+        self.objects.push(SceneObject {
+            shape: ShapeHandle::new(obj),
+            position,
+            texture,
+        });
+    }
+
+    /// Places a copy of `prototype`. The transformation may translate, rotate
+    /// and scale uniformly.
+    pub fn add_instance(&mut self, prototype: &Prototype, transform: Similarity3<f32>) {
+        self.instances.push(Instance {
+            group: prototype.0.clone(),
+            placement: Placement::Static {
+                transform,
+                inverse: transform.inverse(),
+            },
+        });
+    }
+
+    /// Places a copy of `prototype` that moves along `track`.
+    pub fn add_animated_instance(&mut self, prototype: &Prototype, track: Track) {
+        self.instances.push(Instance {
+            group: prototype.0.clone(),
+            placement: Placement::Animated(track),
+        });
+    }
+
+    /// Adds a single object that is at `position` at the start of every
+    /// frame and moves with `velocity` per frame. The motion only shows as
+    /// blur while the camera shutter is open, use `add_animated` for motion
+    /// across frames.
+    pub fn add_moving(
+        &mut self,
+        obj: impl Shape<f32>,
+        position: Isometry3<f32>,
+        velocity: Vector3<f32>,
+        texture: Texture,
+    ) {
+        let mut single = GroupBuilder::new();
+        single.add(obj, Isometry3::identity(), texture);
+        self.instances.push(Instance {
+            group: Arc::new(single.build_group()),
+            placement: Placement::Moving {
+                transform: Similarity3::from_isometry(position, 1.0),
+                velocity,
+            },
+        });
+    }
+
+    /// Adds a single object that moves along `track`.
+    pub fn add_animated(&mut self, obj: impl Shape<f32>, track: Track, texture: Texture) {
+        let mut single = GroupBuilder::new();
+        single.add(obj, Isometry3::identity(), texture);
+        self.add_animated_instance(&single.build(), track);
+    }
+
+    pub fn build(self) -> Prototype {
+        Prototype(Arc::new(self.build_group()))
+    }
+
+    pub(crate) fn build_group(self) -> Group {
+        let bounds: Vec<Aabb> = self
+            .objects
+            .iter()
+            .map(|obj| obj.shape.aabb(&obj.position).into())
+            .chain(self.instances.iter().map(|instance| instance.bounds()))
+            .collect();
+        let total = bounds.iter().fold(Aabb::empty(), |acc, b| acc.merged(b));
+        Group {
+            bvh: Bvh::build(&bounds),
+            bounds: total,
+            objects: self.objects,
+            instances: self.instances,
+        }
+    }
+}
+
+impl Group {
+    pub(crate) fn closest_hit(&self, ray: &Ray<f32>, max_toi: f32, time: Time) -> Option<Hit<'_>> {
+        let num_objects = self.objects.len();
+        self.bvh
+            .closest_hit(ray, max_toi, |i, max_toi| {
+                let hit = if i < num_objects {
+                    let obj = &self.objects[i];
+                    let intersection = obj
+                        .shape
+                        .as_ray_cast()?
+                        .toi_and_normal_with_ray(&obj.position, ray, max_toi, true)
+                        .filter(|intersection| intersection.toi > EPSILON)?;
+                    Hit {
+                        toi: intersection.toi,
+                        normal: intersection.normal,
+                        texture: &obj.texture,
+                    }
+                } else {
+                    let instance = &self.instances[i - num_objects];
+                    let (transform, inverse) = instance.transforms(time);
+                    let local_ray = to_local(&inverse, ray);
+                    let hit = instance.group.closest_hit(&local_ray, max_toi, time)?;
+                    Hit {
+                        normal: transform.isometry.rotation * hit.normal,
+                        ..hit
+                    }
+                };
+                Some((hit.toi, hit))
+            })
+            .map(|(_toi, hit)| hit)
+    }
+
+    pub(crate) fn occluded(&self, ray: &Ray<f32>, max_toi: f32, time: Time) -> bool {
+        let num_objects = self.objects.len();
+        self.bvh.any_hit(ray, max_toi, |i, max_toi| {
+            if i < num_objects {
+                let obj = &self.objects[i];
+                obj.shape
+                    .as_ray_cast()
+                    .and_then(|shape| shape.toi_with_ray(&obj.position, ray, max_toi, true))
+                    .is_some_and(|toi| toi > EPSILON)
+            } else {
+                let instance = &self.instances[i - num_objects];
+                let (_, inverse) = instance.transforms(time);
+                let local_ray = to_local(&inverse, ray);
+                instance.group.occluded(&local_ray, max_toi, time)
+            }
+        })
+    }
+
+    /// Visits all objects, including those inside instances, together with
+    /// their transformation to the space of this group at the given time.
+    pub(crate) fn for_each_object(
+        &self,
+        to_parent: &Similarity3<f32>,
+        time: Time,
+        f: &mut impl FnMut(&SceneObject, &Similarity3<f32>),
+    ) {
+        for obj in &self.objects {
+            f(
+                obj,
+                &(to_parent * Similarity3::from_isometry(obj.position, 1.0)),
+            );
+        }
+        for instance in &self.instances {
+            instance
+                .group
+                .for_each_object(&(to_parent * instance.transforms(time).0), time, f);
+        }
+    }
+}
+
+impl Instance {
+    /// Local to parent and parent to local space at the given time.
+    fn transforms(&self, time: Time) -> (Similarity3<f32>, Similarity3<f32>) {
+        match &self.placement {
+            Placement::Static { transform, inverse } => (*transform, *inverse),
+            Placement::Animated(track) => {
+                let transform = track.sample(time.frames());
+                (transform, transform.inverse())
+            }
+            Placement::Moving {
+                transform,
+                velocity,
+            } => {
+                let shift = Translation3::from(velocity * time.offset);
+                let transform = shift * *transform;
+                (transform, transform.inverse())
+            }
+        }
+    }
+
+    /// Bounds in parent space, covering all times for animated instances.
+    fn bounds(&self) -> Aabb {
+        match &self.placement {
+            Placement::Static { transform, .. } => self.group.bounds.transformed(transform),
+            Placement::Animated(track) => track.sweep(&self.group.bounds),
+            Placement::Moving {
+                transform,
+                velocity,
+            } => {
+                let end = Translation3::from(*velocity) * *transform;
+                self.group
+                    .bounds
+                    .transformed(transform)
+                    .merged(&self.group.bounds.transformed(&end))
+            }
+        }
+    }
+}
+
+/// Transforms origin and direction with the same linear map, which keeps the
+/// time of impact valid in both spaces.
+fn to_local(inverse: &Similarity3<f32>, ray: &Ray<f32>) -> Ray<f32> {
+    Ray::new(
+        inverse.transform_point(&ray.origin),
+        inverse.transform_vector(&ray.dir),
+    )
+}
+
+#[test]
+fn nested_instances_are_intersected_in_local_space() {
+    let no_rotation = UnitQuaternion::identity();
+    let mut unit_ball = GroupBuilder::new();
+    unit_ball.add(
+        Ball::new(1.0),
+        Isometry3::identity(),
+        Texture::perfect_mirror(),
+    );
+    let unit_ball = unit_ball.build();
+
+    let mut pair = GroupBuilder::new();
+    for x in [-2.0, 2.0] {
+        let tran = Translation3::new(x, 0.0, 0.0);
+        pair.add_instance(&unit_ball, Similarity3::from_parts(tran, no_rotation, 1.0));
+    }
+    let pair = pair.build();
+
+    let mut root = GroupBuilder::new();
+    let tran = Translation3::new(0.0, 0.0, -10.0);
+    root.add_instance(&pair, Similarity3::from_parts(tran, no_rotation, 2.0));
+    let root = root.build_group();
+
+    // balls of radius 2, centered at x = -4 and x = 4 with z = -10
+    let ray = Ray::new(Point3::new(4.0, 0.0, 0.0), -Vector3::z());
+    let hit = root
+        .closest_hit(&ray, 100.0, Time::at_frame(0.0))
+        .expect("must hit right ball");
+    assert!((hit.toi - 8.0).abs() < 1e-4, "toi was {}", hit.toi);
+    assert!((hit.normal - Vector3::z()).norm() < 1e-4);
+    assert!(root.occluded(&ray, 100.0, Time::at_frame(0.0)));
+    assert!(!root.occluded(&ray, 7.0, Time::at_frame(0.0)));
+
+    let between = Ray::new(Point3::origin(), -Vector3::z());
+    assert!(root
+        .closest_hit(&between, 100.0, Time::at_frame(0.0))
+        .is_none());
+}
+
+#[test]
+fn animated_objects_are_intersected_at_the_given_time() {
+    use crate::animation::Interpolation;
+    let at_x = |x: f32| {
+        Similarity3::from_parts(
+            Translation3::new(x, 0.0, -10.0),
+            UnitQuaternion::identity(),
+            1.0,
+        )
+    };
+    let track = Track::new(Interpolation::Linear)
+        .key(0.0, at_x(0.0))
+        .key(10.0, at_x(5.0));
+    let mut root = GroupBuilder::new();
+    root.add_animated(Ball::new(1.0), track, Texture::perfect_mirror());
+    let root = root.build_group();
+
+    let ray = Ray::new(Point3::new(5.0, 0.0, 0.0), -Vector3::z());
+    assert!(root.closest_hit(&ray, 100.0, Time::at_frame(0.0)).is_none());
+    assert!(!root.occluded(&ray, 100.0, Time::at_frame(2.0)));
+    let hit = root
+        .closest_hit(&ray, 100.0, Time::at_frame(10.0))
+        .expect("ball arrived");
+    assert!((hit.toi - 9.0).abs() < 1e-4, "toi was {}", hit.toi);
+    assert!(root.occluded(&ray, 100.0, Time::at_frame(9.5)));
+}
+
+#[test]
+fn moving_objects_move_continuously_while_the_shutter_is_open() {
+    let mut root = GroupBuilder::new();
+    let start = Isometry3::translation(0.0, 0.0, -10.0);
+    root.add_moving(
+        Ball::new(1.0),
+        start,
+        Vector3::new(4.0, 0.0, 0.0),
+        Texture::perfect_mirror(),
+    );
+    let root = root.build_group();
+
+    let ray = Ray::new(Point3::new(3.0, 0.0, 0.0), -Vector3::z());
+    let during = |offset: f32| Time { frame: 7.0, offset };
+    assert!(root.closest_hit(&ray, 100.0, during(0.0)).is_none());
+    assert!(root.closest_hit(&ray, 100.0, during(0.75)).is_some());
+    // still moving at the end of an exposure of the whole frame
+    let end = Ray::new(Point3::new(4.0, 0.0, 0.0), -Vector3::z());
+    let hit = root
+        .closest_hit(&end, 100.0, during(1.0))
+        .expect("ball arrived");
+    assert!((hit.toi - 9.0).abs() < 1e-4, "toi was {}", hit.toi);
+    // every frame starts at the same place
+    assert!(!root.occluded(&ray, 100.0, Time::at_frame(8.0)));
+}
@@ -0,0 +1,199 @@
+//! Strategies to compute the color seen along a camera ray.
+//!
+//! The path tracer produces the final image. The others trade accuracy for
+//! speed, or visualize properties of the scene for debugging.
+
+use std::sync::Arc;
+
+use crate::reflection::*;
+use crate::Snapshot;
+use api::IntegratorKind;
+use nalgebra::Vector3;
+use ncollide3d::query::Ray;
+
+pub trait Integrator: Send + Sync {
+    /// Light arriving along `ray`, with at most `depth` bounces.
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, depth: usize) -> Vector3<f32>;
+}
+
+/// Recursive Monte Carlo path tracing, as implemented by `Snapshot::cast_ray`.
+pub struct PathTracer;
+
+/// Direct light from analytic lights plus unoccluded sky light along the
+/// normal on diffuse surfaces, recursion only for mirrors. Noise free, but
+/// without indirect light. Ignores participating media.
+pub struct Whitted;
+
+/// Fraction of the hemisphere above the first hit that is free of other
+/// objects within `radius`.
+pub struct AmbientOcclusion {
+    pub radius: f32,
+    pub samples: usize,
+}
+
+/// Surface normals in world space, mapped from [-1, 1] to [0, 1] per axis.
+pub struct Normals;
+
+/// Distance to the first hit, bright when close and fading to black.
+pub struct Depth {
+    /// Distance at which the brightness has dropped to about 37%.
+    pub scale: f32,
+}
+
+/// Number of bounces of each path tracing sample, from blue (none) over green
+/// to red (maximum depth).
+pub struct Bounces;
+
+/// Creates the integrator selected in a render job.
+pub fn build_integrator(kind: IntegratorKind) -> Arc<dyn Integrator> {
+    match kind {
+        IntegratorKind::PathTracer => Arc::new(PathTracer),
+        IntegratorKind::Whitted => Arc::new(Whitted),
+        IntegratorKind::AmbientOcclusion => Arc::new(AmbientOcclusion::default()),
+        IntegratorKind::Normals => Arc::new(Normals),
+        IntegratorKind::Depth => Arc::new(Depth::default()),
+        IntegratorKind::Bounces => Arc::new(Bounces),
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, depth: usize) -> Vector3<f32> {
+        scene.cast_ray(ray, depth)
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, depth: usize) -> Vector3<f32> {
+        if depth == 0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let hit = match scene.closest_hit(ray, scene.max_distance()) {
+            Some(hit) => hit,
+            None => return scene.background(ray),
+        };
+        let texture = hit.texture;
+        let point_of_impact = ray.point_at(hit.toi);
+        let normal = hit.normal.normalize();
+        let light_in = match texture.reflection_type {
+            ReflectionType::Lambert => {
+                let up = Ray::new(point_of_impact, normal);
+                let sky = if scene.occluded(&up, scene.max_distance()) {
+                    Vector3::new(0.0, 0.0, 0.0)
+                } else {
+                    scene.background(&up)
+                };
+                sky + scene.direct_light(&point_of_impact, &normal)
+            }
+            ReflectionType::Metal => {
+                let new_ray = mirror_reflection(&ray.dir, &point_of_impact, &normal);
+                self.radiance(scene, &new_ray, depth - 1)
+            }
+            ReflectionType::Absorb => Vector3::new(0.0, 0.0, 0.0),
+            ReflectionType::LightSource => texture.color(),
+        };
+        texture.shade(light_in)
+    }
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            radius: 1.0,
+            samples: 4,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, _depth: usize) -> Vector3<f32> {
+        let hit = match scene.closest_hit(ray, scene.max_distance()) {
+            Some(hit) => hit,
+            None => return Vector3::new(1.0, 1.0, 1.0),
+        };
+        let point_of_impact = ray.point_at(hit.toi);
+        let normal = hit.normal.normalize();
+        let open = (0..self.samples)
+            .filter(|_| {
+                let probe = lambertian_reflection(&point_of_impact, &normal);
+                !scene.occluded(&probe, self.radius / probe.dir.norm())
+            })
+            .count();
+        let v = open as f32 / self.samples.max(1) as f32;
+        Vector3::new(v, v, v)
+    }
+}
+
+impl Integrator for Normals {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, _depth: usize) -> Vector3<f32> {
+        match scene.closest_hit(ray, scene.max_distance()) {
+            Some(hit) => (hit.normal.normalize() + Vector3::new(1.0, 1.0, 1.0)) / 2.0,
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self { scale: 10.0 }
+    }
+}
+
+impl Integrator for Depth {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, _depth: usize) -> Vector3<f32> {
+        match scene.closest_hit(ray, scene.max_distance()) {
+            Some(hit) => {
+                let distance = hit.toi * ray.dir.norm();
+                let v = (-distance / self.scale).exp();
+                Vector3::new(v, v, v)
+            }
+            None => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Integrator for Bounces {
+    fn radiance(&self, scene: &Snapshot<'_>, ray: &Ray<f32>, depth: usize) -> Vector3<f32> {
+        let mut bounces = 0;
+        scene.trace(ray, depth, &mut bounces);
+        let t = bounces as f32 / depth.max(1) as f32;
+        if t < 0.5 {
+            Vector3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+        } else {
+            Vector3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+        }
+    }
+}
+
+#[test]
+fn debug_views_of_a_single_ball() {
+    use crate::{SceneBuilder, Texture};
+    use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion};
+    use ncollide3d::shape::Ball;
+
+    let mut scene = SceneBuilder::new(100.0, |_| Vector3::new(0.0, 0.0, 0.0));
+    let position = Isometry3::from_parts(
+        Translation3::new(0.0, 0.0, -5.0),
+        UnitQuaternion::identity(),
+    );
+    scene.add(
+        Ball::new(1.0),
+        position,
+        Texture::perfect_diffuse(Vector3::new(1.0, 1.0, 1.0)),
+    );
+    let scene = scene.build();
+    let scene = scene.snapshot(0.0);
+
+    let towards_ball = Ray::new(Point3::origin(), -Vector3::z());
+    let normal = Normals.radiance(&scene, &towards_ball, 1);
+    assert!((normal - Vector3::new(0.5, 0.5, 1.0)).norm() < 1e-4);
+
+    let depth = Depth { scale: 4.0 }.radiance(&scene, &towards_ball, 1);
+    assert!((depth.x - (-1.0f32).exp()).abs() < 1e-4);
+
+    // nothing but the ball itself, which never occludes its own front
+    let ao = AmbientOcclusion::default().radiance(&scene, &towards_ball, 1);
+    assert_eq!(ao, Vector3::new(1.0, 1.0, 1.0));
+
+    let past_ball = Ray::new(Point3::origin(), Vector3::z());
+    assert_eq!(Normals.radiance(&scene, &past_ball, 1), Vector3::zeros());
+}
@@ -1,20 +1,41 @@
 //! Simple CPU ray-tracer, based on and inspired by https://github.com/RayTracing/raytracing.github.io
 
+mod animation;
+mod bvh;
+mod cache;
 mod camera;
+mod filter;
+mod group;
+mod integrator;
+mod light;
+mod medium;
 mod output;
 mod pixel;
+mod random;
 mod reflection;
 mod render_job;
 mod scene;
+mod scheduler;
+mod stitch;
 mod texture;
 
 pub mod sample_scenes;
 
+pub use animation::{Interpolation, Keyframe, Time, Track};
+pub use cache::{CachedRenderer, FsCache};
 pub use camera::*;
+pub use filter::Filter;
+pub use group::{GroupBuilder, Hit, Prototype, SceneObject};
+pub use integrator::{
+    build_integrator, AmbientOcclusion, Bounces, Depth, Integrator, Normals, PathTracer, Whitted,
+};
+pub use light::{Light, LightSample};
+pub use medium::{Fog, PhaseFunction, Volume};
 pub use pixel::*;
 pub use reflection::*;
 pub use render_job::RenderJobExt;
 pub use scene::*;
+pub use stitch::{stitch, Region, StitchError, Stitcher};
 pub use texture::*;
 
 #[cfg(feature = "web")]
@@ -0,0 +1,160 @@
+//! Lights without a surface.
+//!
+//! Random bounces can never hit a light of zero area, hence these lights are
+//! evaluated explicitly at each diffuse surface hit, with a shadow ray to
+//! check that nothing blocks the way.
+
+use nalgebra::{Point3, Vector3};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    /// Shines equally in all directions, falling off with the squared distance.
+    Point {
+        position: Point3<f32>,
+        intensity: Vector3<f32>,
+    },
+    /// A point light limited to a cone, fading out between the inner and the
+    /// outer cone angle.
+    Spot {
+        position: Point3<f32>,
+        /// Unit vector along the center of the cone.
+        direction: Vector3<f32>,
+        intensity: Vector3<f32>,
+        cos_inner: f32,
+        cos_outer: f32,
+    },
+    /// Infinitely far away light, such as the sun. Constant everywhere.
+    Directional {
+        /// Unit vector in the direction the light travels.
+        direction: Vector3<f32>,
+        irradiance: Vector3<f32>,
+    },
+}
+
+/// Light arriving at a point in the scene, ignoring occlusion.
+pub struct LightSample {
+    /// Unit vector from the illuminated point towards the light.
+    pub to_light: Vector3<f32>,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    /// Irradiance on a surface facing the light.
+    pub irradiance: Vector3<f32>,
+}
+
+impl Light {
+    pub fn point(position: Point3<f32>, intensity: Vector3<f32>) -> Self {
+        Light::Point {
+            position,
+            intensity,
+        }
+    }
+
+    /// Spot light, cone angles are in radians, measured from the center.
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        intensity: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+        }
+    }
+
+    pub fn directional(direction: Vector3<f32>, irradiance: Vector3<f32>) -> Self {
+        Light::Directional {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+
+    pub fn illuminate(&self, point: &Point3<f32>) -> Option<LightSample> {
+        match *self {
+            Light::Point {
+                position,
+                intensity,
+            } => Some(inverse_square(&position, point, intensity)),
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let sample = inverse_square(&position, point, intensity);
+                let cos = (-sample.to_light).dot(&direction);
+                if cos <= cos_outer {
+                    return None;
+                }
+                let t = ((cos - cos_outer) / (cos_inner - cos_outer).max(f32::EPSILON)).min(1.0);
+                let falloff = t * t * (3.0 - 2.0 * t);
+                Some(LightSample {
+                    irradiance: sample.irradiance * falloff,
+                    ..sample
+                })
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => Some(LightSample {
+                to_light: -direction,
+                distance: f32::INFINITY,
+                irradiance,
+            }),
+        }
+    }
+}
+
+fn inverse_square(
+    light: &Point3<f32>,
+    point: &Point3<f32>,
+    intensity: Vector3<f32>,
+) -> LightSample {
+    let offset = light - point;
+    let distance_squared = offset.norm_squared().max(f32::EPSILON);
+    let distance = distance_squared.sqrt();
+    LightSample {
+        to_light: offset / distance,
+        distance,
+        irradiance: intensity / distance_squared,
+    }
+}
+
+#[test]
+fn point_light_falls_off_with_inverse_square() {
+    let light = Light::point(Point3::origin(), Vector3::new(4.0, 8.0, 16.0));
+    let at = |d: f32| light.illuminate(&Point3::new(0.0, d, 0.0)).unwrap();
+
+    assert!((at(1.0).irradiance - Vector3::new(4.0, 8.0, 16.0)).norm() < 1e-5);
+    assert!((at(2.0).irradiance - Vector3::new(1.0, 2.0, 4.0)).norm() < 1e-5);
+    assert!((at(4.0).irradiance - Vector3::new(0.25, 0.5, 1.0)).norm() < 1e-5);
+    assert!((at(4.0).distance - 4.0).abs() < 1e-5);
+    assert!((at(4.0).to_light - Vector3::new(0.0, -1.0, 0.0)).norm() < 1e-5);
+}
+
+#[test]
+fn spot_light_is_limited_to_its_cone() {
+    use std::f32::consts::FRAC_PI_8;
+    let down = Vector3::new(0.0, -1.0, 0.0);
+    let light = Light::spot(
+        Point3::new(0.0, 2.0, 0.0),
+        down,
+        Vector3::new(4.0, 4.0, 4.0),
+        FRAC_PI_8,
+        2.0 * FRAC_PI_8,
+    );
+    // straight below, full intensity with inverse square falloff
+    let center = light.illuminate(&Point3::origin()).unwrap();
+    assert!((center.irradiance - Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-5);
+    // at 45°, outside of the outer cone
+    assert!(light.illuminate(&Point3::new(2.0, 0.0, 0.0)).is_none());
+    // between inner and outer cone, dimmer than plain inverse square
+    let edge = light.illuminate(&Point3::new(1.3, 0.0, 0.0)).unwrap();
+    assert!(edge.irradiance.x > 0.0);
+    assert!(edge.irradiance.x < 4.0 / (4.0 + 1.3 * 1.3));
+}
@@ -1,9 +1,23 @@
+use api::{
+    FilterKind, IntegratorKind, JobValidationError, Limits, ProjectionKind, RenderJob, RenderResult,
+};
 use clumsy_rt::*;
+use std::convert::TryFrom;
 use std::path::Path;
 
 // #[no_mangle]
 // #[start]
 pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("stitch") {
+        stitch_tiles(&args[2..]);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("render") {
+        render_job(&args[2..]);
+        return;
+    }
+
     let n_threads: usize = std::env::var("N_THREADS")
         .map(|s| s.parse::<usize>().expect("invalid value"))
         .unwrap_or(1);
@@ -17,24 +31,189 @@ pub fn main() {
     let n_recursion: usize = std::env::var("N_RECURSION")
         .map(|s| s.parse::<usize>().expect("invalid value"))
         .unwrap_or(50);
+    let integrator = std::env::var("INTEGRATOR")
+        .map(|s| {
+            let n = s.parse::<u32>().expect("invalid value");
+            IntegratorKind::try_from(n).expect("unknown integrator")
+        })
+        .unwrap_or_default();
+    let projection = std::env::var("PROJECTION")
+        .map(|s| {
+            let n = s.parse::<u32>().expect("invalid value");
+            ProjectionKind::try_from(n).expect("unknown projection")
+        })
+        .unwrap_or_default();
+    let shutter_open: f32 = std::env::var("SHUTTER_OPEN")
+        .map(|s| s.parse::<f32>().expect("invalid value"))
+        .unwrap_or(0.0);
+    let filter = std::env::var("FILTER")
+        .map(|s| {
+            let n = s.parse::<u32>().expect("invalid value");
+            FilterKind::try_from(n).expect("unknown filter")
+        })
+        .unwrap_or_default();
+    let shutter: f32 = std::env::var("SHUTTER")
+        .map(|s| s.parse::<f32>().expect("invalid value"))
+        .unwrap_or(0.0);
 
     let w = 4 * size_scalar;
     let h = 3 * size_scalar;
 
-    // let scene = clumsy_rt::sample_scenes::build_simple_scene();
-    let scene = clumsy_rt::sample_scenes::build_cool_scene();
-    let camera = Camera::new(n_samples, n_recursion, w, h);
+    check_settings(w, h, n_samples, n_recursion)
+        .unwrap_or_else(|err| exit_with(format!("invalid settings: {err}")));
 
-    let mut img = PixelPlane::new(w, h);
+    // a shutter that stays open or opens late needs the animated scene even
+    // in a still image
+    let animated = std::env::var_os("FRAMES").is_some() || shutter_open > 0.0 || shutter > 0.0;
+    // let scene = clumsy_rt::sample_scenes::build_simple_scene();
+    let scene = if animated {
+        clumsy_rt::sample_scenes::build_animated_cool_scene()
+    } else {
+        clumsy_rt::sample_scenes::build_cool_scene()
+    };
+    let camera = Camera::new(n_samples, n_recursion, w, h)
+        .with_integrator(build_integrator(integrator))
+        .with_projection(projection.into())
+        .with_shutter(shutter_open, shutter_open + shutter)
+        .with_filter(filter.into());
 
     println!("{}x{}", w, h);
     println!("{}x multi-sampling", n_samples);
     println!("{}x ray-bouncing", n_recursion);
+    println!("{:?}", integrator);
+    println!("{:?} projection", projection);
+
+    match std::env::var("FRAMES") {
+        Ok(range) => render_animation(&camera, &scene, &range, w, h, n_threads),
+        Err(_) => {
+            let mut img = PixelPlane::new(w, h);
+            camera.render(scene, &mut img, n_threads);
+            img.export_png(Path::new("out.png")).unwrap();
+            // img.export_ppm(Path::new("out.ppm"))?;
+        }
+    }
+}
+
+/// Renders the inclusive frame range `first-last` to `out_0000.png`,
+/// `out_0001.png` and so on, or to a single animated `out.png` if `APNG` is
+/// set.
+fn render_animation(
+    camera: &Camera,
+    scene: &Scene,
+    range: &str,
+    w: usize,
+    h: usize,
+    n_threads: usize,
+) {
+    let (first, last) = range
+        .split_once('-')
+        .map(|(a, b)| (a.parse::<u32>(), b.parse::<u32>()))
+        .and_then(|(a, b)| Some((a.ok()?, b.ok()?)))
+        .unwrap_or_else(|| panic!("FRAMES must look like 0-95 but was {}", range));
+    let apng = std::env::var_os("APNG").is_some();
+    let fps: u16 = std::env::var("FPS")
+        .map(|s| s.parse::<u16>().expect("invalid value"))
+        .unwrap_or(24);
+
+    let mut frames = vec![];
+    for frame in first..=last {
+        println!("frame {}", frame);
+        let mut img = PixelPlane::new(w, h);
+        camera.render(scene.at_time(frame as f32), &mut img, n_threads);
+        if apng {
+            frames.push(img);
+        } else {
+            let path = format!("out_{:04}.png", frame);
+            img.export_png(Path::new(&path)).unwrap();
+        }
+    }
+    if apng {
+        PixelPlane::export_apng(&frames, fps, Path::new("out.png")).unwrap();
+    }
+}
+
+/// `clumsy-rt stitch <out.png> <job> <tile.png> [<job> <tile.png>...]`
+///
+/// Assembles the PNG results of render jobs, given in their `x/y/w/h/...`
+/// form, into one image. Exits with an error if the jobs do not cover the
+/// frame exactly once.
+fn stitch_tiles(args: &[String]) {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        eprintln!("usage: clumsy-rt stitch <out.png> <job> <tile.png> [<job> <tile.png>...]");
+        std::process::exit(2);
+    }
+    let mut results = vec![];
+    for pair in args[1..].chunks(2) {
+        let job: RenderJob = pair[0]
+            .parse()
+            .unwrap_or_else(|err| exit_with(format!("invalid job {}: {err}", pair[0])));
+        let png = std::fs::read(&pair[1])
+            .unwrap_or_else(|err| exit_with(format!("cannot read {}: {err}", pair[1])));
+        results.push((job, png));
+    }
+    let img = clumsy_rt::stitch(results.iter().map(|(job, png)| (job, png.as_slice())))
+        .unwrap_or_else(|err| exit_with(format!("cannot stitch tiles: {err}")));
+    img.export_png(Path::new(&args[0]))
+        .unwrap_or_else(|err| exit_with(format!("cannot write {}: {err}", args[0])));
+    println!("{}x{} written to {}", img.w, img.h, args[0]);
+}
+
+/// `clumsy-rt render <job> [<out>]`
+///
+/// Renders a single job, given in its `x/y/w/h/...` form, to `out` or to
+/// `out.png`. Results are kept in `CACHE_DIR`, `.clumsy-cache` by default, so
+/// that rendering the same job again only copies the file.
+fn render_job(args: &[String]) {
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: clumsy-rt render <job> [<out>]");
+        std::process::exit(2);
+    }
+    let job: RenderJob = args[0]
+        .parse()
+        .unwrap_or_else(|err| exit_with(format!("invalid job {}: {err}", args[0])));
+    job.validate()
+        .unwrap_or_else(|err| exit_with(format!("invalid job {}: {err}", args[0])));
+    let out = args.get(1).map(String::as_str).unwrap_or("out.png");
+    let n_threads: usize = std::env::var("N_THREADS")
+        .map(|s| s.parse::<usize>().expect("invalid value"))
+        .unwrap_or(1);
+    let cache_dir = std::env::var("CACHE_DIR").unwrap_or_else(|_| ".clumsy-cache".to_owned());
+    let cache = FsCache::new(&cache_dir)
+        .unwrap_or_else(|err| exit_with(format!("cannot use cache {cache_dir}: {err}")));
+
+    let renderer = CachedRenderer::new(cache);
+    let (result, cached) = renderer.get_or_render(&job, || {
+        let start = std::time::Instant::now();
+        let image = job.render_threaded(n_threads);
+        RenderResult::new(job.clone(), image)
+            .with_duration_ms(u32::try_from(start.elapsed().as_millis()).unwrap_or(u32::MAX))
+            .with_stats(job.stats(n_threads))
+            .with_worker("cli")
+    });
+    std::fs::write(out, &result.image)
+        .unwrap_or_else(|err| exit_with(format!("cannot write {out}: {err}")));
+    let origin = if cached { "from cache" } else { "rendered" };
+    println!("{} {origin}, written to {out}", result.summary());
+}
 
-    camera.render(scene, &mut img, n_threads);
+/// Checks the settings of a local render like a job of the whole frame sent
+/// to workers, without the ray budget, as local renders may take as long as
+/// they like.
+fn check_settings(
+    w: usize,
+    h: usize,
+    n_samples: usize,
+    n_recursion: usize,
+) -> Result<(), JobValidationError> {
+    let limits = Limits::default().with_max_rays(u64::MAX);
+    let to_u32 = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+    let (w, h) = (to_u32(w), to_u32(h));
+    RenderJob::new(0, 0, w, h, w, h, to_u32(n_samples), to_u32(n_recursion)).validate_with(&limits)
+}
 
-    img.export_png(Path::new("out.png")).unwrap();
-    // img.export_ppm(Path::new("out.ppm"))?;
+fn exit_with(msg: String) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
 }
 
 // not an actual test, just to produce a reference image
@@ -72,3 +251,16 @@ fn smoke_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn settings_are_checked_without_ray_budget() {
+    assert_eq!(check_settings(480, 360, 4096, 256), Ok(()));
+    assert!(matches!(
+        check_settings(0, 360, 9, 50),
+        Err(JobValidationError::EmptyCamera { .. })
+    ));
+    assert!(matches!(
+        check_settings(480, 360, 9, 300),
+        Err(JobValidationError::RecursionTooDeep { .. })
+    ));
+}
@@ -0,0 +1,177 @@
+//! Participating media: light is scattered or absorbed inside a volume
+//! instead of only at surfaces.
+//!
+//! Media are homogeneous. A ray travelling through one is scattered after a
+//! random, exponentially distributed distance. When that distance lies before
+//! the next surface, the ray continues from the scattering point in a
+//! direction given by the phase function.
+
+use crate::random;
+use nalgebra::{Isometry3, Vector3};
+use ncollide3d::query::Ray;
+use ncollide3d::shape::ShapeHandle;
+use std::f32::consts::PI;
+
+/// Angular distribution of scattered light.
+#[derive(Clone, Copy, Debug)]
+pub enum PhaseFunction {
+    /// Scatters equally in all directions.
+    Isotropic,
+    /// Henyey-Greenstein with asymmetry `g` in (-1, 1). Positive values favor
+    /// forward scattering, as in haze around the sun.
+    HenyeyGreenstein(f32),
+}
+
+/// Fog that fills the scene below a given height.
+#[derive(Clone, Debug)]
+pub struct Fog {
+    /// Scattering coefficient, probability of scattering per unit distance.
+    pub scattering: f32,
+    /// Absorption coefficient, probability of absorption per unit distance.
+    pub absorption: f32,
+    /// Tints the scattered light.
+    pub color: Vector3<f32>,
+    pub phase: PhaseFunction,
+    /// World space y coordinate of the upper fog boundary.
+    pub top: f32,
+}
+
+/// Constant density medium inside the boundary of a shape, such as smoke.
+///
+/// The boundary should be convex, rays are assumed to leave the volume at
+/// the first boundary intersection from the inside.
+pub struct Volume {
+    pub boundary: ShapeHandle<f32>,
+    pub position: Isometry3<f32>,
+    /// Probability of scattering per unit distance.
+    pub density: f32,
+    pub color: Vector3<f32>,
+    pub phase: PhaseFunction,
+}
+
+/// A sampled scattering event.
+pub(crate) struct Scattering<'a> {
+    /// Distance along the ray, in multiples of the ray direction.
+    pub toi: f32,
+    /// Fraction of light that continues in the new direction.
+    pub weight: Vector3<f32>,
+    pub phase: &'a PhaseFunction,
+}
+
+impl PhaseFunction {
+    /// Samples a new direction for light travelling along `dir`.
+    pub fn sample(&self, dir: &Vector3<f32>) -> Vector3<f32> {
+        let cos_theta = match *self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * random::unit());
+                (1.0 + g * g - sq * sq) / (2.0 * g)
+            }
+            _ => 1.0 - 2.0 * random::unit(),
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::unit();
+
+        let forward = dir.normalize();
+        let helper = if forward.x.abs() > 0.9 {
+            Vector3::y()
+        } else {
+            Vector3::x()
+        };
+        let tangent = forward.cross(&helper).normalize();
+        let bitangent = forward.cross(&tangent);
+        sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * forward
+    }
+}
+
+impl Fog {
+    /// Isotropic fog covering the entire scene.
+    pub fn new(scattering: f32, absorption: f32, color: Vector3<f32>) -> Self {
+        Self {
+            scattering,
+            absorption,
+            color,
+            phase: PhaseFunction::Isotropic,
+            top: f32::INFINITY,
+        }
+    }
+
+    pub fn with_phase(mut self, phase: PhaseFunction) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    pub fn with_top(mut self, top: f32) -> Self {
+        self.top = top;
+        self
+    }
+
+    pub(crate) fn sample(&self, ray: &Ray<f32>, max_toi: f32) -> Option<Scattering<'_>> {
+        let extinction = self.scattering + self.absorption;
+        if extinction <= 0.0 {
+            return None;
+        }
+        // part of the ray below `top`
+        let (start, end) = if ray.origin.y < self.top {
+            let exit = if ray.dir.y > 0.0 {
+                (self.top - ray.origin.y) / ray.dir.y
+            } else {
+                f32::INFINITY
+            };
+            (0.0, exit)
+        } else if ray.dir.y < 0.0 {
+            ((self.top - ray.origin.y) / ray.dir.y, f32::INFINITY)
+        } else {
+            return None;
+        };
+        let end = end.min(max_toi);
+        let toi = start + free_flight(extinction, ray);
+        (toi < end).then(|| Scattering {
+            toi,
+            weight: self.color * (self.scattering / extinction),
+            phase: &self.phase,
+        })
+    }
+}
+
+impl Volume {
+    pub(crate) fn sample(&self, ray: &Ray<f32>, max_toi: f32) -> Option<Scattering<'_>> {
+        let shape = self.boundary.as_ray_cast()?;
+        // rays starting inside enter at 0, thanks to the solid flag
+        let enter = shape.toi_with_ray(&self.position, ray, max_toi, true)?;
+        let nudge = 1e-4 / ray.dir.norm();
+        let inside = Ray::new(ray.point_at(enter + nudge), ray.dir);
+        let exit = enter
+            + nudge
+            + shape
+                .toi_with_ray(&self.position, &inside, f32::MAX, false)
+                .unwrap_or(0.0);
+        let toi = enter + free_flight(self.density, ray);
+        (toi < exit.min(max_toi)).then_some(Scattering {
+            toi,
+            weight: self.color,
+            phase: &self.phase,
+        })
+    }
+}
+
+/// Random travel distance until the next interaction, in multiples of the
+/// ray direction.
+fn free_flight(extinction: f32, ray: &Ray<f32>) -> f32 {
+    let distance = -(1.0 - random::unit()).ln() / extinction;
+    distance / ray.dir.norm()
+}
+
+#[test]
+fn henyey_greenstein_favors_forward_directions() {
+    let dir = Vector3::new(0.0, 0.0, -2.0);
+    let n = 2000;
+    let mean_cos = |phase: PhaseFunction| {
+        (0..n)
+            .map(|_| phase.sample(&dir).dot(&dir.normalize()))
+            .sum::<f32>()
+            / n as f32
+    };
+    // the mean cosine of Henyey-Greenstein is g
+    assert!((mean_cos(PhaseFunction::HenyeyGreenstein(0.7)) - 0.7).abs() < 0.1);
+    assert!(mean_cos(PhaseFunction::Isotropic).abs() < 0.1);
+}
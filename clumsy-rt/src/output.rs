@@ -1,6 +1,6 @@
 use crate::PixelPlane;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
 impl PixelPlane {
@@ -15,6 +15,28 @@ impl PixelPlane {
         self.write_png(buffer)?;
         Ok(())
     }
+    pub fn import_png(path: &Path) -> std::io::Result<Self> {
+        Self::read_png(BufReader::new(File::open(path)?))
+    }
+    /// Decodes an 8-bit RGB PNG, as produced by `write_png`.
+    pub fn read_png(input: impl Read) -> std::io::Result<Self> {
+        let mut reader = png::Decoder::new(input).read_info()?;
+        let color = reader.output_color_type();
+        if color != (png::ColorType::Rgb, png::BitDepth::Eight) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("expected 8-bit RGB png but got {color:?}"),
+            ));
+        }
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+        Ok(Self::from_raw_data(
+            info.width as usize,
+            info.height as usize,
+            &buf,
+        ))
+    }
     pub fn write_png(&self, out: impl Write) -> Result<(), std::io::Error> {
         let mut encoder = png::Encoder::new(out, self.w as u32, self.h as u32);
         encoder.set_color(png::ColorType::Rgb);
@@ -24,7 +46,36 @@ impl PixelPlane {
             writer.write_image_data(&self.raw_data())?;
         })
     }
-    fn write_ppm(&self, out: &mut impl Write) -> std::io::Result<()> {
+    /// Exports an animated PNG that loops forever. All frames must have the
+    /// same size.
+    pub fn export_apng(frames: &[PixelPlane], fps: u16, path: &Path) -> std::io::Result<()> {
+        let buffer = BufWriter::new(File::create(path)?);
+        Self::write_apng(frames, fps, buffer)
+    }
+    pub fn write_apng(frames: &[PixelPlane], fps: u16, out: impl Write) -> std::io::Result<()> {
+        let first = frames.first().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "animation without frames")
+        })?;
+        let mut encoder = png::Encoder::new(out, first.w as u32, first.h as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0)?;
+        encoder.set_frame_delay(1, fps)?;
+        let mut writer = encoder.write_header()?;
+        for frame in frames {
+            if (frame.w, frame.h) != (first.w, first.h) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "all frames of an animation must have the same size",
+                ));
+            }
+            unsafe {
+                writer.write_image_data(frame.raw_data())?;
+            }
+        }
+        Ok(())
+    }
+    pub fn write_ppm(&self, out: &mut impl Write) -> std::io::Result<()> {
         writeln!(out, "P3")?;
         writeln!(out, "{} {}", self.w, self.h)?;
         writeln!(out, "255")?;
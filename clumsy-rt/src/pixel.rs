@@ -68,6 +68,21 @@ impl PixelPlane {
             self.pixels[a..b].clone_from_slice(&shard.pixels);
         }
     }
+    /// Builds a plane from tightly packed 8-bit RGB data, row by row.
+    pub fn from_raw_data(w: usize, h: usize, data: &[u8]) -> Self {
+        assert_eq!(
+            w * h * 3,
+            data.len(),
+            "raw data must contain 3 bytes per pixel"
+        );
+        let pixels = data
+            .chunks_exact(3)
+            .map(|rgb| Pixel {
+                col: Vector3::new(rgb[0], rgb[1], rgb[2]),
+            })
+            .collect();
+        Self { w, h, pixels }
+    }
     pub unsafe fn raw_data(&self) -> &[u8] {
         std::slice::from_raw_parts(
             &self.pixels[0] as *const Pixel as *const u8,
@@ -0,0 +1,33 @@
+//! Deterministic randomness for the ray tracer.
+//!
+//! All random decisions (reflections, fuzz, sub-pixel offsets) draw from a
+//! thread-local generator. The camera re-seeds it for every pixel from the
+//! render seed and the pixel coordinates, which makes the output independent
+//! of how an image is split into tiles, shards or threads.
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::seed_from_u64(0));
+}
+
+/// Re-seed the generator of the current thread for the given pixel.
+pub(crate) fn seed_pixel(seed: u64, x: usize, y: usize) {
+    let pixel_seed = mix(mix(seed ^ x as u64) ^ (y as u64).rotate_left(32));
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(pixel_seed));
+}
+
+/// Uniformly distributed number in `[0, 1)`.
+pub(crate) fn unit() -> f32 {
+    RNG.with(|rng| rng.borrow_mut().gen::<f32>())
+}
+
+/// splitmix64 finalizer, spreads close inputs over the full `u64` range.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
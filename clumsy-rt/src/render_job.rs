@@ -1,31 +1,167 @@
-use api::RenderJob;
+#[cfg(test)]
+use api::{IntegratorKind, ProjectionKind, SceneKind};
+use api::{OutputFormat, RenderJob, RenderStats, RowBlock};
 
-use crate::{sample_scenes, Camera, PixelPlane};
+use crate::{build_integrator, sample_scenes, Camera, PixelPlane, RowSink};
 
 pub trait RenderJobExt {
+    /// Camera with the quality settings and frame size of the job.
+    fn camera(&self) -> Camera;
+    /// Work done when rendering the job with `n_threads` threads, for
+    /// `api::RenderResult`.
+    fn stats(&self, n_threads: usize) -> RenderStats;
+    /// Renders the tile, encoded in the job's output format.
     fn render(&self) -> Vec<u8>;
+    /// Same as `render`, using up to `n_threads` threads.
+    fn render_threaded(&self, n_threads: usize) -> Vec<u8>;
+    /// Same as `render_threaded`, calling `progress` with the number of
+    /// finished rows, from the top, each time `rows_per_call` more are done.
+    fn render_with_progress(
+        &self,
+        n_threads: usize,
+        rows_per_call: usize,
+        progress: &mut dyn FnMut(usize),
+    ) -> Vec<u8>;
+    /// Same as `render_threaded`, passing each block of `rows_per_block`
+    /// finished rows to `on_rows` as soon as it is done. Blocks are always
+    /// PNG.
+    fn render_row_blocks(
+        &self,
+        n_threads: usize,
+        rows_per_block: usize,
+        on_rows: &mut dyn FnMut(RowBlock),
+    ) -> Vec<u8>;
+    /// Same as `render_row_blocks` for callers that only need the blocks,
+    /// without encoding the whole tile at the end.
+    fn for_each_row_block(
+        &self,
+        n_threads: usize,
+        rows_per_block: usize,
+        on_rows: &mut dyn FnMut(RowBlock),
+    );
 }
 
 impl RenderJobExt for RenderJob {
-    fn render(&self) -> Vec<u8> {
-        let mut pixels = PixelPlane::new(self.w as usize, self.h as usize);
-        let camera = Camera::new(
+    fn camera(&self) -> Camera {
+        Camera::new(
             self.n_samples as usize,
             self.n_recursion as usize,
             self.camera_w as usize,
             self.camera_h as usize,
-        );
-        let scene = sample_scenes::build_cool_scene();
-        camera.render_tile(&scene, self.x as usize, self.y as usize, &mut pixels);
+        )
+        .with_integrator(build_integrator(self.integrator))
+        .with_projection(self.projection.into())
+        .with_filter(self.filter.into())
+        .with_shutter(
+            self.shutter_open as f32 / RenderJob::MAX_SHUTTER as f32,
+            self.shutter_open.saturating_add(self.shutter) as f32 / RenderJob::MAX_SHUTTER as f32,
+        )
+        .with_seed(self.seed)
+    }
+
+    fn stats(&self, n_threads: usize) -> RenderStats {
+        RenderStats {
+            samples: self.w as u64 * self.h as u64 * self.camera().samples_per_pixel() as u64,
+            threads: n_threads as u32,
+        }
+    }
+
+    fn render(&self) -> Vec<u8> {
+        self.render_threaded(1)
+    }
+
+    fn render_threaded(&self, n_threads: usize) -> Vec<u8> {
+        self.render_with_progress(n_threads, self.h as usize, &mut |_| {})
+    }
+
+    fn render_with_progress(
+        &self,
+        n_threads: usize,
+        rows_per_call: usize,
+        progress: &mut dyn FnMut(usize),
+    ) -> Vec<u8> {
+        struct Progress<'a>(usize, &'a mut dyn FnMut(usize));
+        impl RowSink for Progress<'_> {
+            fn rows_per_block(&self) -> usize {
+                self.0
+            }
+            fn rows(&mut self, y: usize, block: &PixelPlane) {
+                (self.1)(y + block.h)
+            }
+        }
+        encode(
+            &render_streaming(self, n_threads, &mut Progress(rows_per_call, progress)),
+            self.format,
+        )
+    }
 
-        let mut buf = Vec::new();
-        pixels
-            .write_png(&mut buf)
-            .expect("failed writing png to buffer");
-        buf
+    fn render_row_blocks(
+        &self,
+        n_threads: usize,
+        rows_per_block: usize,
+        on_rows: &mut dyn FnMut(RowBlock),
+    ) -> Vec<u8> {
+        encode(
+            &render_streaming(self, n_threads, &mut Blocks(rows_per_block, on_rows)),
+            self.format,
+        )
+    }
+
+    fn for_each_row_block(
+        &self,
+        n_threads: usize,
+        rows_per_block: usize,
+        on_rows: &mut dyn FnMut(RowBlock),
+    ) {
+        render_streaming(self, n_threads, &mut Blocks(rows_per_block, on_rows));
     }
 }
 
+/// Passes finished rows on as PNG row blocks.
+struct Blocks<'a>(usize, &'a mut dyn FnMut(RowBlock));
+
+impl RowSink for Blocks<'_> {
+    fn rows_per_block(&self) -> usize {
+        self.0
+    }
+    fn rows(&mut self, y: usize, block: &PixelPlane) {
+        (self.1)(RowBlock {
+            y: y as u32,
+            h: block.h as u32,
+            png: encode(block, OutputFormat::Png),
+        })
+    }
+}
+
+fn render_streaming(job: &RenderJob, n_threads: usize, sink: &mut dyn RowSink) -> PixelPlane {
+    let mut pixels = PixelPlane::new(job.w as usize, job.h as usize);
+    // a sharp frame 0 is the still image, which renders faster without
+    // animation
+    let scene = match (job.frame, job.shutter_open, job.shutter) {
+        (0, 0, 0) => sample_scenes::build_scene(job.scene),
+        (frame, _, _) => sample_scenes::build_animated_scene(job.scene).at_time(frame as f32),
+    };
+    job.camera().render_tile_streaming(
+        &scene,
+        job.x as usize,
+        job.y as usize,
+        &mut pixels,
+        n_threads,
+        sink,
+    );
+    pixels
+}
+
+fn encode(pixels: &PixelPlane, format: OutputFormat) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match format {
+        OutputFormat::Png => pixels.write_png(&mut buf),
+        OutputFormat::Ppm => pixels.write_ppm(&mut buf),
+    }
+    .expect("failed writing image to buffer");
+    buf
+}
+
 #[test]
 fn smoke_test() {
     RenderJob::new(0, 0, 128, 128, 128, 128, 1, 1).render();
@@ -33,4 +169,90 @@ fn smoke_test() {
     RenderJob::new(0, 0, 96, 54, 96, 54, 2, 2).render();
     RenderJob::new(48, 0, 48, 27, 96, 54, 2, 2).render();
     RenderJob::new(150, 157, 30, 22, 240, 180, 1, 2).render();
+    for integrator in [
+        IntegratorKind::Whitted,
+        IntegratorKind::AmbientOcclusion,
+        IntegratorKind::Normals,
+        IntegratorKind::Depth,
+        IntegratorKind::Bounces,
+    ] {
+        RenderJob::new(0, 0, 32, 24, 64, 48, 1, 2)
+            .with_integrator(integrator)
+            .render();
+    }
+    RenderJob::new(0, 0, 32, 24, 64, 48, 1, 2)
+        .with_frame(30)
+        .render();
+    for projection in [
+        ProjectionKind::Orthographic,
+        ProjectionKind::Fisheye,
+        ProjectionKind::Equirectangular,
+    ] {
+        RenderJob::new(16, 8, 32, 24, 64, 32, 1, 2)
+            .with_projection(projection)
+            .render();
+    }
+}
+
+#[test]
+fn scene_seed_and_format_are_applied() {
+    let job = RenderJob::new(0, 0, 16, 12, 16, 12, 1, 2);
+    let plain = job.render();
+    for scene in [SceneKind::Hazy, SceneKind::Simple, SceneKind::Lights] {
+        assert_ne!(job.clone().with_scene(scene).render(), plain, "{scene:?}");
+    }
+    assert_ne!(job.clone().with_seed(7).render(), plain);
+    let ppm = job.with_format(OutputFormat::Ppm).render();
+    assert!(ppm.starts_with(b"P3\n16 12\n255\n"));
+}
+
+#[test]
+fn progress_is_reported_per_row_block() {
+    let job = RenderJob::new(8, 4, 16, 10, 32, 24, 1, 2);
+    let mut reported = vec![];
+    let png = job.render_with_progress(2, 4, &mut |rows| reported.push(rows));
+    assert_eq!(reported, vec![4, 8, 10]);
+    assert_eq!(png, job.render());
+
+    // blocks finish in any order on several threads, rows still in order
+    let job = RenderJob::new(0, 0, 80, 70, 80, 70, 1, 1).with_filter(api::FilterKind::Mitchell);
+    let mut reported = vec![];
+    let png = job.render_with_progress(4, 16, &mut |rows| reported.push(rows));
+    assert_eq!(reported, vec![16, 32, 48, 64, 70]);
+    assert_eq!(png, job.render());
+}
+
+#[test]
+fn row_blocks_cover_the_tile() {
+    let job = RenderJob::new(8, 4, 16, 10, 32, 24, 1, 2);
+    let mut blocks = vec![];
+    let png = job.render_row_blocks(1, 4, &mut |block| blocks.push(block));
+    let rows: Vec<_> = blocks.iter().map(|block| (block.y, block.h)).collect();
+    assert_eq!(rows, vec![(0, 4), (4, 4), (8, 2)]);
+
+    let whole = PixelPlane::read_png(png.as_slice()).unwrap();
+    for block in &blocks {
+        let part = PixelPlane::read_png(block.png.as_slice()).unwrap();
+        for y in 0..part.h {
+            for x in 0..part.w {
+                assert_eq!(
+                    part.pixel(x, y).col,
+                    whole.pixel(x, block.y as usize + y).col
+                );
+            }
+        }
+    }
+
+    let mut only_blocks = vec![];
+    job.for_each_row_block(2, 4, &mut |block| only_blocks.push(block));
+    assert_eq!(only_blocks, blocks);
+}
+
+#[test]
+fn cameras_one_pixel_wide_or_high_render() {
+    for (w, h) in [(1, 1), (1, 6), (6, 1)] {
+        let job = RenderJob::new(0, 0, w, h, w, h, 1, 2);
+        assert_eq!(job.validate(), Ok(()));
+        job.render();
+    }
 }
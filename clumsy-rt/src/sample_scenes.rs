@@ -6,8 +6,71 @@ use ncollide3d::shape::*;
 use std::f32::consts::*;
 
 use crate::*;
+use api::SceneKind;
 
+/// Number of frames after which the animation of the cool scene repeats.
+pub const COOL_SCENE_FRAMES: u32 = 96;
+
+/// The scene selected by a render job, as a still image.
+pub fn build_scene(kind: SceneKind) -> Scene {
+    match kind {
+        SceneKind::Cool => build_cool_scene(),
+        SceneKind::Hazy => build_hazy_scene(),
+        SceneKind::Simple => build_simple_scene(),
+        SceneKind::Lights => build_lights_scene(),
+    }
+}
+
+/// The scene selected by a render job, with its animation. Scenes without
+/// animation are the same as in `build_scene`.
+pub fn build_animated_scene(kind: SceneKind) -> Scene {
+    match kind {
+        SceneKind::Cool => build_animated_cool_scene(),
+        SceneKind::Hazy => hazy_scene_builder(true).build(),
+        SceneKind::Simple => build_simple_scene(),
+        SceneKind::Lights => build_lights_scene(),
+    }
+}
+
+/// Spheres on a ring over a green floor, under a sunset sky. The still image
+/// is frame 0 of `build_animated_cool_scene`, without the cost of animation.
 pub fn build_cool_scene() -> Scene {
+    cool_scene_builder(false).build()
+}
+
+/// The cool scene with the rings orbiting, the die spinning and the camera
+/// swaying from side to side, repeating every `COOL_SCENE_FRAMES` frames.
+pub fn build_animated_cool_scene() -> Scene {
+    cool_scene_builder(true).build()
+}
+
+/// The cool scene at dusk, with ground haze glowing in the sunset and a cloud
+/// of smoke around the die.
+pub fn build_hazy_scene() -> Scene {
+    hazy_scene_builder(false).build()
+}
+
+fn hazy_scene_builder(animated: bool) -> SceneBuilder {
+    let mut scene = cool_scene_builder(animated);
+    let haze_col = Vector3::new(1.0, 0.8, 0.6);
+    scene.set_fog(
+        Fog::new(0.02, 0.004, haze_col)
+            .with_phase(PhaseFunction::HenyeyGreenstein(0.6))
+            .with_top(1.0),
+    );
+    let smoke_center = Translation3::new(0.0, -2.5, -5.0 * VIEWPORT_WIDTH);
+    scene.add_volume(
+        Ball::new(1.2),
+        Isometry3::from_parts(smoke_center, UnitQuaternion::identity()),
+        0.6,
+        Vector3::new(0.8, 0.8, 0.8),
+        PhaseFunction::Isotropic,
+    );
+    scene
+}
+
+/// Without `animated`, everything is placed as in frame 0.
+fn cool_scene_builder(animated: bool) -> SceneBuilder {
     const MAX_DISTANCE: f32 = 1_000_000.0;
     let mut scene = SceneBuilder::new(MAX_DISTANCE, sky);
 
@@ -45,7 +108,7 @@ pub fn build_cool_scene() -> Scene {
         Texture::light_source(moon_col),
     );
 
-    // die
+    // die, spinning about the vertical axis
     let tran = Translation3::new(0.0, center_h - 3.0, -5.0 * VIEWPORT_WIDTH);
     let die_rot = UnitQuaternion::from_scaled_axis(Vector3::x() * FRAC_PI_4)
         * UnitQuaternion::from_scaled_axis(Vector3::z() * FRAC_PI_4)
@@ -53,31 +116,84 @@ pub fn build_cool_scene() -> Scene {
     let red = Vector3::new(0.839, 0.25, 0.27);
     let side_len = center_sphere_radius * 0.3819;
     let die = Cuboid::new(Vector3::new(side_len, side_len, side_len));
-    scene.add(
-        die,
-        Isometry3::from_parts(tran, die_rot),
-        // Texture::metal(red, 0.55, 0.25),
-        Texture::metal(red, 0.95, 0.25),
-    );
+    let mut die_track = Track::new(Interpolation::Linear).repeating();
+    for quarter in 0..=4 {
+        let spin = UnitQuaternion::from_scaled_axis(Vector3::y() * FRAC_PI_2 * quarter as f32);
+        let time = (COOL_SCENE_FRAMES * quarter / 4) as f32;
+        die_track = die_track.key(time, Similarity3::from_parts(tran, spin * die_rot, 1.0));
+    }
+    // let die_texture = Texture::metal(red, 0.55, 0.25);
+    let die_texture = Texture::metal(red, 0.95, 0.25);
+    if animated {
+        scene.add_animated(die, die_track, die_texture);
+    } else {
+        scene.add(die, die_track.sample(0.0).isometry, die_texture);
+    }
 
+    // rings orbiting the center, alternating direction, one sphere spacing per loop
     let smaller = VIEWPORT_WIDTH / 4.0;
+    let mut ring_sphere = GroupBuilder::new();
+    ring_sphere.add(
+        Ball::new(smaller),
+        Isometry3::from_parts(Translation3::identity(), rot),
+        Texture::metal(Vector3::new(0.313, 0.196, 0.078), 1.0, 0.1).with_fuzz(0.05),
+    );
+    let ring_sphere = scene.define_prototype("ring_sphere", ring_sphere);
     for ring_level in 0..4 {
         let r = center_sphere_radius + 1.0 + 1.25 * ring_level as f32;
         let y = center_h + -ring_level as f32 * 1.25;
+        let mut ring = GroupBuilder::new();
         for alpha in 0..8 {
             let alpha = std::f32::consts::FRAC_PI_4 * (alpha as f32 + 0.5);
-            let x = r * alpha.cos();
-            let z = r * alpha.sin() - 5.0 * VIEWPORT_WIDTH;
-            let tran = Translation3::new(x, y, z);
-            scene.add(
-                Ball::new(smaller),
-                Isometry3::from_parts(tran, rot),
-                Texture::metal(Vector3::new(0.313, 0.196, 0.078), 1.0, 0.1).with_fuzz(0.05),
+            let tran = Translation3::new(r * alpha.cos(), 0.0, r * alpha.sin());
+            ring.add_instance(
+                &ring_sphere,
+                Similarity3::from_parts(tran, UnitQuaternion::identity(), 1.0),
             );
         }
+        let name = format!("ring_{ring_level}");
+        scene.define_prototype(name.clone(), ring);
+
+        let center = Translation3::new(0.0, y, -5.0 * VIEWPORT_WIDTH);
+        let direction = if ring_level % 2 == 0 { 1.0 } else { -1.0 };
+        let orbit = |angle: f32| {
+            let rotation = UnitQuaternion::from_scaled_axis(Vector3::y() * angle * direction);
+            Similarity3::from_parts(center, rotation, 1.0)
+        };
+        if animated {
+            let track = Track::new(Interpolation::Linear)
+                .key(0.0, orbit(0.0))
+                .key(COOL_SCENE_FRAMES as f32, orbit(FRAC_PI_4))
+                .repeating();
+            scene.add_animated_instance(&name, track);
+        } else {
+            scene.add_instance(&name, orbit(0.0));
+        }
     }
 
-    scene.build()
+    // camera swaying left and right, turned to keep the center in view
+    let look_from = |x: f32| {
+        let yaw = (x / (5.0 * VIEWPORT_WIDTH)).atan();
+        Similarity3::from_parts(
+            Translation3::new(x, 0.0, 0.0),
+            UnitQuaternion::from_scaled_axis(Vector3::y() * yaw),
+            1.0,
+        )
+    };
+    if animated {
+        let frames = COOL_SCENE_FRAMES as f32;
+        scene.set_camera_track(
+            Track::new(Interpolation::Spline)
+                .key(0.0, look_from(0.0))
+                .key(frames * 0.25, look_from(1.5))
+                .key(frames * 0.5, look_from(0.0))
+                .key(frames * 0.75, look_from(-1.5))
+                .key(frames, look_from(0.0))
+                .repeating(),
+        );
+    }
+
+    scene
 }
 
 pub fn build_simple_scene() -> Scene {
@@ -109,6 +225,97 @@ pub fn build_simple_scene() -> Scene {
     scene.build()
 }
 
+/// A dark room lit only by a point, a spot and a directional light.
+pub fn build_lights_scene() -> Scene {
+    const MAX_DISTANCE: f32 = 100.0;
+    let mut scene = SceneBuilder::new(MAX_DISTANCE, night);
+
+    let rot = UnitQuaternion::identity();
+    let z = -5.0 * VIEWPORT_WIDTH;
+
+    // floor
+    let tran = Translation3::new(0.0, VIEWPORT_WIDTH * -100.5, z);
+    scene.add(
+        Ball::new(VIEWPORT_WIDTH * 100.0),
+        Isometry3::from_parts(tran, rot),
+        Texture::perfect_diffuse(Vector3::new(0.8, 0.8, 0.8)),
+    );
+
+    // three spheres, one below each light
+    let spheres = [
+        (-1.2, Texture::perfect_diffuse(Vector3::new(0.9, 0.3, 0.2))),
+        (0.0, Texture::diffuse(Vector3::new(0.2, 0.8, 0.3), 0.2)),
+        (
+            1.2,
+            Texture::metal(Vector3::new(0.9, 0.9, 0.9), 0.1, 0.8).with_fuzz(0.1),
+        ),
+    ];
+    for (x, texture) in spheres {
+        let tran = Translation3::new(x * VIEWPORT_WIDTH, 0.0, z);
+        scene.add(
+            Ball::new(VIEWPORT_WIDTH * 0.5),
+            Isometry3::from_parts(tran, rot),
+            texture,
+        );
+    }
+
+    scene.add_light(Light::point(
+        Point3::new(-1.2 * VIEWPORT_WIDTH, 1.0, z + 1.0),
+        Vector3::new(6.0, 5.0, 4.0),
+    ));
+    scene.add_light(Light::spot(
+        Point3::new(0.0, 2.5, z),
+        -Vector3::y(),
+        Vector3::new(12.0, 12.0, 12.0),
+        FRAC_PI_8,
+        FRAC_PI_6,
+    ));
+    scene.add_light(Light::directional(
+        Vector3::new(-1.0, -1.0, -0.5),
+        Vector3::new(0.15, 0.15, 0.3),
+    ));
+
+    scene.build()
+}
+
+/// A field of `n` small spheres over the floor, useful to measure how ray
+/// casting scales with the number of objects.
+pub fn build_sphere_field_scene(n: usize) -> Scene {
+    const MAX_DISTANCE: f32 = 1_000_000.0;
+    let mut scene = SceneBuilder::new(MAX_DISTANCE, sky);
+
+    let rot = UnitQuaternion::identity();
+    let tran = Translation3::new(0.0, VIEWPORT_WIDTH * -100.5 - 2.5, -5.0 * VIEWPORT_WIDTH);
+    scene.add(
+        Ball::new(VIEWPORT_WIDTH * 100.0),
+        Isometry3::from_parts(tran, rot),
+        Texture::diffuse(Vector3::new(0.025, 0.4, 0.0325) / 1.5, 0.75),
+    );
+
+    let columns = (n as f32).sqrt().ceil() as usize;
+    let spacing = 0.6;
+    for i in 0..n {
+        let (row, col) = (i / columns, i % columns);
+        // cheap deterministic jitter, keeps the scene identical on every build
+        let jitter = ((i * 7919) % 101) as f32 / 101.0;
+        let x = (col as f32 - columns as f32 / 2.0) * spacing;
+        let y = -2.0 + jitter;
+        let z = -3.0 - row as f32 * spacing;
+        let texture = match i % 3 {
+            0 => Texture::diffuse(Vector3::new(jitter, 0.3, 1.0 - jitter), 0.5),
+            1 => Texture::metal(Vector3::new(0.313, 0.196, 0.078), 1.0, 0.1).with_fuzz(0.05),
+            _ => Texture::dark_mirror(0.2),
+        };
+        scene.add(
+            Ball::new(spacing / 4.0),
+            Isometry3::from_parts(Translation3::new(x, y, z), rot),
+            texture,
+        );
+    }
+
+    scene.build()
+}
+
 fn sky(ray: &Ray<f32>) -> Vector3<f32> {
     let direction: Vector3<f32> = ray.dir.into();
     let unit_direction = direction.normalize();
@@ -132,6 +339,10 @@ fn sky(ray: &Ray<f32>) -> Vector3<f32> {
     0.5 * (1.0 - t) * sky_col + t * horizon_col + sun_light
 }
 
+fn night(_ray: &Ray<f32>) -> Vector3<f32> {
+    Vector3::new(0.01, 0.01, 0.02)
+}
+
 fn simple_background(ray: &Ray<f32>) -> Vector3<f32> {
     let direction: Vector3<f32> = ray.dir.into();
     let t = direction.normalize().y;
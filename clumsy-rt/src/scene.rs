@@ -1,111 +1,352 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::sync::Arc;
 
+use crate::animation::{Time, Track};
+use crate::group::{Group, GroupBuilder, Hit, Prototype, SceneObject};
+use crate::light::Light;
+use crate::medium::{Fog, PhaseFunction, Scattering, Volume};
 use crate::reflection::*;
 use crate::texture::Texture;
 use nalgebra::geometry::*;
 use nalgebra::Vector3;
-use ncollide3d::pipeline::*;
 use ncollide3d::query::*;
 use ncollide3d::shape::*;
 
-const EPSILON: f32 = f32::EPSILON;
-
 pub struct SceneBuilder {
     max_distance: f32,
-    collision_group: CollisionGroups,
-    query_type: GeometricQueryType<f32>,
-    world: CollisionWorld<f32, Texture>,
+    root: GroupBuilder,
+    prototypes: HashMap<String, Prototype>,
+    fog: Option<Fog>,
+    volumes: Vec<Volume>,
+    lights: Vec<Light>,
+    camera_track: Option<Track>,
     background_color: fn(&Ray<f32>) -> Vector3<f32>,
 }
 
 #[derive(Clone)]
 pub struct Scene {
     max_distance: f32,
-    collision_group: CollisionGroups,
-    world: Arc<CollisionWorld<f32, Texture>>,
+    root: Arc<Group>,
+    fog: Option<Fog>,
+    volumes: Arc<Vec<Volume>>,
+    lights: Arc<Vec<Light>>,
+    camera_track: Option<Arc<Track>>,
+    /// Point in time at which animated objects are intersected, in frames.
+    time: f32,
     background_color: fn(&Ray<f32>) -> Vector3<f32>,
 }
 
+/// The scene at one point in time during the exposure of a frame. Only
+/// borrows the scene, so that each sample can see it at its own time.
+#[derive(Clone, Copy)]
+pub struct Snapshot<'a> {
+    scene: &'a Scene,
+    time: Time,
+}
+
 impl SceneBuilder {
     pub fn new(max_distance: f32, background_color: fn(&Ray<f32>) -> Vector3<f32>) -> Self {
-        let margin = 0.0002;
-        // these values should only matter for collision between objects - not for mere ray casting
-        //  Contact points will be generated as long as the two objects are penetrating or closer than the sum of both prediction values.
-        let prediction = 0.2;
-        let ang_prediction = 0.9;
-        //  allow the generation of contacts between two features (vertices, edges, or faces) of solids that should be in contact if the solid were rotated by this amount.
-        let query_type = GeometricQueryType::Contacts(prediction, ang_prediction);
-
         Self {
-            world: CollisionWorld::new(margin),
-            collision_group: CollisionGroups::new(),
+            root: GroupBuilder::new(),
+            prototypes: HashMap::new(),
+            fog: None,
+            volumes: vec![],
+            lights: vec![],
+            camera_track: None,
             max_distance,
-            query_type,
             background_color,
         }
     }
     pub fn add(&mut self, obj: impl Shape<f32>, position: Isometry3<f32>, texture: Texture) {
-        let handle = ShapeHandle::new(obj);
+        self.root.add(obj, position, texture);
+    }
+
+    /// Registers a group under a name, to be placed with `add_instance`.
+    /// Prototypes may contain instances of previously defined prototypes.
+    pub fn define_prototype(&mut self, name: impl Into<String>, group: GroupBuilder) -> Prototype {
+        let prototype = group.build();
+        self.prototypes.insert(name.into(), prototype.clone());
+        prototype
+    }
+
+    pub fn prototype(&self, name: &str) -> Option<&Prototype> {
+        self.prototypes.get(name)
+    }
+
+    /// Places an instance of a prototype defined with `define_prototype`.
+    ///
+    /// Panics if no prototype with this name exists.
+    pub fn add_instance(&mut self, name: &str, transform: Similarity3<f32>) {
+        let prototype = self
+            .prototypes
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown prototype {}", name));
+        self.root.add_instance(prototype, transform);
+    }
+
+    /// Places an instance of a prototype that moves along `track`.
+    ///
+    /// Panics if no prototype with this name exists.
+    pub fn add_animated_instance(&mut self, name: &str, track: Track) {
+        let prototype = self
+            .prototypes
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown prototype {}", name));
+        self.root.add_animated_instance(prototype, track);
+    }
+
+    /// Adds an object that moves along `track`. The shape is centered at the
+    /// origin of the track's transformation.
+    pub fn add_animated(&mut self, obj: impl Shape<f32>, track: Track, texture: Texture) {
+        self.root.add_animated(obj, track, texture);
+    }
+
+    /// Adds an object that moves with `velocity` per frame while the camera
+    /// shutter is open, starting at `position` in every frame.
+    pub fn add_moving(
+        &mut self,
+        obj: impl Shape<f32>,
+        position: Isometry3<f32>,
+        velocity: Vector3<f32>,
+        texture: Texture,
+    ) {
+        self.root.add_moving(obj, position, velocity, texture);
+    }
 
-        self.world.add(
+    /// Moves the camera along `track`, from camera to world space. Without a
+    /// track, the camera sits at the origin looking along -z.
+    pub fn set_camera_track(&mut self, track: Track) {
+        self.camera_track = Some(track);
+    }
+
+    /// Fills the scene with fog, replacing previously set fog.
+    pub fn set_fog(&mut self, fog: Fog) {
+        self.fog = Some(fog);
+    }
+
+    /// Adds a constant density medium bounded by a convex shape.
+    pub fn add_volume(
+        &mut self,
+        boundary: impl Shape<f32>,
+        position: Isometry3<f32>,
+        density: f32,
+        color: Vector3<f32>,
+        phase: PhaseFunction,
+    ) {
+        self.volumes.push(Volume {
+            boundary: ShapeHandle::new(boundary),
             position,
-            handle,
-            self.collision_group,
-            self.query_type,
-            texture,
-        );
-        self.world.update();
+            density,
+            color,
+            phase,
+        });
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
     }
 
     pub fn build(self) -> Scene {
         Scene {
             max_distance: self.max_distance,
-            collision_group: self.collision_group,
-            world: Arc::new(self.world),
+            root: Arc::new(self.root.build_group()),
+            fog: self.fog,
+            volumes: Arc::new(self.volumes),
+            lights: Arc::new(self.lights),
+            camera_track: self.camera_track.map(Arc::new),
+            time: 0.0,
             background_color: self.background_color,
         }
     }
 }
 
 impl Scene {
+    /// The same scene, with animations at the given time in frames.
+    pub fn at_time(&self, time: f32) -> Scene {
+        Scene {
+            time,
+            ..self.clone()
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// The scene `offset` frames after the start of its frame, while the
+    /// shutter is open.
+    pub fn snapshot(&self, offset: f32) -> Snapshot<'_> {
+        Snapshot {
+            scene: self,
+            time: Time {
+                frame: self.time,
+                offset,
+            },
+        }
+    }
+
+    pub fn cast_ray(&self, ray: &Ray<f32>, depth: usize) -> Vector3<f32> {
+        self.snapshot(0.0).cast_ray(ray, depth)
+    }
+
+    /// Rays are followed up to this distance.
+    pub fn max_distance(&self) -> f32 {
+        self.max_distance
+    }
+
+    /// Color of rays that leave the scene.
+    pub fn background(&self, ray: &Ray<f32>) -> Vector3<f32> {
+        (self.background_color)(ray)
+    }
+
+    /// Finds the closest object hit by the ray within `max_toi`.
+    pub fn closest_hit(&self, ray: &Ray<f32>, max_toi: f32) -> Option<Hit<'_>> {
+        self.snapshot(0.0).closest_hit(ray, max_toi)
+    }
+
+    /// Checks if any object intersects the ray within `max_toi`. Cheaper than
+    /// `closest_hit`, meant for shadow rays.
+    pub fn occluded(&self, ray: &Ray<f32>, max_toi: f32) -> bool {
+        self.snapshot(0.0).occluded(ray, max_toi)
+    }
+
+    /// Visits every object in the scene, including all copies placed through
+    /// instances, with its transformation to world space at the scene's time.
+    pub fn for_each_object(&self, mut f: impl FnMut(&SceneObject, &Similarity3<f32>)) {
+        self.root
+            .for_each_object(&Similarity3::identity(), Time::at_frame(self.time), &mut f);
+    }
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn time(&self) -> Time {
+        self.time
+    }
+
+    /// Moves a ray from camera space to world space.
+    pub fn view_ray(&self, ray: &Ray<f32>) -> Ray<f32> {
+        match &self.scene.camera_track {
+            Some(track) => {
+                let transform = track.sample(self.time.frames());
+                Ray::new(
+                    transform.transform_point(&ray.origin),
+                    transform.transform_vector(&ray.dir),
+                )
+            }
+            None => *ray,
+        }
+    }
+
     pub fn cast_ray(&self, ray: &Ray<f32>, depth: usize) -> Vector3<f32> {
+        self.trace(ray, depth, &mut 0)
+    }
+
+    /// Path tracing as in `cast_ray`, counting the surface and medium
+    /// interactions along the path in `bounces`.
+    pub(crate) fn trace(&self, ray: &Ray<f32>, depth: usize, bounces: &mut usize) -> Vector3<f32> {
         if depth == 0 {
             return Vector3::new(0.0, 0.0, 0.0);
         }
 
-        let mut intersections: Vec<_> = self
-            .world
-            .interferences_with_ray(ray, self.max_distance, &self.collision_group)
-            .filter(|(_handle, _obj, collision)| collision.toi > EPSILON)
-            .collect();
-
-        intersections.sort_unstable_by(|a, b| a.2.toi.partial_cmp(&b.2.toi).unwrap());
-
-        for (_handle, obj, collision) in intersections {
-            let texture = obj.data();
-            let point_of_impact = ray.origin + collision.toi * ray.dir;
-            let normal = collision.normal;
-            let light_in = match texture.reflection_type {
-                ReflectionType::Lambert => {
-                    let mut new_ray = lambertian_reflection(&point_of_impact, &normal);
-                    if let Some(fuzz) = texture.fuzz() {
-                        new_ray.dir = new_ray.dir.normalize() + fuzz;
-                    }
-                    self.cast_ray(&new_ray, depth - 1)
+        let hit = self.closest_hit(ray, self.scene.max_distance);
+        let surface_toi = hit.as_ref().map_or(self.scene.max_distance, |hit| hit.toi);
+        if let Some(scattering) = self.sample_media(ray, surface_toi) {
+            *bounces += 1;
+            let new_ray = Ray::new(
+                ray.point_at(scattering.toi),
+                scattering.phase.sample(&ray.dir),
+            );
+            return scattering
+                .weight
+                .component_mul(&self.trace(&new_ray, depth - 1, bounces));
+        }
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return self.scene.background(ray),
+        };
+        *bounces += 1;
+        let texture = hit.texture;
+        let point_of_impact = ray.origin + hit.toi * ray.dir;
+        let normal = hit.normal;
+        let light_in = match texture.reflection_type {
+            ReflectionType::Lambert => {
+                let mut new_ray = lambertian_reflection(&point_of_impact, &normal);
+                if let Some(fuzz) = texture.fuzz() {
+                    new_ray.dir = new_ray.dir.normalize() + fuzz;
                 }
-                ReflectionType::Metal => {
-                    let mut new_ray = mirror_reflection(&ray.dir, &point_of_impact, &normal);
-                    if let Some(fuzz) = texture.fuzz() {
-                        new_ray.dir = new_ray.dir.normalize() + fuzz;
-                    }
-                    self.cast_ray(&new_ray, depth - 1)
+                self.trace(&new_ray, depth - 1, bounces)
+                    + self.direct_light(&point_of_impact, &normal)
+            }
+            ReflectionType::Metal => {
+                let mut new_ray = mirror_reflection(&ray.dir, &point_of_impact, &normal);
+                if let Some(fuzz) = texture.fuzz() {
+                    new_ray.dir = new_ray.dir.normalize() + fuzz;
                 }
-                ReflectionType::Absorb => Vector3::new(0.0, 0.0, 0.0),
-                ReflectionType::LightSource => texture.color(),
+                self.trace(&new_ray, depth - 1, bounces)
+            }
+            ReflectionType::Absorb => Vector3::new(0.0, 0.0, 0.0),
+            ReflectionType::LightSource => texture.color(),
+        };
+        texture.shade(light_in)
+    }
+
+    /// Rays are followed up to this distance.
+    pub fn max_distance(&self) -> f32 {
+        self.scene.max_distance
+    }
+
+    /// Color of rays that leave the scene.
+    pub fn background(&self, ray: &Ray<f32>) -> Vector3<f32> {
+        self.scene.background(ray)
+    }
+
+    /// Finds the closest object hit by the ray within `max_toi`.
+    pub fn closest_hit(&self, ray: &Ray<f32>, max_toi: f32) -> Option<Hit<'a>> {
+        self.scene.root.closest_hit(ray, max_toi, self.time)
+    }
+
+    /// Light reflected by a diffuse surface from all lights that are not
+    /// blocked by other objects.
+    pub(crate) fn direct_light(&self, point: &Point3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+        let mut total = Vector3::new(0.0, 0.0, 0.0);
+        let normal = normal.normalize();
+        for light in self.scene.lights.iter() {
+            let sample = match light.illuminate(point) {
+                Some(sample) => sample,
+                None => continue,
             };
-            return texture.color_strength() * texture.color() * light_in.norm()
-                + texture.reflective_strength() * light_in;
+            let cos = sample.to_light.dot(&normal);
+            if cos <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray::new(*point, sample.to_light);
+            if !self.occluded(&shadow_ray, sample.distance.min(self.scene.max_distance)) {
+                total += sample.irradiance * cos / PI;
+            }
         }
-        (self.background_color)(ray)
+        total
+    }
+
+    /// Finds the first scattering event in any medium before `max_toi`.
+    fn sample_media(&self, ray: &Ray<f32>, max_toi: f32) -> Option<Scattering<'a>> {
+        let fog = self
+            .scene
+            .fog
+            .iter()
+            .filter_map(|fog| fog.sample(ray, max_toi));
+        let volumes = self
+            .scene
+            .volumes
+            .iter()
+            .filter_map(|volume| volume.sample(ray, max_toi));
+        fog.chain(volumes)
+            .min_by(|a, b| a.toi.partial_cmp(&b.toi).unwrap())
+    }
+
+    /// Checks if any object intersects the ray within `max_toi`. Cheaper than
+    /// `closest_hit`, meant for shadow rays.
+    pub fn occluded(&self, ray: &Ray<f32>, max_toi: f32) -> bool {
+        self.scene.root.occluded(ray, max_toi, self.time)
     }
 }
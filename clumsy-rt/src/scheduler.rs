@@ -0,0 +1,169 @@
+//! Spreads the pixels of a tile over several threads.
+//!
+//! The tile is cut into blocks and each thread takes the next block as soon
+//! as it finished the previous one, so threads that hit cheap blocks (sky)
+//! simply render more of them. With the `threads` feature, the threads are
+//! tasks on rayon's pool instead. That is also how threads are available in
+//! the browser, where the pool runs on web workers.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use crate::{Camera, PixelPlane, Scene};
+
+/// Edge length of blocks. Wide filters compute an apron around each block,
+/// which gets expensive for much smaller blocks.
+const BLOCK_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+}
+
+/// Same as `Camera::render_tile`, using up to `n_threads` threads. Calls
+/// `rows_done(n, out)` on this thread each time the first `n` rows of `out`
+/// are finished, until all are.
+pub(crate) fn render_blocks(
+    camera: &Camera,
+    scene: &Scene,
+    start_x: usize,
+    start_y: usize,
+    out: &mut PixelPlane,
+    n_threads: usize,
+    rows_done: &mut dyn FnMut(usize, &PixelPlane),
+) {
+    let blocks = blocks(out.w, out.h);
+    let render = |block: &Block| {
+        let mut pixels = PixelPlane::new(block.w, block.h);
+        camera.render_tile(scene, start_x + block.x, start_y + block.y, &mut pixels);
+        pixels
+    };
+    // blocks still missing in each band of rows
+    let blocks_per_band = out.w.div_ceil(BLOCK_SIZE);
+    let mut missing = vec![blocks_per_band; out.h.div_ceil(BLOCK_SIZE)];
+    let mut bands_done = 0;
+    let mut finish = |i: usize, pixels: PixelPlane| {
+        let block = blocks[i];
+        for y in 0..block.h {
+            for x in 0..block.w {
+                out.set_pixel(block.x + x, block.y + y, pixels.pixel(x, y));
+            }
+        }
+        missing[block.y / BLOCK_SIZE] -= 1;
+        let first_missing = bands_done;
+        while missing.get(bands_done) == Some(&0) {
+            bands_done += 1;
+        }
+        if bands_done > first_missing {
+            rows_done((bands_done * BLOCK_SIZE).min(out.h), out);
+        }
+    };
+    if n_threads <= 1 || blocks.len() <= 1 {
+        for (i, block) in blocks.iter().enumerate() {
+            finish(i, render(block));
+        }
+    } else {
+        parallel_for_each(&blocks, n_threads, render, &mut finish);
+    }
+}
+
+fn blocks(w: usize, h: usize) -> Vec<Block> {
+    let mut blocks = vec![];
+    for y in (0..h).step_by(BLOCK_SIZE) {
+        for x in (0..w).step_by(BLOCK_SIZE) {
+            blocks.push(Block {
+                x,
+                y,
+                w: BLOCK_SIZE.min(w - x),
+                h: BLOCK_SIZE.min(h - y),
+            });
+        }
+    }
+    blocks
+}
+
+/// Maps all items with `n_threads` tasks on rayon's global pool, which share
+/// one queue. Other renders on the same pool keep their share of threads.
+/// Results are passed to `done` on this thread, in the order they finish.
+#[cfg(feature = "threads")]
+fn parallel_for_each<T: Sync, R: Send>(
+    items: &[T],
+    n_threads: usize,
+    f: impl Fn(&T) -> R + Sync,
+    done: &mut dyn FnMut(usize, R),
+) {
+    use rayon::prelude::*;
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    rayon::in_place_scope(|s| {
+        s.spawn(|_| {
+            (0..n_threads.min(items.len()))
+                .into_par_iter()
+                .for_each_with(tx, |tx, _| take_all(&next, items, &f, tx));
+        });
+        for (i, result) in rx {
+            done(i, result);
+        }
+    });
+}
+
+/// Maps all items on `n_threads` scoped threads that share one queue.
+/// Results are passed to `done` on this thread, in the order they finish.
+#[cfg(not(feature = "threads"))]
+fn parallel_for_each<T: Sync, R: Send>(
+    items: &[T],
+    n_threads: usize,
+    f: impl Fn(&T) -> R + Sync,
+    done: &mut dyn FnMut(usize, R),
+) {
+    use std::thread;
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..n_threads.min(items.len()) {
+            let (next, f, tx) = (&next, &f, tx.clone());
+            s.spawn(move || take_all(next, items, f, &tx));
+        }
+        drop(tx);
+        for (i, result) in rx {
+            done(i, result);
+        }
+    });
+}
+
+/// Maps items from the shared queue until it is empty, sending each result
+/// with its index.
+fn take_all<T, R>(
+    next: &AtomicUsize,
+    items: &[T],
+    f: impl Fn(&T) -> R,
+    tx: &mpsc::Sender<(usize, R)>,
+) {
+    loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        match items.get(i) {
+            // the receiver only hangs up when it panicked
+            Some(item) => {
+                if tx.send((i, f(item))).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+}
+
+#[test]
+fn blocks_cover_the_tile() {
+    let blocks = blocks(70, 33);
+    assert_eq!(blocks.len(), 3 * 2);
+    let area: usize = blocks.iter().map(|b| b.w * b.h).sum();
+    assert_eq!(area, 70 * 33);
+    let last = blocks.last().unwrap();
+    assert_eq!((last.x, last.y, last.w, last.h), (64, 32, 6, 1));
+}
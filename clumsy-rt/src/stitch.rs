@@ -0,0 +1,282 @@
+//! Assembles a full frame from the results of render jobs.
+//!
+//! Each job result is a PNG of the job's tile. The stitcher places it at the
+//! job's position and keeps count of how often each pixel was written, to
+//! report regions that are missing or covered by more than one job.
+
+use crate::PixelPlane;
+use api::RenderJob;
+use std::fmt;
+use thiserror::Error;
+
+/// Collects job results for one frame.
+pub struct Stitcher {
+    image: PixelPlane,
+    /// Number of jobs that wrote each pixel.
+    coverage: Vec<u32>,
+}
+
+/// Rectangle in output coordinates, y pointing down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum StitchError {
+    #[error("no job results to stitch")]
+    NoJobs,
+    #[error("job {job} is for a {}x{} frame, expected {}x{}", .actual.0, .actual.1, .expected.0, .expected.1)]
+    FrameMismatch {
+        job: String,
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    #[error("job {job} reaches outside of the frame")]
+    OutOfFrame { job: String },
+    #[error("image of job {job} is {}x{}, expected {}x{}", .actual.0, .actual.1, .expected.0, .expected.1)]
+    SizeMismatch {
+        job: String,
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    #[error("cannot decode image of job {job}")]
+    Decode {
+        job: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", coverage_message(.missing, .overlapping))]
+    Coverage {
+        missing: Vec<Region>,
+        overlapping: Vec<Region>,
+    },
+}
+
+impl Stitcher {
+    pub fn new(camera_w: usize, camera_h: usize) -> Self {
+        Self {
+            image: PixelPlane::new(camera_w, camera_h),
+            coverage: vec![0; camera_w * camera_h],
+        }
+    }
+
+    /// Places the PNG image produced for `job`.
+    pub fn add(&mut self, job: &RenderJob, png: &[u8]) -> Result<(), StitchError> {
+        let tile = PixelPlane::read_png(png).map_err(|source| StitchError::Decode {
+            job: job.to_string(),
+            source,
+        })?;
+        self.add_pixels(job, &tile)
+    }
+
+    /// Places an already decoded image produced for `job`.
+    pub fn add_pixels(&mut self, job: &RenderJob, tile: &PixelPlane) -> Result<(), StitchError> {
+        let frame = (self.image.w, self.image.h);
+        let job_frame = (job.camera_w as usize, job.camera_h as usize);
+        if job_frame != frame {
+            return Err(StitchError::FrameMismatch {
+                job: job.to_string(),
+                expected: frame,
+                actual: job_frame,
+            });
+        }
+        let (x0, y0, w, h) = (
+            job.x as usize,
+            job.y as usize,
+            job.w as usize,
+            job.h as usize,
+        );
+        if x0 + w > frame.0 || y0 + h > frame.1 {
+            return Err(StitchError::OutOfFrame {
+                job: job.to_string(),
+            });
+        }
+        if (tile.w, tile.h) != (w, h) {
+            return Err(StitchError::SizeMismatch {
+                job: job.to_string(),
+                expected: (w, h),
+                actual: (tile.w, tile.h),
+            });
+        }
+        for y in 0..h {
+            for x in 0..w {
+                self.image.set_pixel(x0 + x, y0 + y, tile.pixel(x, y));
+                self.coverage[(y0 + y) * frame.0 + x0 + x] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Regions that no job has covered so far.
+    pub fn missing(&self) -> Vec<Region> {
+        self.regions(|count| count == 0)
+    }
+
+    /// Regions covered by more than one job.
+    pub fn overlapping(&self) -> Vec<Region> {
+        self.regions(|count| count > 1)
+    }
+
+    /// The assembled frame, if every pixel was covered exactly once.
+    pub fn finish(self) -> Result<PixelPlane, StitchError> {
+        let missing = self.missing();
+        let overlapping = self.overlapping();
+        if missing.is_empty() && overlapping.is_empty() {
+            Ok(self.image)
+        } else {
+            Err(StitchError::Coverage {
+                missing,
+                overlapping,
+            })
+        }
+    }
+
+    /// Groups matching pixels into rectangles: runs within a row, merged
+    /// with the identical run of the row above.
+    fn regions(&self, matches: impl Fn(u32) -> bool) -> Vec<Region> {
+        let w = self.image.w;
+        let mut done: Vec<Region> = vec![];
+        let mut open: Vec<Region> = vec![];
+        for y in 0..self.image.h {
+            let row = &self.coverage[y * w..(y + 1) * w];
+            let mut runs = vec![];
+            let mut x = 0;
+            while x < w {
+                if matches(row[x]) {
+                    let start = x;
+                    while x < w && matches(row[x]) {
+                        x += 1;
+                    }
+                    runs.push((start, x - start));
+                } else {
+                    x += 1;
+                }
+            }
+            let mut still_open = vec![];
+            for region in open.drain(..) {
+                if let Some(i) = runs.iter().position(|&run| run == (region.x, region.w)) {
+                    runs.remove(i);
+                    still_open.push(Region {
+                        h: region.h + 1,
+                        ..region
+                    });
+                } else {
+                    done.push(region);
+                }
+            }
+            still_open.extend(runs.into_iter().map(|(x, w)| Region { x, y, w, h: 1 }));
+            open = still_open;
+        }
+        done.extend(open);
+        done.sort_by_key(|r| (r.y, r.x));
+        done
+    }
+}
+
+/// Stitches job results into one frame, with the frame size taken from the
+/// first job.
+pub fn stitch<'a>(
+    results: impl IntoIterator<Item = (&'a RenderJob, &'a [u8])>,
+) -> Result<PixelPlane, StitchError> {
+    let mut results = results.into_iter().peekable();
+    let (first, _) = results.peek().ok_or(StitchError::NoJobs)?;
+    let mut stitcher = Stitcher::new(first.camera_w as usize, first.camera_h as usize);
+    for (job, png) in results {
+        stitcher.add(job, png)?;
+    }
+    stitcher.finish()
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{} at ({}, {})", self.w, self.h, self.x, self.y)
+    }
+}
+
+fn coverage_message(missing: &[Region], overlapping: &[Region]) -> String {
+    let list = |regions: &[Region]| {
+        regions
+            .iter()
+            .map(Region::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match (missing.is_empty(), overlapping.is_empty()) {
+        (false, true) => format!("missing {}", list(missing)),
+        (true, false) => format!("overlapping {}", list(overlapping)),
+        _ => format!(
+            "missing {}; overlapping {}",
+            list(missing),
+            list(overlapping)
+        ),
+    }
+}
+
+#[test]
+fn stitched_jobs_equal_a_single_job() {
+    use crate::RenderJobExt;
+    let whole = RenderJob::new(0, 0, 24, 16, 24, 16, 1, 2);
+    let expected = PixelPlane::read_png(whole.render().as_slice()).unwrap();
+
+    let jobs = [
+        RenderJob::new(0, 0, 10, 16, 24, 16, 1, 2),
+        RenderJob::new(10, 0, 14, 7, 24, 16, 1, 2),
+        RenderJob::new(10, 7, 14, 9, 24, 16, 1, 2),
+    ];
+    let images: Vec<Vec<u8>> = jobs.iter().map(|job| job.render()).collect();
+    let stitched = stitch(jobs.iter().zip(images.iter().map(Vec::as_slice))).unwrap();
+    for y in 0..16 {
+        for x in 0..24 {
+            assert_eq!(stitched.pixel(x, y).col, expected.pixel(x, y).col);
+        }
+    }
+}
+
+#[test]
+fn stitcher_reports_missing_and_overlapping_regions() {
+    let mut stitcher = Stitcher::new(8, 6);
+    let left = RenderJob::new(0, 0, 5, 6, 8, 6, 1, 1);
+    let right = RenderJob::new(4, 0, 4, 3, 8, 6, 1, 1);
+    stitcher.add_pixels(&left, &PixelPlane::new(5, 6)).unwrap();
+    stitcher.add_pixels(&right, &PixelPlane::new(4, 3)).unwrap();
+
+    assert_eq!(
+        stitcher.missing(),
+        vec![Region {
+            x: 5,
+            y: 3,
+            w: 3,
+            h: 3
+        }]
+    );
+    assert_eq!(
+        stitcher.overlapping(),
+        vec![Region {
+            x: 4,
+            y: 0,
+            w: 1,
+            h: 3
+        }]
+    );
+    let err = stitcher.finish().err().expect("frame is incomplete");
+    assert_eq!(
+        err.to_string(),
+        "missing 3x3 at (5, 3); overlapping 1x3 at (4, 0)"
+    );
+
+    let mut stitcher = Stitcher::new(8, 6);
+    let outside = RenderJob::new(6, 0, 4, 3, 8, 6, 1, 1);
+    assert!(matches!(
+        stitcher.add_pixels(&outside, &PixelPlane::new(4, 3)),
+        Err(StitchError::OutOfFrame { .. })
+    ));
+    let other_frame = RenderJob::new(0, 0, 4, 3, 16, 12, 1, 1);
+    assert!(matches!(
+        stitcher.add_pixels(&other_frame, &PixelPlane::new(4, 3)),
+        Err(StitchError::FrameMismatch { .. })
+    ));
+}
@@ -1,6 +1,6 @@
 use super::*;
+use crate::random;
 use nalgebra::Vector3;
-use rand::Rng;
 
 pub struct Texture {
     /// Determines direction and weight of recursive rays
@@ -59,6 +59,10 @@ impl Texture {
     pub fn reflective_strength(&self) -> f32 {
         self.reflective_strength
     }
+    /// Outgoing light for the given incoming light.
+    pub fn shade(&self, light_in: Vector3<f32>) -> Vector3<f32> {
+        self.color_strength * self.color * light_in.norm() + self.reflective_strength * light_in
+    }
     pub fn with_fuzz(mut self, f: f32) -> Self {
         self.fuzz = Some(f);
         self
@@ -69,9 +73,8 @@ impl Texture {
 }
 
 fn random_fuzz(max: f32) -> Vector3<f32> {
-    let mut rng = rand::thread_rng();
-    let x = rng.gen::<f32>() * 2.0 * max - max;
-    let y = rng.gen::<f32>() * 2.0 * max - max;
-    let z = rng.gen::<f32>() * 2.0 * max - max;
+    let x = random::unit() * 2.0 * max - max;
+    let y = random::unit() * 2.0 * max - max;
+    let z = random::unit() * 2.0 * max - max;
     Vector3::new(x, y, z)
 }
@@ -1,15 +1,344 @@
+//! JavaScript API of the ray tracer, used by `worker.js`.
+//!
+//! Invalid jobs and failing callbacks throw JS exceptions instead of
+//! panicking, so that the worker survives and can report the error. Jobs are
+//! checked against the limits set with `setLimits`, or the default
+//! `api::Limits`, before rendering.
+
 use crate::render_job::RenderJobExt;
-use api::RenderJob;
-use js_sys::Uint32Array;
-use wasm_bindgen::prelude::wasm_bindgen;
+use api::{
+    FilterKind, IntegratorKind, Limits, OutputFormat, ProjectionKind, RenderJob, RenderResult,
+    SceneKind,
+};
+use js_sys::{Function, Uint32Array, Uint8Array};
+use std::cell::Cell;
+use std::convert::TryFrom;
+use wasm_bindgen::prelude::*;
+
+/// Starts the pool of web workers used by `render`, exported to JS as
+/// `initThreadPool(n)`. Must be awaited once before the first `render`.
+#[cfg(all(feature = "web-threads", target_arch = "wasm32"))]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+/// Rows between two callbacks during rendering, unless specified.
+const DEFAULT_PROGRESS_ROWS: u32 = 16;
+
+thread_local! {
+    /// Limits that jobs rendered from JS are checked against.
+    static LIMITS: Cell<Limits> = Cell::new(Limits::default());
+}
+
+/// Sets the limits of jobs rendered from this thread, exported to JS as
+/// `setLimits(maxResolution, maxSamples, maxRecursion, maxRays)`, with
+/// `maxRays` as a `BigInt`.
+#[wasm_bindgen(js_name = setLimits)]
+pub fn set_limits(max_resolution: u32, max_samples: u32, max_recursion: u32, max_rays: u64) {
+    let limits = Limits::default()
+        .with_max_resolution(max_resolution)
+        .with_max_samples(max_samples)
+        .with_max_recursion(max_recursion)
+        .with_max_rays(max_rays);
+    LIMITS.with(|cell| cell.set(limits));
+}
+
+fn validate(job: &RenderJob) -> Result<(), JsError> {
+    Ok(job.validate_with(&LIMITS.with(Cell::get))?)
+}
+
+/// A render job, `RenderJob` in JS.
+#[wasm_bindgen(js_name = RenderJob)]
+pub struct JsRenderJob(RenderJob);
+
+/// Image and statistics of a finished job, `RenderResult` in JS.
+#[wasm_bindgen(js_name = RenderResult)]
+pub struct JsRenderResult(RenderResult);
+
+#[wasm_bindgen(js_class = RenderJob)]
+impl JsRenderJob {
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        camera_w: u32,
+        camera_h: u32,
+        n_samples: u32,
+        n_recursion: u32,
+    ) -> JsRenderJob {
+        JsRenderJob(RenderJob::new(
+            x,
+            y,
+            w,
+            h,
+            camera_w,
+            camera_h,
+            n_samples,
+            n_recursion,
+        ))
+    }
+
+    /// Parses the `Uint32Array` form of a job, as sent by the web-view.
+    #[wasm_bindgen(js_name = fromArray)]
+    pub fn from_array(array: &Uint32Array) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(RenderJob::try_from_slice(&array.to_vec())?))
+    }
+
+    /// Parses the `x/y/w/h/...` form of a job, as used in worker URLs.
+    #[wasm_bindgen(js_name = fromString)]
+    pub fn from_string(s: &str) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(s.parse()?))
+    }
+
+    #[wasm_bindgen(js_name = toArray)]
+    pub fn to_array(&self) -> Vec<u32> {
+        self.0.to_vec()
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    #[wasm_bindgen(js_name = withIntegrator)]
+    pub fn with_integrator(self, code: u32) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(
+            self.0.with_integrator(IntegratorKind::try_from(code)?),
+        ))
+    }
+
+    #[wasm_bindgen(js_name = withProjection)]
+    pub fn with_projection(self, code: u32) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(
+            self.0.with_projection(ProjectionKind::try_from(code)?),
+        ))
+    }
+
+    #[wasm_bindgen(js_name = withFilter)]
+    pub fn with_filter(self, code: u32) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(self.0.with_filter(FilterKind::try_from(code)?)))
+    }
+
+    #[wasm_bindgen(js_name = withFrame)]
+    pub fn with_frame(self, frame: u32) -> JsRenderJob {
+        JsRenderJob(self.0.with_frame(frame))
+    }
+
+    /// Shutter open from `open` for `shutter` thousandths of a frame.
+    #[wasm_bindgen(js_name = withShutter)]
+    pub fn with_shutter(self, open: u32, shutter: u32) -> JsRenderJob {
+        JsRenderJob(self.0.with_shutter(open, shutter))
+    }
+
+    /// Takes a `BigInt`.
+    #[wasm_bindgen(js_name = withSeed)]
+    pub fn with_seed(self, seed: u64) -> JsRenderJob {
+        JsRenderJob(self.0.with_seed(seed))
+    }
+
+    #[wasm_bindgen(js_name = withScene)]
+    pub fn with_scene(self, code: u32) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(self.0.with_scene(SceneKind::try_from(code)?)))
+    }
+
+    #[wasm_bindgen(js_name = withFormat)]
+    pub fn with_format(self, code: u32) -> Result<JsRenderJob, JsError> {
+        Ok(JsRenderJob(
+            self.0.with_format(OutputFormat::try_from(code)?),
+        ))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn x(&self) -> u32 {
+        self.0.x
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn y(&self) -> u32 {
+        self.0.y
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.0.w
+    }
 
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.0.h
+    }
+
+    #[wasm_bindgen(getter = cameraWidth)]
+    pub fn camera_width(&self) -> u32 {
+        self.0.camera_w
+    }
+
+    #[wasm_bindgen(getter = cameraHeight)]
+    pub fn camera_height(&self) -> u32 {
+        self.0.camera_h
+    }
+
+    /// Code of the output format, 0 for PNG.
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> u32 {
+        self.0.format as u32
+    }
+}
+
+#[wasm_bindgen(js_class = RenderResult)]
+impl JsRenderResult {
+    /// The rendered tile in the job's format, PNG by default, copied into a
+    /// new `Uint8Array`.
+    #[wasm_bindgen(getter)]
+    pub fn png(&self) -> Vec<u8> {
+        self.0.image.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.0.job.w
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.0.job.h
+    }
+
+    /// Number of camera samples traced for the tile.
+    #[wasm_bindgen(getter)]
+    pub fn samples(&self) -> f64 {
+        self.0.stats.samples as f64
+    }
+
+    #[wasm_bindgen(getter = durationMs)]
+    pub fn duration_ms(&self) -> u32 {
+        self.0.duration_ms
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn threads(&self) -> u32 {
+        self.0.stats.threads
+    }
+
+    /// The binary form of `api::RenderResult`, with the job and statistics
+    /// in front of the image, as expected by the web-view.
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.0.encode(&mut out);
+        out
+    }
+}
+
+/// Renders a job. If given, `progress(rowsDone, rowsTotal)` is called each
+/// time another `progressRows` rows are finished, 16 by default. Throws if
+/// the job is invalid or the callback throws.
+#[wasm_bindgen(js_name = renderJob)]
+pub fn render_job(
+    job: &JsRenderJob,
+    progress: Option<Function>,
+    progress_rows: Option<u32>,
+) -> Result<JsRenderResult, JsError> {
+    console_error_panic_hook::set_once();
+    let job = &job.0;
+    validate(job)?;
+    let start = js_sys::Date::now();
+    let threads = num_threads();
+
+    let png = match progress {
+        Some(callback) => {
+            let total = JsValue::from(job.h);
+            let mut error = None;
+            let png = job.render_with_progress(
+                threads,
+                progress_rows.unwrap_or(DEFAULT_PROGRESS_ROWS) as usize,
+                &mut |rows| {
+                    if error.is_none() {
+                        let rows = JsValue::from(rows as u32);
+                        error = callback.call2(&JsValue::NULL, &rows, &total).err();
+                    }
+                },
+            );
+            check_callback(error)?;
+            png
+        }
+        None => job.render_threaded(threads),
+    };
+    Ok(result(job, png, start, threads))
+}
+
+/// Renders a job and passes each block of finished rows to
+/// `onRows(y, height, png)` right away, `y` counted from the top of the tile.
+/// Blocks have `rowsPerBlock` rows, 16 by default. The result still holds
+/// the full tile. Throws if the job is invalid or the callback throws.
+#[wasm_bindgen(js_name = renderJobRows)]
+pub fn render_job_rows(
+    job: &JsRenderJob,
+    on_rows: &Function,
+    rows_per_block: Option<u32>,
+) -> Result<JsRenderResult, JsError> {
+    console_error_panic_hook::set_once();
+    let job = &job.0;
+    validate(job)?;
+    let start = js_sys::Date::now();
+    let threads = num_threads();
+
+    let mut error = None;
+    let png = job.render_row_blocks(
+        threads,
+        rows_per_block.unwrap_or(DEFAULT_PROGRESS_ROWS) as usize,
+        &mut |block| {
+            if error.is_none() {
+                let png = Uint8Array::from(block.png.as_slice());
+                error = on_rows
+                    .call3(&JsValue::NULL, &block.y.into(), &block.h.into(), &png)
+                    .err();
+            }
+        },
+    );
+    check_callback(error)?;
+    Ok(result(job, png, start, threads))
+}
+
+fn result(job: &RenderJob, image: Vec<u8>, start: f64, threads: usize) -> JsRenderResult {
+    let worker = if threads > 1 { "wasm-threads" } else { "wasm" };
+    JsRenderResult(
+        RenderResult::new(job.clone(), image)
+            .with_duration_ms((js_sys::Date::now() - start).round() as u32)
+            .with_stats(job.stats(threads))
+            .with_worker(worker),
+    )
+}
+
+fn check_callback(error: Option<JsValue>) -> Result<(), JsError> {
+    match error {
+        Some(err) => Err(JsError::new(&format!(
+            "callback failed: {}",
+            err.as_string()
+                .or_else(|| js_sys::Error::from(err).message().as_string())
+                .unwrap_or_default()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Renders a job in its `Uint32Array` form and returns the PNG. Throws if
+/// the job is invalid.
 #[wasm_bindgen]
-pub fn render(array: Uint32Array) -> Vec<u8> {
+pub fn render(array: Uint32Array) -> Result<Vec<u8>, JsError> {
     console_error_panic_hook::set_once();
-    let vec: Vec<u32> = Uint32Array::from(array).to_vec();
-    let job = RenderJob::try_from_slice(&vec).unwrap();
+    let job = RenderJob::try_from_slice(&array.to_vec())?;
+    validate(&job)?;
+    Ok(job.render_threaded(num_threads()))
+}
 
-    let result = job.render();
+#[cfg(feature = "web-threads")]
+fn num_threads() -> usize {
+    rayon::current_num_threads()
+}
 
-    result
+#[cfg(not(feature = "web-threads"))]
+fn num_threads() -> usize {
+    1
 }
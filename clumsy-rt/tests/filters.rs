@@ -0,0 +1,64 @@
+//! Tiles rendered with a reconstruction filter must stitch together into
+//! exactly the image of a full-frame render.
+
+use clumsy_rt::*;
+
+const W: usize = 32;
+const H: usize = 24;
+
+#[test]
+fn tiled_render_equals_full_frame() {
+    let scene = sample_scenes::build_cool_scene();
+    for filter in [
+        Filter::Box,
+        Filter::Tent { radius: 1.0 },
+        Filter::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        },
+        Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        },
+    ] {
+        let camera = Camera::new(4, 3, W, H).with_seed(36).with_filter(filter);
+        let mut whole = PixelPlane::new(W, H);
+        camera.render_tile(&scene, 0, 0, &mut whole);
+
+        let (tile_w, tile_h) = (W / 4, H / 4);
+        for ty in 0..4 {
+            for tx in 0..4 {
+                let (x0, y0) = (tx * tile_w, ty * tile_h);
+                let mut tile = PixelPlane::new(tile_w, tile_h);
+                camera.render_tile(&scene, x0, y0, &mut tile);
+                for y in 0..tile_h {
+                    for x in 0..tile_w {
+                        assert_eq!(
+                            tile.pixel(x, y).col,
+                            whole.pixel(x0 + x, y0 + y).col,
+                            "{filter:?}: pixel ({}, {}) differs",
+                            x0 + x,
+                            y0 + y
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn threaded_render_equals_tile_render() {
+    let scene = sample_scenes::build_cool_scene();
+    let camera = Camera::new(1, 2, W, H).with_filter(Filter::Tent { radius: 1.0 });
+    let mut tile = PixelPlane::new(W, H);
+    camera.render_tile(&scene, 0, 0, &mut tile);
+    let mut threaded = PixelPlane::new(W, H);
+    camera.render(scene, &mut threaded, 4);
+    for y in 0..H {
+        for x in 0..W {
+            assert_eq!(tile.pixel(x, y).col, threaded.pixel(x, y).col);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Golden-image regression tests.
+//!
+//! Each case renders a small scene with a fixed seed and compares the result
+//! against a reference PNG in `tests/golden/`. Small deviations are tolerated,
+//! measured as peak signal-to-noise ratio (PSNR), to allow for floating point
+//! differences between platforms.
+//!
+//! On failure, the rendered image and an amplified difference image are
+//! written to `target/tmp/golden/`.
+//!
+//! After an intended change of the rendering output, re-bless all references
+//! and bump `api::RENDER_VERSION`:
+//!
+//! ```bash
+//! CLUMSY_RT_BLESS=1 cargo test -p clumsy-rt --test golden
+//! ```
+
+use clumsy_rt::*;
+use std::path::{Path, PathBuf};
+
+/// Renders that score below this are considered different images.
+const MIN_PSNR_DB: f64 = 35.0;
+const SEED: u64 = 26;
+
+fn render(scene: Scene, n_samples: usize, n_recursion: usize, w: usize, h: usize) -> PixelPlane {
+    let camera = Camera::new(n_samples, n_recursion, w, h).with_seed(SEED);
+    let mut img = PixelPlane::new(w, h);
+    camera.render_tile(&scene, 0, 0, &mut img);
+    img
+}
+
+#[test]
+fn golden_simple_scene() {
+    let img = render(sample_scenes::build_simple_scene(), 4, 4, 64, 48);
+    check_golden("simple_scene", &img);
+}
+
+#[test]
+fn golden_cool_scene() {
+    let img = render(sample_scenes::build_cool_scene(), 4, 6, 64, 48);
+    check_golden("cool_scene", &img);
+}
+
+#[test]
+fn golden_cool_scene_single_sample() {
+    let img = render(sample_scenes::build_cool_scene(), 1, 2, 96, 72);
+    check_golden("cool_scene_single_sample", &img);
+}
+
+#[test]
+fn golden_hazy_scene() {
+    let img = render(sample_scenes::build_hazy_scene(), 4, 6, 64, 48);
+    check_golden("hazy_scene", &img);
+}
+
+#[test]
+fn golden_lights_scene() {
+    let img = render(sample_scenes::build_lights_scene(), 4, 4, 64, 48);
+    check_golden("lights_scene", &img);
+}
+
+#[test]
+fn golden_cool_scene_tile() {
+    let camera = Camera::new(4, 4, 128, 96).with_seed(SEED);
+    let mut img = PixelPlane::new(40, 30);
+    camera.render_tile(&sample_scenes::build_cool_scene(), 48, 20, &mut img);
+    check_golden("cool_scene_tile", &img);
+}
+
+fn check_golden(name: &str, actual: &PixelPlane) {
+    let reference_path = golden_dir().join(format!("{name}.png"));
+    if std::env::var_os("CLUMSY_RT_BLESS").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.export_png(&reference_path).unwrap();
+        return;
+    }
+    let expected = PixelPlane::import_png(&reference_path).unwrap_or_else(|e| {
+        panic!(
+            "cannot read reference {}: {e}\nrun with CLUMSY_RT_BLESS=1 to create it",
+            reference_path.display()
+        )
+    });
+    assert_eq!(
+        (expected.w, expected.h),
+        (actual.w, actual.h),
+        "{name}: image size differs from reference"
+    );
+    let psnr = psnr(&expected, actual);
+    if psnr < MIN_PSNR_DB {
+        let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let actual_path = out_dir.join(format!("{name}.actual.png"));
+        let diff_path = out_dir.join(format!("{name}.diff.png"));
+        actual.export_png(&actual_path).unwrap();
+        diff_image(&expected, actual)
+            .export_png(&diff_path)
+            .unwrap();
+        panic!(
+            "{name}: PSNR {psnr:.1} dB is below {MIN_PSNR_DB} dB\n  output: {}\n  diff:   {}",
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Peak signal-to-noise ratio in dB, infinite for identical images.
+fn psnr(a: &PixelPlane, b: &PixelPlane) -> f64 {
+    let mut squared_error = 0.0;
+    for y in 0..a.h {
+        for x in 0..a.w {
+            let d = a.pixel(x, y).col.cast::<f64>() - b.pixel(x, y).col.cast::<f64>();
+            squared_error += d.norm_squared();
+        }
+    }
+    let mse = squared_error / (a.w * a.h * 3) as f64;
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Per-channel absolute difference, amplified to make small errors visible.
+fn diff_image(a: &PixelPlane, b: &PixelPlane) -> PixelPlane {
+    const AMPLIFY: u8 = 8;
+    let mut out = PixelPlane::new(a.w, a.h);
+    for y in 0..a.h {
+        for x in 0..a.w {
+            let (pa, pb) = (a.pixel(x, y).col, b.pixel(x, y).col);
+            let diff = pa.zip_map(&pb, |ca, cb| ca.abs_diff(cb).saturating_mul(AMPLIFY));
+            out.set_pixel(x, y, Pixel { col: diff });
+        }
+    }
+    out
+}
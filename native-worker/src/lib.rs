@@ -0,0 +1,450 @@
+//! Render worker running natively instead of inside Spin.
+//!
+//! Serves the routes of the Spin component, `/ping`, `/capabilities`,
+//! `/<job>`, `/job`, `/batch` and `/rows`, with the same headers, so that the
+//! web-view can use either.
+//! Jobs are rendered on all cores and their results are cached.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use api::{
+    Capabilities, ErrorResponse, JobValidationError, Limits, LruCache, RenderBatch,
+    RenderBatchError, RenderJob, RenderJobParseError, RenderResult, ResultCache, RowBlock,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use clumsy_rt::{CachedRenderer, FsCache, RenderJobExt};
+use tokio::sync::Semaphore;
+
+#[macro_use]
+extern crate log;
+
+/// Identifies this worker in `api::RenderResult`.
+const WORKER_NAME: &str = "native";
+
+/// Rows per block of the `/rows/` route.
+const ROWS_PER_BLOCK: usize = 16;
+
+/// Results kept in memory when no cache directory is configured.
+const CACHED_RESULTS: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Address to listen on, `127.0.0.1:3000` by default, like `spin up`.
+    pub bind: SocketAddr,
+    /// Jobs rendered at the same time, further requests wait for a slot.
+    /// Each job uses all cores, more than one only helps to hide latency.
+    pub max_concurrent_jobs: usize,
+    /// Keeps results as files in this directory, otherwise only in memory.
+    pub cache_dir: Option<PathBuf>,
+    /// Largest jobs and batches that are rendered, also announced at
+    /// `/capabilities`.
+    pub limits: Limits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            max_concurrent_jobs: 2,
+            cache_dir: None,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Threads each job is rendered with, one per core.
+pub fn num_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Listens on `config.bind` and serves requests until the server fails.
+pub async fn serve(config: Config) -> std::io::Result<()> {
+    let app = router(&config)?;
+    axum::Server::try_bind(&config.bind)
+        .map_err(std::io::Error::other)?
+        .serve(app.into_make_service())
+        .await
+        .map_err(std::io::Error::other)
+}
+
+/// All routes of the worker, for use with any server.
+pub fn router(config: &Config) -> std::io::Result<Router> {
+    let worker = Worker::new(config)?;
+    Ok(Router::new()
+        .route("/ping", get(ping))
+        .route("/capabilities", get(capabilities))
+        .route(
+            "/job",
+            get(job_from_query).post(job_from_json).options(preflight),
+        )
+        .route("/batch", post(batch).options(preflight))
+        .route(
+            "/rows",
+            get(rows_from_query).post(rows_from_json).options(preflight),
+        )
+        .route("/rows/*job", get(rows_from_path))
+        .fallback(job_from_path)
+        .layer(middleware::map_response(allow_any_origin))
+        .with_state(Arc::new(worker)))
+}
+
+struct Worker {
+    renderer: CachedRenderer<Cache>,
+    /// One permit per job that may render at the same time.
+    jobs: Arc<Semaphore>,
+    n_threads: usize,
+    limits: Limits,
+    capabilities: Capabilities,
+}
+
+impl Worker {
+    fn new(config: &Config) -> std::io::Result<Self> {
+        let cache = match &config.cache_dir {
+            Some(dir) => Cache::Files(FsCache::new(dir)?),
+            None => Cache::Memory(LruCache::new(CACHED_RESULTS)),
+        };
+        let n_threads = num_threads();
+        Ok(Self {
+            renderer: CachedRenderer::new(cache),
+            jobs: Arc::new(Semaphore::new(config.max_concurrent_jobs.max(1))),
+            n_threads,
+            limits: config.limits,
+            capabilities: Capabilities::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))
+                .with_cores(n_threads as u32)
+                .with_batch(true)
+                .with_limits(config.limits),
+        })
+    }
+
+    /// Cached results of `jobs`, looked up off the async threads since the
+    /// cache may read files. Hits are served without waiting for a slot.
+    async fn cached(
+        self: &Arc<Self>,
+        jobs: Vec<RenderJob>,
+    ) -> Result<Vec<Option<RenderResult>>, Error> {
+        let worker = self.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            jobs.iter()
+                .map(|job| worker.renderer.cache().get(job))
+                .collect::<Vec<_>>()
+        })
+        .await?;
+        for result in results.iter().flatten() {
+            info!("{} served from cache", result.summary());
+        }
+        Ok(results)
+    }
+
+    /// Serves `job` from the cache or renders it, blocking until it is done.
+    fn render_cached(&self, job: &RenderJob) -> RenderResult {
+        let (result, cached) = self.renderer.get_or_render(job, || {
+            let dt = std::time::Instant::now();
+            let image = job.render_threaded(self.n_threads);
+            RenderResult::new(job.clone(), image)
+                .with_duration_ms(dt.elapsed().as_millis().try_into().unwrap_or(u32::MAX))
+                .with_stats(job.stats(self.n_threads))
+                .with_worker(WORKER_NAME)
+        });
+        let origin = if cached { "served from cache" } else { "done" };
+        info!("{} {origin}", result.summary());
+        result
+    }
+}
+
+enum Cache {
+    Memory(LruCache),
+    Files(FsCache),
+}
+
+impl ResultCache for Cache {
+    fn get(&self, job: &RenderJob) -> Option<RenderResult> {
+        match self {
+            Cache::Memory(cache) => cache.get(job),
+            Cache::Files(cache) => cache.get(job),
+        }
+    }
+
+    fn put(&self, result: &RenderResult) {
+        match self {
+            Cache::Memory(cache) => cache.put(result),
+            Cache::Files(cache) => cache.put(result),
+        }
+    }
+}
+
+/// A failed request, answered with the status and JSON body of the
+/// `api::ErrorResponse`.
+struct Error(ErrorResponse);
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let Error(err) = self;
+        let status = StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_string(&err).unwrap_or_default();
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, ErrorResponse::CONTENT_TYPE)],
+            body,
+        )
+            .into_response();
+        if let Some(allow) = err.allow {
+            response
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static(allow));
+        }
+        response
+    }
+}
+
+impl From<ErrorResponse> for Error {
+    fn from(err: ErrorResponse) -> Self {
+        Error(err)
+    }
+}
+
+impl From<RenderJobParseError> for Error {
+    fn from(err: RenderJobParseError) -> Self {
+        Error(err.into())
+    }
+}
+
+impl From<JobValidationError> for Error {
+    fn from(err: JobValidationError) -> Self {
+        Error(err.into())
+    }
+}
+
+impl From<RenderBatchError> for Error {
+    fn from(err: RenderBatchError) -> Self {
+        Error(err.into())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Self {
+        error!("rendering failed: {err}");
+        Error(ErrorResponse::internal())
+    }
+}
+
+/// Every response may be read by pages of any origin.
+async fn allow_any_origin(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+async fn ping() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain")], "pong")
+}
+
+async fn capabilities(State(worker): State<Arc<Worker>>) -> Json<Capabilities> {
+    Json(worker.capabilities.clone())
+}
+
+/// Answers CORS preflight requests, which browsers send before posting JSON.
+async fn preflight() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, OPTIONS"),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type"),
+        ],
+    )
+}
+
+/// Renders jobs given as path, other paths are unknown routes.
+async fn job_from_path(
+    State(worker): State<Arc<Worker>>,
+    method: Method,
+    uri: Uri,
+) -> Result<Response, Error> {
+    if method != Method::GET && method != Method::POST {
+        return Err(ErrorResponse::method_not_allowed("GET, POST").into());
+    }
+    let path = uri.path();
+    let job = path
+        .parse()
+        .map_err(|err| ErrorResponse::for_path(path, err))?;
+    render(worker, job).await
+}
+
+async fn job_from_query(
+    State(worker): State<Arc<Worker>>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, Error> {
+    render(worker, RenderJob::from_query(&query.unwrap_or_default())?).await
+}
+
+async fn job_from_json(State(worker): State<Arc<Worker>>, body: Bytes) -> Result<Response, Error> {
+    render(worker, RenderJob::from_json(&body)?).await
+}
+
+/// Renders `job`, or serves it from the cache, with the result in headers.
+async fn render(worker: Arc<Worker>, job: RenderJob) -> Result<Response, Error> {
+    job.validate_with(&worker.limits)?;
+    let result = match worker.cached(vec![job.clone()]).await?.pop().flatten() {
+        Some(result) => result,
+        None => {
+            let _permit = worker.jobs.clone().acquire_owned().await.unwrap();
+            tokio::task::spawn_blocking(move || worker.render_cached(&job)).await?
+        }
+    };
+
+    let headers = result.headers();
+    let mut response = result.image.into_response();
+    insert_metadata(&mut response, &headers)?;
+    Ok(response)
+}
+
+/// Adds the headers of a result to `response`, readable by pages of any
+/// origin.
+fn insert_metadata(
+    response: &mut Response,
+    headers: &[(&'static str, String)],
+) -> Result<(), Error> {
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(RenderResult::EXPOSED_HEADERS),
+    );
+    for (name, value) in headers {
+        let value = HeaderValue::try_from(value).map_err(|_| {
+            error!("invalid {name} header: {value}");
+            Error(ErrorResponse::internal())
+        })?;
+        response_headers.insert(*name, value);
+    }
+    Ok(())
+}
+
+/// Renders the jobs of a batch one after the other, with a single slot that
+/// is only taken if some job is not cached. The batch is plain text or, with
+/// a JSON `Content-Type`, a list of jobs.
+async fn batch(
+    State(worker): State<Arc<Worker>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let batch = if is_json {
+        RenderBatch::from_json(&body)?
+    } else {
+        std::str::from_utf8(&body)
+            .map_err(|err| ErrorResponse::bad_request(format!("invalid batch: {err}")))?
+            .parse()?
+    };
+    batch.validate_with(&worker.limits)?;
+    let cached = worker.cached(batch.jobs.clone()).await?;
+    let _permit = if cached.iter().all(Option::is_some) {
+        None
+    } else {
+        Some(worker.jobs.clone().acquire_owned().await.unwrap())
+    };
+    let body = tokio::task::spawn_blocking(move || {
+        let results: Vec<_> = batch
+            .jobs
+            .iter()
+            .zip(cached)
+            .map(|(job, cached)| cached.unwrap_or_else(|| worker.render_cached(job)))
+            .collect();
+        let mut body = vec![];
+        RenderBatch::encode_results(&results, &mut body);
+        body
+    })
+    .await?;
+    Ok((
+        [(header::CONTENT_TYPE, RenderBatch::RESULTS_CONTENT_TYPE)],
+        body,
+    )
+        .into_response())
+}
+
+async fn rows_from_path(
+    State(worker): State<Arc<Worker>>,
+    Path(job): Path<String>,
+) -> Result<Response, Error> {
+    render_rows(worker, job.parse()?).await
+}
+
+async fn rows_from_query(
+    State(worker): State<Arc<Worker>>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, Error> {
+    render_rows(worker, RenderJob::from_query(&query.unwrap_or_default())?).await
+}
+
+async fn rows_from_json(State(worker): State<Arc<Worker>>, body: Bytes) -> Result<Response, Error> {
+    render_rows(worker, RenderJob::from_json(&body)?).await
+}
+
+/// Renders the job in blocks of rows. They are sent together once the tile
+/// is done, like the Spin component does.
+async fn render_rows(worker: Arc<Worker>, job: RenderJob) -> Result<Response, Error> {
+    job.validate_with(&worker.limits)?;
+    let _permit = worker.jobs.clone().acquire_owned().await.unwrap();
+    let (result, body) = tokio::task::spawn_blocking(move || {
+        let dt = std::time::Instant::now();
+        let mut bytes = vec![];
+        job.for_each_row_block(worker.n_threads, ROWS_PER_BLOCK, &mut |block| {
+            block.encode(&mut bytes)
+        });
+        let result = RenderResult::new(job.clone(), vec![])
+            .with_duration_ms(dt.elapsed().as_millis().try_into().unwrap_or(u32::MAX))
+            .with_stats(job.stats(worker.n_threads))
+            .with_worker(WORKER_NAME);
+        info!("{} done in row blocks", result.summary());
+        (result, bytes)
+    })
+    .await?;
+    let mut response = ([(header::CONTENT_TYPE, RowBlock::CONTENT_TYPE)], body).into_response();
+    insert_metadata(&mut response, &result.metadata_headers())?;
+    Ok(response)
+}
+
+#[tokio::test]
+async fn renders_wait_for_a_free_slot() {
+    let max_concurrent_jobs = 2;
+    let worker = Arc::new(
+        Worker::new(&Config {
+            max_concurrent_jobs,
+            ..Config::default()
+        })
+        .unwrap(),
+    );
+    // take every slot, like jobs that are still rendering
+    let slots = worker
+        .jobs
+        .clone()
+        .acquire_many_owned(max_concurrent_jobs as u32)
+        .await
+        .unwrap();
+    assert_eq!(worker.jobs.available_permits(), 0);
+
+    let job = RenderJob::new(0, 0, 1, 1, 8, 8, 1, 1);
+    let waiting = tokio::spawn(render(worker.clone(), job));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(!waiting.is_finished());
+
+    drop(slots);
+    let response = waiting
+        .await
+        .unwrap()
+        .unwrap_or_else(IntoResponse::into_response);
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,35 @@
+use native_worker::Config;
+
+#[macro_use]
+extern crate log;
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let mut config = Config::default();
+    if let Ok(bind) = std::env::var("BIND_ADDR") {
+        config.bind = bind.parse().expect("invalid BIND_ADDR");
+    }
+    if let Ok(n) = std::env::var("MAX_CONCURRENT_JOBS") {
+        config.max_concurrent_jobs = n.parse().expect("invalid MAX_CONCURRENT_JOBS");
+    }
+    if let Ok(dir) = std::env::var("CACHE_DIR") {
+        config.cache_dir = Some(dir.into());
+    }
+    if let Ok(json) = std::env::var("LIMITS") {
+        config.limits = serde_json::from_str(&json).expect("invalid LIMITS");
+    }
+
+    println!("Starting render worker on http://{}", config.bind);
+    info!(
+        "{} jobs at a time on {} threads",
+        config.max_concurrent_jobs,
+        native_worker::num_threads()
+    );
+
+    if let Err(err) = native_worker::serve(config).await {
+        eprintln!("render worker failed: {err}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,238 @@
+//! Starts the worker in-process and talks to it over localhost.
+
+use std::net::{SocketAddr, TcpListener};
+
+use api::{Capabilities, Limits, RenderBatch, RenderJob, RenderResult, RowBlock};
+use hyper::{body::to_bytes, Body, Client, Method, Request, Response, StatusCode};
+use native_worker::Config;
+
+/// Serves the worker on a free port until the test ends.
+fn start(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = native_worker::router(&config).unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+    addr
+}
+
+async fn send(addr: SocketAddr, method: Method, path: &str, body: &str) -> Response<Body> {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{addr}{path}"))
+        .body(Body::from(body.to_owned()))
+        .unwrap();
+    Client::new().request(request).await.unwrap()
+}
+
+async fn get(addr: SocketAddr, path: &str) -> Response<Body> {
+    send(addr, Method::GET, path, "").await
+}
+
+async fn result(response: Response<Body>) -> RenderResult {
+    assert_eq!(response.status(), StatusCode::OK);
+    let (parts, body) = response.into_parts();
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_owned())
+    };
+    assert_eq!(header("Access-Control-Allow-Origin").as_deref(), Some("*"));
+    assert_eq!(
+        header("Access-Control-Expose-Headers").as_deref(),
+        Some(RenderResult::EXPOSED_HEADERS)
+    );
+    let image = to_bytes(body).await.unwrap().to_vec();
+    RenderResult::from_headers(header, image).unwrap()
+}
+
+#[tokio::test]
+async fn answers_ping() {
+    let addr = start(Config::default());
+    let response = get(addr, "/ping").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    assert_eq!(to_bytes(response.into_body()).await.unwrap(), "pong");
+}
+
+#[tokio::test]
+async fn describes_capabilities() {
+    let addr = start(Config::default());
+    let response = get(addr, "/capabilities").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let capabilities: Capabilities = serde_json::from_slice(&body).unwrap();
+    assert_eq!(capabilities.worker, "native");
+    assert_eq!(capabilities.cores, native_worker::num_threads() as u32);
+    assert!(capabilities.batch);
+    assert!(capabilities.job_versions.contains(&RenderJob::VERSION));
+    assert_eq!(
+        capabilities.check_job(&RenderJob::new(0, 0, 8, 8, 8, 8, 1, 1)),
+        Ok(())
+    );
+}
+
+#[tokio::test]
+async fn renders_job_over_localhost() {
+    let addr = start(Config::default());
+    let job = RenderJob::new(4, 4, 8, 8, 16, 16, 1, 1).with_seed(3);
+
+    let rendered = result(get(addr, &format!("/{job}")).await).await;
+    rendered.check_job(&job).unwrap();
+    assert_eq!(rendered.worker, "native");
+    assert!(rendered.image.starts_with(b"\x89PNG"));
+
+    // the other forms of the same job are answered from the cache
+    let from_query = result(get(addr, &format!("/job?{}", job.to_query())).await).await;
+    assert_eq!(from_query, rendered);
+    let json = r#"{"x":4,"y":4,"w":8,"h":8,"camera_w":16,"camera_h":16,"samples":1,"recursion":1,"seed":3}"#;
+    let from_json = result(send(addr, Method::POST, "/job", json).await).await;
+    assert_eq!(from_json, rendered);
+}
+
+#[tokio::test]
+async fn renders_batches_in_order() {
+    let addr = start(Config::default());
+    let batch = RenderBatch::new(
+        (0..4)
+            .map(|i| RenderJob::new(4 * i, 0, 4, 4, 16, 4, 1, 1))
+            .collect(),
+    );
+    let response = send(addr, Method::POST, "/batch", &batch.to_string()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    assert_eq!(
+        response.headers()["Content-Type"],
+        RenderBatch::RESULTS_CONTENT_TYPE
+    );
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let results = batch.decode_results(&body).unwrap();
+    assert_eq!(results.len(), 4);
+
+    // a single tile of the batch is cached like any other
+    let single = result(get(addr, &format!("/{}", batch.jobs[2])).await).await;
+    assert_eq!(single, results[2]);
+
+    // or as JSON, with the jobs as objects
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{addr}/batch"))
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&batch).unwrap()))
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    let body = to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(batch.decode_results(&body).unwrap(), results);
+
+    let response = send(addr, Method::POST, "/batch", "0/0/4/4/16/4/1/1\n0/0").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = send(addr, Method::POST, "/batch", "").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn renders_row_blocks() {
+    let addr = start(Config {
+        max_concurrent_jobs: 1,
+        ..Config::default()
+    });
+    let job = RenderJob::new(0, 0, 4, 20, 4, 20, 1, 1);
+    let response = get(addr, &format!("/rows/{job}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], RowBlock::CONTENT_TYPE);
+    let (parts, body) = response.into_parts();
+    let header = |name: &str| {
+        (name != "Content-Type")
+            .then(|| parts.headers.get(name))
+            .flatten()
+            .map(|value| value.to_str().unwrap().to_owned())
+    };
+    let result = RenderResult::from_headers(header, vec![]).unwrap();
+    assert_eq!(result.job, job);
+    assert_eq!(result.worker, "native");
+    let body = to_bytes(body).await.unwrap();
+
+    let mut rest = &body[..];
+    let mut rows = vec![];
+    while let Some((block, len)) = RowBlock::decode(rest) {
+        rows.push((block.y, block.h));
+        rest = &rest[len..];
+    }
+    assert!(rest.is_empty());
+    assert_eq!(rows, vec![(0, 16), (16, 4)]);
+
+    let from_query = get(addr, &format!("/rows?{}", job.to_query())).await;
+    assert_eq!(to_bytes(from_query.into_body()).await.unwrap(), body);
+    let json = serde_json::to_string(&job).unwrap();
+    let from_json = send(addr, Method::POST, "/rows", &json).await;
+    assert_eq!(to_bytes(from_json.into_body()).await.unwrap(), body);
+}
+
+#[tokio::test]
+async fn uses_configured_limits() {
+    let limits = Limits::default().with_max_samples(2);
+    let addr = start(Config {
+        limits,
+        ..Config::default()
+    });
+    let body = to_bytes(get(addr, "/capabilities").await.into_body())
+        .await
+        .unwrap();
+    let capabilities: Capabilities = serde_json::from_slice(&body).unwrap();
+    assert_eq!(capabilities.limits, limits);
+
+    assert_eq!(get(addr, "/0/0/8/8/8/8/2/1").await.status(), StatusCode::OK);
+    for path in ["/0/0/8/8/8/8/3/1", "/rows/0/0/8/8/8/8/3/1"] {
+        let response = get(addr, path).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{path}");
+    }
+    let response = send(addr, Method::POST, "/batch", "0/0/8/8/8/8/3/1").await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn rejects_invalid_jobs_with_json_errors() {
+    let addr = start(Config::default());
+    for (path, status) in [
+        ("/not/a/job", StatusCode::NOT_FOUND),
+        ("/v1/0/0/8/8/8/8/1/1/!scene=99", StatusCode::NOT_FOUND),
+        ("/0/0/8/8/4/4/1/1", StatusCode::BAD_REQUEST),
+        (
+            "/job?x=0&y=0&w=8&h=8&camera_w=8&camera_h=8&samples=1",
+            StatusCode::BAD_REQUEST,
+        ),
+        ("/0/0/8/8/8/8/1/300", StatusCode::PAYLOAD_TOO_LARGE),
+    ] {
+        let response = get(addr, path).await;
+        assert_eq!(response.status(), status, "{path}");
+        assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], status.as_u16(), "{path}");
+    }
+    let response = send(addr, Method::POST, "/job", r#"{"x":0}"#).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("missing field `y`"));
+
+    for path in ["/0/0/8/8/8/8/1/1", "/garbage", "/0/0/8"] {
+        let response = send(addr, Method::PUT, path, "").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{path}");
+        assert_eq!(response.headers()["Allow"], "GET, POST");
+        assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    }
+
+    let response = send(addr, Method::OPTIONS, "/job", "").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+}
@@ -1,15 +1,49 @@
 use anyhow::Result;
-use clumsy_rt::RenderJobExt;
+use api::{
+    Capabilities, ErrorResponse, JobValidationError, Limits, RenderBatch, RenderBatchError,
+    RenderJob, RenderJobParseError, RenderResult, ResultCache,
+};
+use clumsy_rt::{CachedRenderer, RenderJobExt};
+use http::{header, HeaderValue, StatusCode};
 use spin_sdk::{
+    config,
     http::{Request, Response},
     http_component,
+    key_value::Store,
 };
+use std::fmt::Display;
 use std::str::FromStr;
 
+/// Identifies this worker in `api::RenderResult`.
+const WORKER_NAME: &str = "spin";
+
+/// Rows per block of the `/rows/` route.
+const ROWS_PER_BLOCK: usize = 16;
+
 /// A simple Spin HTTP component.
 #[http_component]
 fn handle_spin_component(req: Request) -> Result<Response> {
-    if req.uri().path() == "/ping" {
+    Ok(match limits() {
+        Ok(limits) => handle(&req, &limits, &CachedRenderer::new(KvCache::open())),
+        Err(err) => err.into_response(),
+    })
+}
+
+/// Answers `req` within `limits`, turning failures into error responses that
+/// pages of any origin can read. Builds for `wasm32-wasi` abort on panic
+/// instead of unwinding, so jobs are validated before rendering to rule out
+/// panics.
+fn handle(req: &Request, limits: &Limits, renderer: &CachedRenderer<impl ResultCache>) -> Response {
+    route(req, limits, renderer).unwrap_or_else(HttpError::into_response)
+}
+
+fn route(
+    req: &Request,
+    limits: &Limits,
+    renderer: &CachedRenderer<impl ResultCache>,
+) -> Result<Response, HttpError> {
+    let path = req.uri().path();
+    if path == "/ping" {
         let body = Some("pong".as_bytes().to_vec().into());
         return Ok(http::Response::builder()
             .status(200)
@@ -17,15 +51,476 @@ fn handle_spin_component(req: Request) -> Result<Response> {
             .header("Content-Type", "text/plain")
             .body(body)?);
     }
-    let job = api::RenderJob::from_str(req.uri().path())?;
+    if path == "/capabilities" {
+        let body = serde_json::to_vec(&capabilities(limits)).map_err(HttpError::internal)?;
+        return Ok(http::Response::builder()
+            .status(200)
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Content-Type", "application/json")
+            .body(Some(body.into()))?);
+    }
+    if path == "/rows" || path.starts_with("/rows/") {
+        let job = match path.strip_prefix("/rows/") {
+            Some(job) => RenderJob::from_str(job)?,
+            None if *req.method() == http::Method::OPTIONS => return preflight(),
+            None => job_from_form(req, "GET, POST, OPTIONS")?,
+        };
+        return render_rows(job, limits);
+    }
+    if path == "/batch" {
+        return match *req.method() {
+            http::Method::OPTIONS => preflight(),
+            http::Method::POST if is_json(req) => render_batch(
+                RenderBatch::from_json(req.body().as_deref().unwrap_or_default())?,
+                limits,
+                renderer,
+            ),
+            http::Method::POST => {
+                let body = std::str::from_utf8(req.body().as_deref().unwrap_or_default())
+                    .map_err(|err| ErrorResponse::bad_request(format!("invalid batch: {err}")))?;
+                render_batch(body.parse()?, limits, renderer)
+            }
+            _ => Err(ErrorResponse::method_not_allowed("POST, OPTIONS").into()),
+        };
+    }
+    if path == "/job" {
+        if *req.method() == http::Method::OPTIONS {
+            return preflight();
+        }
+        return render(job_from_form(req, "GET, POST, OPTIONS")?, limits, renderer);
+    }
+    if !matches!(*req.method(), http::Method::GET | http::Method::POST) {
+        return Err(ErrorResponse::method_not_allowed("GET, POST").into());
+    }
+    let job = RenderJob::from_str(path).map_err(|err| ErrorResponse::for_path(path, err))?;
+    render(job, limits, renderer)
+}
+
+/// Reads the job of `/job` and `/rows`, from the query of a GET request or
+/// the JSON body of a POST. Other methods than those in `allow` are
+/// rejected.
+fn job_from_form(req: &Request, allow: &'static str) -> Result<RenderJob, HttpError> {
+    match *req.method() {
+        http::Method::POST => Ok(RenderJob::from_json(
+            req.body().as_deref().unwrap_or_default(),
+        )?),
+        http::Method::GET => Ok(RenderJob::from_query(
+            req.uri().query().unwrap_or_default(),
+        )?),
+        _ => Err(ErrorResponse::method_not_allowed(allow).into()),
+    }
+}
+
+/// Whether the request body is JSON, by its `Content-Type`.
+fn is_json(req: &Request) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
 
+/// Everything this component renders, on a single thread, with the limits
+/// of `limits`.
+fn capabilities(limits: &Limits) -> Capabilities {
+    Capabilities::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))
+        .with_batch(true)
+        .with_limits(*limits)
+}
+
+/// Limits of the jobs this component renders, from the `limits` variable,
+/// a JSON object like `{"max_rays": 1000000}`. Fields left out, or the whole
+/// variable, default to `api::Limits::default()`.
+fn limits() -> Result<Limits, HttpError> {
+    match config::get("limits") {
+        Ok(json) => serde_json::from_str(&json)
+            .map_err(|err| HttpError::internal(format!("invalid limits variable: {err}"))),
+        Err(_) => Ok(Limits::default()),
+    }
+}
+
+/// Answers CORS preflight requests, which browsers send before posting JSON.
+fn preflight() -> Result<Response, HttpError> {
+    Ok(http::Response::builder()
+        .status(204)
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
+        .header("Access-Control-Allow-Headers", "Content-Type")
+        .body(None)?)
+}
+
+/// Renders `job`, or serves it from the cache, with the result in headers.
+fn render(
+    job: RenderJob,
+    limits: &Limits,
+    renderer: &CachedRenderer<impl ResultCache>,
+) -> Result<Response, HttpError> {
+    job.validate_with(limits)?;
+    let result = render_cached(renderer, &job);
+
+    let mut response = http::Response::builder()
+        .status(200)
+        .header("Access-Control-Allow-Origin", "*")
+        .header(
+            "Access-Control-Expose-Headers",
+            RenderResult::EXPOSED_HEADERS,
+        );
+    for (name, value) in result.headers() {
+        response = response.header(name, value);
+    }
+    Ok(response.body(Some(result.image.into()))?)
+}
+
+/// Renders `job` in blocks of rows, with the metadata of the result in
+/// headers. Spin 1 sends the body only once it is complete, so all blocks
+/// are answered together when the tile is done.
+fn render_rows(job: RenderJob, limits: &Limits) -> Result<Response, HttpError> {
+    job.validate_with(limits)?;
     let dt = std::time::Instant::now();
-    let response_bytes = job.render();
-    println!("{job:?} done after {:<#.1?}", dt.elapsed());
+    let mut response_bytes = vec![];
+    job.for_each_row_block(1, ROWS_PER_BLOCK, &mut |block| {
+        block.encode(&mut response_bytes)
+    });
+    let result = RenderResult::new(job.clone(), vec![])
+        .with_duration_ms(dt.elapsed().as_millis().try_into().unwrap_or(u32::MAX))
+        .with_stats(job.stats(1))
+        .with_worker(WORKER_NAME);
+    println!("{} done in row blocks", result.summary());
+
+    let mut response = http::Response::builder()
+        .status(200)
+        .header("Content-Type", api::RowBlock::CONTENT_TYPE)
+        .header("Access-Control-Allow-Origin", "*")
+        .header(
+            "Access-Control-Expose-Headers",
+            RenderResult::EXPOSED_HEADERS,
+        );
+    for (name, value) in result.metadata_headers() {
+        response = response.header(name, value);
+    }
+    Ok(response.body(Some(response_bytes.into()))?)
+}
+
+/// Renders all jobs of the batch one after the other, each served from the
+/// cache if possible, and answers with all results at once.
+fn render_batch(
+    batch: RenderBatch,
+    limits: &Limits,
+    renderer: &CachedRenderer<impl ResultCache>,
+) -> Result<Response, HttpError> {
+    batch.validate_with(limits)?;
+    let results: Vec<_> = batch
+        .jobs
+        .iter()
+        .map(|job| render_cached(renderer, job))
+        .collect();
+    let mut body = vec![];
+    RenderBatch::encode_results(&results, &mut body);
 
     Ok(http::Response::builder()
         .status(200)
-        .header("Content-Type", "image/png")
+        .header("Content-Type", RenderBatch::RESULTS_CONTENT_TYPE)
         .header("Access-Control-Allow-Origin", "*")
-        .body(Some(response_bytes.into()))?)
+        .body(Some(body.into()))?)
+}
+
+/// Serves `job` from the store or renders it. Each request runs in its own
+/// instance with its own `CachedRenderer`, so requests for the same job at
+/// the same time are each rendered, only later requests find the result.
+fn render_cached(renderer: &CachedRenderer<impl ResultCache>, job: &RenderJob) -> RenderResult {
+    let (result, cached) = renderer.get_or_render(job, || {
+        let dt = std::time::Instant::now();
+        let image = job.render();
+        RenderResult::new(job.clone(), image)
+            .with_duration_ms(dt.elapsed().as_millis().try_into().unwrap_or(u32::MAX))
+            .with_stats(job.stats(1))
+            .with_worker(WORKER_NAME)
+    });
+    let origin = if cached { "served from cache" } else { "done" };
+    println!("{} {origin}", result.summary());
+    result
+}
+
+/// A failed request, answered with the status and body of the
+/// `api::ErrorResponse`.
+#[derive(Debug)]
+struct HttpError(ErrorResponse);
+
+impl HttpError {
+    /// A failure of the worker itself, logged with its details.
+    fn internal(err: impl Display) -> Self {
+        eprintln!("internal error: {err}");
+        Self(ErrorResponse::internal())
+    }
+
+    fn into_response(self) -> Response {
+        let HttpError(err) = self;
+        let body = serde_json::to_string(&err).unwrap_or_default();
+        let mut response = http::Response::new(Some(body.into()));
+        *response.status_mut() =
+            StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let headers = response.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(ErrorResponse::CONTENT_TYPE),
+        );
+        if let Some(allow) = err.allow {
+            headers.insert(header::ALLOW, HeaderValue::from_static(allow));
+        }
+        response
+    }
+}
+
+impl From<ErrorResponse> for HttpError {
+    fn from(err: ErrorResponse) -> Self {
+        HttpError(err)
+    }
+}
+
+impl From<RenderJobParseError> for HttpError {
+    fn from(err: RenderJobParseError) -> Self {
+        HttpError(err.into())
+    }
+}
+
+impl From<JobValidationError> for HttpError {
+    fn from(err: JobValidationError) -> Self {
+        HttpError(err.into())
+    }
+}
+
+impl From<RenderBatchError> for HttpError {
+    fn from(err: RenderBatchError) -> Self {
+        HttpError(err.into())
+    }
+}
+
+impl From<http::Error> for HttpError {
+    fn from(err: http::Error) -> Self {
+        HttpError::internal(err)
+    }
+}
+
+/// Results in Spin's default key-value store, shared by all instances of the
+/// component. Without a store, nothing is cached.
+struct KvCache(Option<Store>);
+
+impl KvCache {
+    fn open() -> Self {
+        Self(Store::open_default().ok())
+    }
+}
+
+impl ResultCache for KvCache {
+    fn get(&self, job: &RenderJob) -> Option<RenderResult> {
+        let bytes = self.0.as_ref()?.get(job.content_hash().to_string()).ok()?;
+        RenderResult::decode(&bytes)
+            .ok()
+            .filter(|result| result.job == *job)
+    }
+
+    fn put(&self, result: &RenderResult) {
+        if let Some(store) = &self.0 {
+            let mut bytes = vec![];
+            result.encode(&mut bytes);
+            let _ = store.set(result.job.content_hash().to_string(), bytes);
+        }
+    }
+}
+
+/// Answers `req` with the default limits and an in-memory cache, so that no
+/// host functions of Spin are called.
+#[cfg(test)]
+fn answer(req: &Request) -> Response {
+    handle(
+        req,
+        &Limits::default(),
+        &CachedRenderer::new(api::LruCache::new(8)),
+    )
+}
+
+#[cfg(test)]
+fn request(method: http::Method, uri: &str, body: &str) -> Request {
+    http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Some(body.as_bytes().to_vec().into()))
+        .unwrap()
+}
+
+#[cfg(test)]
+fn json_request(uri: &str, body: &str) -> Request {
+    let mut req = request(http::Method::POST, uri, body);
+    req.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    req
+}
+
+/// Status and message of an error response, checking its headers.
+#[cfg(test)]
+fn error_of(response: Response) -> (u16, String) {
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value =
+        serde_json::from_slice(response.body().as_deref().unwrap()).unwrap();
+    assert_eq!(body["status"], response.status().as_u16());
+    (
+        response.status().as_u16(),
+        body["error"].as_str().unwrap().to_owned(),
+    )
+}
+
+#[test]
+fn answers_ping_and_capabilities() {
+    let response = answer(&request(http::Method::GET, "/ping", ""));
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.body().as_deref(), Some(&b"pong"[..]));
+
+    let response = answer(&request(http::Method::GET, "/capabilities", ""));
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    let caps: Capabilities = serde_json::from_slice(response.body().as_deref().unwrap()).unwrap();
+    assert_eq!(caps, capabilities(&Limits::default()));
+}
+
+#[test]
+fn row_blocks_come_with_result_headers() {
+    let job = RenderJob::new(0, 0, 8, 20, 8, 20, 1, 1);
+    for uri in [format!("/rows/{job}"), format!("/rows?{}", job.to_query())] {
+        let response = answer(&request(http::Method::GET, &uri, ""));
+        assert_eq!(response.status(), 200, "{uri}");
+        assert_eq!(
+            response.headers()["Content-Type"],
+            api::RowBlock::CONTENT_TYPE
+        );
+        let header = |name: &str| {
+            (name != "Content-Type")
+                .then(|| response.headers().get(name))
+                .flatten()
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+        let result = RenderResult::from_headers(header, vec![]).unwrap();
+        assert_eq!(result.job, job);
+        assert_eq!(result.worker, WORKER_NAME);
+    }
+}
+
+#[test]
+fn malformed_jobs_are_bad_requests() {
+    for uri in [
+        "/0/0/8",
+        "/0/0/8/8/4/4/1/1",
+        "/v1/0/0/8/8/8/8/1/1/seed",
+        "/rows/0/0/8/x/8/8/1/1",
+        "/job?x=0&y=0&w=8&h=8&camera_w=8&camera_h=8&samples=1",
+    ] {
+        let (status, message) = error_of(answer(&request(http::Method::GET, uri, "")));
+        assert_eq!(status, 400, "{uri}");
+        assert!(message.starts_with("invalid job: "), "{message}");
+    }
+    let response = answer(&request(http::Method::POST, "/job", r#"{"x":0}"#));
+    let (status, message) = error_of(response);
+    assert_eq!(status, 400);
+    assert!(message.contains("missing field `y`"), "{message}");
+
+    let body = "0/0/4/4/16/4/1/1\n0/0";
+    let response = answer(&request(http::Method::POST, "/batch", body));
+    let (status, message) = error_of(response);
+    assert_eq!(status, 400);
+    assert!(message.starts_with("invalid batch: job 1 "), "{message}");
+    let response = answer(&request(http::Method::POST, "/batch", ""));
+    assert_eq!(error_of(response).0, 400);
+}
+
+#[test]
+fn unknown_routes_and_scenes_are_not_found() {
+    for uri in [
+        "/",
+        "/favicon.ico",
+        "/index.html/0/0",
+        "/v1/0/0/8/8/8/8/1/1/!scene=9",
+        "/rows/v1/0/0/8/8/8/8/1/1/!scene=9",
+        "/job?x=0&y=0&w=8&h=8&camera_w=8&camera_h=8&samples=1&recursion=1&scene=city",
+    ] {
+        let (status, _) = error_of(answer(&request(http::Method::GET, uri, "")));
+        assert_eq!(status, 404, "{uri}");
+    }
+    let json = r#"{"x":0,"y":0,"w":8,"h":8,"camera_w":8,"camera_h":8,"samples":1,"recursion":1,"scene":"city"}"#;
+    let (status, message) = error_of(answer(&request(http::Method::POST, "/job", json)));
+    assert_eq!(status, 404);
+    assert!(message.contains("city"), "{message}");
+    let body = "0/0/8/8/8/8/1/1\nv1/0/0/8/8/8/8/1/1/!scene=9";
+    let (status, _) = error_of(answer(&request(http::Method::POST, "/batch", body)));
+    assert_eq!(status, 404);
+    let json = json.replace(r#""city""#, "9");
+    let (status, _) = error_of(answer(&request(http::Method::POST, "/rows", &json)));
+    assert_eq!(status, 404);
+    let batch = format!("[{json}]");
+    let (status, message) = error_of(answer(&json_request("/batch", &batch)));
+    assert_eq!(status, 404);
+    assert!(message.starts_with("invalid batch: job 0 "), "{message}");
+}
+
+#[test]
+fn over_budget_jobs_are_too_large() {
+    for uri in [
+        "/0/0/8/8/8/8/5000/1",
+        "/0/0/8/8/20000/8/1/1",
+        "/0/0/8/8/8/8/1/300",
+        "/rows/0/0/8/8/8/8/1/300",
+        "/rows?x=0&y=0&w=8&h=8&camera_w=8&camera_h=8&samples=1&recursion=300",
+        "/job?x=0&y=0&w=8&h=8&camera_w=8&camera_h=8&samples=1&recursion=300",
+    ] {
+        let (status, message) = error_of(answer(&request(http::Method::GET, uri, "")));
+        assert_eq!(status, 413, "{uri}");
+        assert!(message.contains("exceed"), "{message}");
+    }
+    let batch = "0/0/8/8/8/8/1/1\n".repeat(api::Limits::default().max_batch_jobs + 1);
+    let (status, _) = error_of(answer(&request(http::Method::POST, "/batch", &batch)));
+    assert_eq!(status, 413);
+    let batch = "0/0/8/8/8/8/1/1\n0/0/8/8/8/8/5000/1";
+    let (status, message) = error_of(answer(&request(http::Method::POST, "/batch", batch)));
+    assert_eq!(status, 413);
+    assert!(message.starts_with("invalid batch: job 1 "), "{message}");
+    let json = r#"[{"x":0,"y":0,"w":8,"h":8,"camera_w":8,"camera_h":8,"samples":1,"recursion":300,"integrator":1}]"#;
+    let (status, message) = error_of(answer(&json_request("/batch", json)));
+    assert_eq!(status, 413);
+    assert!(message.starts_with("invalid batch: job 0 "), "{message}");
+}
+
+#[test]
+fn wrong_methods_are_not_allowed() {
+    let response = answer(&request(http::Method::GET, "/batch", ""));
+    assert_eq!(response.headers()["Allow"], "POST, OPTIONS");
+    assert_eq!(error_of(response).0, 405);
+    let response = answer(&request(http::Method::PUT, "/rows", ""));
+    assert_eq!(response.headers()["Allow"], "GET, POST, OPTIONS");
+    assert_eq!(error_of(response).0, 405);
+    for method in [http::Method::DELETE, http::Method::HEAD] {
+        let response = answer(&request(method, "/job", ""));
+        assert_eq!(response.headers()["Allow"], "GET, POST, OPTIONS");
+        assert_eq!(error_of(response).0, 405);
+    }
+    for uri in ["/0/0/8/8/8/8/1/1", "/garbage", "/0/0/8"] {
+        let response = answer(&request(http::Method::PUT, uri, ""));
+        assert_eq!(response.headers()["Allow"], "GET, POST", "{uri}");
+        assert_eq!(error_of(response).0, 405, "{uri}");
+    }
+
+    for uri in ["/job", "/rows", "/batch"] {
+        let response = answer(&request(http::Method::OPTIONS, uri, ""));
+        assert_eq!(response.status(), 204, "{uri}");
+        assert_eq!(response.headers()["Access-Control-Allow-Origin"], "*");
+    }
+}
+
+#[test]
+fn internal_errors_hide_details() {
+    let response = HttpError::internal("disk full").into_response();
+    assert_eq!(error_of(response), (500, "internal error".to_owned()));
 }
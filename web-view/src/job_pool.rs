@@ -0,0 +1,172 @@
+use api::IncompatibleWorker;
+
+use crate::render::RenderTask;
+use crate::worker::PngRenderWorker;
+
+/// Tasks waiting for a worker.
+///
+/// Which workers accept a task is checked once, when the task or a worker is
+/// added, not on every frame. Tasks that no worker accepts are set aside
+/// until a worker that accepts them joins.
+#[derive(Default)]
+pub(crate) struct JobPool {
+    /// Taken from the back, in the order they were planned.
+    tasks: Vec<PooledTask>,
+    /// Workers that may accept a task in the pool, cleared for a worker once
+    /// it finds none.
+    wanted_by: WorkerSet,
+    /// Tasks no worker accepts.
+    set_aside: Vec<RenderTask>,
+    /// Tasks set aside since the last `take_rejection`, with a reason.
+    rejection: Option<(usize, IncompatibleWorker)>,
+}
+
+struct PooledTask {
+    task: RenderTask,
+    accepted_by: WorkerSet,
+}
+
+/// Worker ids as bits.
+type WorkerSet = u32;
+
+/// Most workers a `WorkerSet` holds.
+pub(crate) const MAX_WORKERS: usize = WorkerSet::BITS as usize;
+
+impl JobPool {
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn tasks(&self) -> impl Iterator<Item = &RenderTask> {
+        self.tasks.iter().map(|pooled| &pooled.task)
+    }
+
+    /// Drops all tasks, including those set aside.
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+        self.set_aside.clear();
+        self.wanted_by = 0;
+    }
+
+    /// Adds tasks to the back, so they are taken first.
+    pub fn extend(
+        &mut self,
+        tasks: impl IntoIterator<Item = RenderTask>,
+        workers: &[PngRenderWorker],
+    ) {
+        for task in tasks {
+            let mut accepted_by = 0;
+            let mut reason = None;
+            for (worker_id, worker) in workers.iter().enumerate() {
+                match worker.check_task(&task) {
+                    Ok(()) => accepted_by |= 1 << worker_id,
+                    Err(err) => reason = Some(err),
+                }
+            }
+            match reason {
+                Some(reason) if accepted_by == 0 => self.reject(task, reason),
+                // without any workers, tasks wait for the first one
+                _ => {
+                    self.wanted_by |= accepted_by;
+                    self.tasks.push(PooledTask { task, accepted_by });
+                }
+            }
+        }
+    }
+
+    /// Checks all tasks again for a worker that joined or announced its
+    /// capabilities.
+    pub fn update_worker(&mut self, worker_id: usize, workers: &[PngRenderWorker]) {
+        let worker = &workers[worker_id];
+        let bit = 1 << worker_id;
+        self.wanted_by = 0;
+        let mut rejected = vec![];
+        for mut pooled in std::mem::take(&mut self.tasks) {
+            match worker.check_task(&pooled.task) {
+                Ok(()) => pooled.accepted_by |= bit,
+                Err(err) => {
+                    pooled.accepted_by &= !bit;
+                    if pooled.accepted_by == 0 {
+                        rejected.push((pooled.task, err));
+                        continue;
+                    }
+                }
+            }
+            self.wanted_by |= pooled.accepted_by;
+            self.tasks.push(pooled);
+        }
+        let (accepted, set_aside) = std::mem::take(&mut self.set_aside)
+            .into_iter()
+            .partition::<Vec<_>, _>(|task| worker.check_task(task).is_ok());
+        self.set_aside = set_aside;
+        for (task, reason) in rejected {
+            self.reject(task, reason);
+        }
+        self.extend(accepted, workers);
+    }
+
+    /// Removes up to `n` tasks from the back that worker `worker_id`
+    /// accepts, with at most `max_rays` rays together unless it is a single
+    /// task, which is sent alone.
+    pub fn take(&mut self, worker_id: usize, n: usize, max_rays: u64) -> Vec<RenderTask> {
+        let bit = 1 << worker_id;
+        if self.wanted_by & bit == 0 {
+            return vec![];
+        }
+        let mut first = None;
+        let mut taken = 0;
+        let mut rays = 0u64;
+        for (i, pooled) in self.tasks.iter().enumerate().rev() {
+            if taken == n {
+                break;
+            }
+            if pooled.accepted_by & bit != 0 {
+                rays = rays.saturating_add(pooled.task.marshal().max_rays());
+                if taken > 0 && rays > max_rays {
+                    break;
+                }
+                taken += 1;
+                first = Some(i);
+            }
+        }
+        let first = match first {
+            Some(first) => first,
+            None => {
+                // the whole pool was searched
+                self.wanted_by &= !bit;
+                return vec![];
+            }
+        };
+        // every task behind `first` that the worker accepts is taken
+        let (taken, rest): (Vec<_>, Vec<_>) = self
+            .tasks
+            .split_off(first)
+            .into_iter()
+            .partition(|pooled| pooled.accepted_by & bit != 0);
+        self.tasks.extend(rest);
+        taken.into_iter().rev().map(|pooled| pooled.task).collect()
+    }
+
+    /// Removes up to `n` tasks from the back, for any worker.
+    pub fn take_any(&mut self, n: usize) -> Vec<RenderTask> {
+        let start = self.tasks.len().saturating_sub(n);
+        self.tasks
+            .drain(start..)
+            .map(|pooled| pooled.task)
+            .collect()
+    }
+
+    /// Describes the tasks set aside since the last call.
+    pub fn take_rejection(&mut self) -> Option<String> {
+        let (n, reason) = self.rejection.take()?;
+        Some(format!(
+            "{n} tiles cannot be rendered by any worker, for example: {reason}."
+        ))
+    }
+
+    fn reject(&mut self, task: RenderTask, reason: IncompatibleWorker) {
+        let n = self.rejection.as_ref().map_or(0, |(n, _)| *n);
+        self.rejection = Some((n + 1, reason));
+        self.set_aside.push(task);
+    }
+}
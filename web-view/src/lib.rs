@@ -1,4 +1,3 @@
-use std::cell::RefCell;
 use std::rc::Rc;
 
 use bottom_tabs::Tabs;
@@ -6,7 +5,7 @@ use images::Images;
 use js_sys::Uint8Array;
 use network::NetworkView;
 use p2p_proto::RenderControlBody;
-use paddle::quicksilver_compat::Color;
+use paddle::quicksilver_compat::{Color, Shape};
 use paddle::*;
 use palette::CSS_FONT_DARK;
 use progress::{ProgressMade, ProgressReset, RenderProgress};
@@ -17,6 +16,7 @@ use workers_view::WorkerView;
 
 mod bottom_tabs;
 mod images;
+mod job_pool;
 mod network;
 mod p2p_proto;
 mod palette;
@@ -60,6 +60,8 @@ pub fn start() {
     let main_handle = paddle::register_frame(state, (), (0, 0));
     main_handle.register_receiver(&Main::enqueue_next_job);
     main_handle.listen(&Main::new_png_part);
+    main_handle.listen(&Main::png_preview);
+    main_handle.listen(&Main::tile_failed);
     main_handle.listen(&Main::peer_message);
     main_handle.listen(&Main::stop);
 
@@ -76,9 +78,13 @@ pub fn start() {
     worker_handle.register_receiver(&WorkerView::worker_ready);
     worker_handle.register_receiver(&WorkerView::new_jobs);
     worker_handle.register_receiver(&WorkerView::job_done);
+    worker_handle.register_receiver(&WorkerView::job_failed);
+    worker_handle.register_receiver(&WorkerView::job_progress);
     worker_handle.listen(&WorkerView::add_worker);
+    worker_handle.listen(&WorkerView::set_batch_size);
     worker_handle.listen(&WorkerView::stop);
     worker_handle.listen(&WorkerView::peer_message);
+    worker_handle.listen(&WorkerView::unreadable_jobs);
     worker_handle.listen(&WorkerView::new_peer);
 
     let network_handle = NetworkView::init();
@@ -111,6 +117,9 @@ struct Main {
     /// Stack of all images rendered, drawn in the order they were added and
     /// potentially covering older images.
     imgs: Vec<PngPart>,
+    /// Rows of tiles still in progress, drawn on top of `imgs` until the
+    /// full tile arrives.
+    previews: Vec<PngPreview>,
 
     /// number of jobs currently waiting to be done
     outstanding_jobs: usize,
@@ -138,6 +147,9 @@ impl paddle::Frame for Main {
         for part in &self.imgs {
             canvas.draw(&part.screen_area, &part.img.img);
         }
+        for preview in &self.previews {
+            canvas.draw(&preview.screen_area, &preview.img.img);
+        }
         if self.imgs.is_empty() {
             canvas.draw(&Self::area().shrink_to_center(0.25), &self.default_image);
         }
@@ -159,18 +171,34 @@ impl paddle::Frame for Main {
 struct PngPart {
     screen_area: Rectangle,
     img: ImageData,
+    /// Job, stats and image as produced by the worker, forwarded to peers.
+    /// `None` for parts of peers that only send the image.
+    result: Option<Rc<api::RenderResult>>,
+}
+
+/// Partial result of a local worker, only shown until the tile is done.
+#[derive(Clone)]
+struct PngPreview {
+    screen_area: Rectangle,
+    img: ImageData,
+}
+
+/// A tile failed, its previews are removed until it is rendered again.
+#[derive(Clone)]
+struct TileFailed {
+    screen_area: Rectangle,
 }
 
 #[derive(Clone)]
 struct ImageData {
     img: ImageDesc,
-    data: Rc<RefCell<Option<Vec<u8>>>>,
 }
 
 impl Main {
     fn init(images: &Images) -> Self {
         Main {
             imgs: vec![],
+            previews: vec![],
             old_images: 0,
             outstanding_jobs: 0,
             default_image: images.screen,
@@ -211,13 +239,31 @@ impl Main {
         bundle.add_images(&[png.img.img]);
         bundle.load();
 
+        self.previews
+            .retain(|preview| !png.screen_area.contains(preview.screen_area.center()));
         self.imgs.push(png.clone());
         self.outstanding_jobs = self.outstanding_jobs.saturating_sub(1);
     }
 
+    /// paddle event listener
+    fn png_preview(&mut self, _state: &mut (), png: &PngPreview) {
+        let mut bundle = AssetBundle::new();
+        bundle.add_images(&[png.img.img]);
+        bundle.load();
+
+        self.previews.push(png.clone());
+    }
+
+    /// paddle event listener
+    fn tile_failed(&mut self, _state: &mut (), tile: &TileFailed) {
+        self.previews
+            .retain(|preview| !tile.screen_area.contains(preview.screen_area.center()));
+    }
+
     /// paddle event listener
     pub fn stop(&mut self, _state: &mut (), _msg: &crate::Stop) {
         self.imgs.drain(self.old_images..);
+        self.previews.clear();
         self.old_images = 0;
         self.outstanding_jobs = 0;
     }
@@ -240,26 +286,45 @@ impl Main {
     }
 }
 
-impl ImageData {
-    fn new_from_array(data: Uint8Array) -> Self {
-        let vec = data.to_vec();
-        Self::new_from_vec(vec)
+impl PngPart {
+    /// Places the image where the result's job belongs on screen.
+    fn new(result: api::RenderResult) -> Result<Self, String> {
+        let screen_area = RenderTask::from(result.job.clone()).screen_area;
+        Self::with_area(result, screen_area)
     }
 
-    fn new_from_vec(vec: Vec<u8>) -> Self {
-        Self {
-            // TODO: Avoid memory leak (in paddle itself!)
-            img: ImageDesc::from_png_binary(&vec).unwrap(),
-            data: Rc::new(Some(vec).into()),
+    /// Fails for results that are not a PNG, which can not be shown.
+    fn with_area(result: api::RenderResult, screen_area: Rectangle) -> Result<Self, String> {
+        if result.format != api::OutputFormat::Png {
+            return Err(format!("cannot show {:?} image", result.format));
         }
+        Ok(Self {
+            screen_area,
+            img: ImageData::new_from_png(&result.image)?,
+            result: Some(Rc::new(result)),
+        })
     }
 
-    // TODO: avoid memory leaks
-    fn new_leaky(path: String) -> Self {
-        Self {
-            img: ImageDesc::from_path(Box::leak(path.into_boxed_str())),
-            data: Rc::new(None.into()),
-        }
+    /// A part of a peer that only sends the image, placed at `screen_area`.
+    fn without_result(png: &[u8], screen_area: Rectangle) -> Result<Self, String> {
+        Ok(Self {
+            screen_area,
+            img: ImageData::new_from_png(png)?,
+            result: None,
+        })
+    }
+}
+
+impl ImageData {
+    fn new_from_array(data: Uint8Array) -> Result<Self, String> {
+        Self::new_from_png(&data.to_vec())
+    }
+
+    fn new_from_png(png: &[u8]) -> Result<Self, String> {
+        Ok(Self {
+            // TODO: Avoid memory leak (in paddle itself!)
+            img: ImageDesc::from_png_binary(png).map_err(|err| format!("invalid PNG: {err:?}"))?,
+        })
     }
 }
 
@@ -40,6 +40,16 @@ struct OpenNewPeerConnectionMsg;
 /// Send some serialized data to all peers.
 struct BroadcastMsg(Vec<u8>);
 
+/// Send some serialized data to the peer with the given id.
+struct SendMsg(String, Vec<u8>);
+
+/// Event emitted for jobs of a `Job` message that this version cannot read,
+/// with the id of the peer that sent them.
+pub(crate) struct UnreadableJobs {
+    pub peer_id: String,
+    pub jobs: Vec<Vec<u32>>,
+}
+
 impl NetworkView {
     pub(crate) fn init() -> FrameHandle<Self> {
         // Connect WebSocket to signaling server for setting up connections later.
@@ -84,6 +94,7 @@ impl NetworkView {
         handle.listen(Self::request_new_connection);
         handle.listen(Self::connected);
         handle.register_receiver(Self::broadcast);
+        handle.register_receiver(Self::send);
         handle
     }
 
@@ -138,12 +149,15 @@ impl NetworkView {
 
     /// paddle event listener: forward png parts when they are produced
     pub(crate) fn new_png_part(&mut self, _state: &mut (), png: &crate::PngPart) {
+        // only parts rendered here have a result, those of peers are not
+        // forwarded
+        let Some(result) = &png.result else {
+            return;
+        };
+        // Best effort pre-allocation: the image plus room for the job and stats.
+        let size_hint = result.image.len() + 128;
         let msg = crate::p2p_proto::Message::RenderedPart(png.clone());
-        let num_pixels = png.screen_area.width() as usize * png.screen_area.height() as usize;
-        // Best effort pre-allocation: One byte per pixel, which is most likely
-        // too much due to PNG compression. Hence it should avoid re-allocation
-        // in most cases.
-        broadcast_async(msg, Some(num_pixels));
+        broadcast_async(msg, Some(size_hint));
     }
 
     /// Send a message to all peers.
@@ -152,6 +166,14 @@ impl NetworkView {
             peer.connection.send(&data).unwrap();
         }
     }
+
+    /// Send a message to one peer.
+    fn send(&mut self, _state: &mut (), SendMsg(id, data): SendMsg) {
+        match self.peers.get(&id) {
+            Some(peer) => peer.connection.send(&data).unwrap(),
+            None => paddle::println!("cannot send to {id}, no such peer"),
+        }
+    }
 }
 
 impl Frame for NetworkView {
@@ -209,9 +231,10 @@ fn on_message(_data_channel: &RtcDataChannel, id: &str, ev: MessageEvent) {
     // Handling Blobs directly is most efficient and works in FF. In fact, it's
     // the default in FF.
     else if let Some(blob) = ev.data().dyn_into::<web_sys::Blob>().ok() {
-        let future = async {
+        let id = id.to_owned();
+        let future = async move {
             match crate::p2p_proto::Message::from_blob(blob).await {
-                Ok(msg) => paddle::share(msg),
+                Ok(msg) => received(&id, msg),
                 Err(e) => paddle::println!("failed to parse received message: {e:?}"),
             }
         };
@@ -223,7 +246,7 @@ fn on_message(_data_channel: &RtcDataChannel, id: &str, ev: MessageEvent) {
     // started with FF, so I'll stubbornly keep both implementations.
     else if let Some(array_buffer) = ev.data().dyn_into::<js_sys::ArrayBuffer>().ok() {
         match crate::p2p_proto::Message::from_array(array_buffer) {
-            Ok(msg) => paddle::share(msg),
+            Ok(msg) => received(id, msg),
             Err(e) => paddle::println!("failed to parse received message: {e:?}"),
         }
     } else {
@@ -234,6 +257,20 @@ fn on_message(_data_channel: &RtcDataChannel, id: &str, ev: MessageEvent) {
     }
 }
 
+/// Shares a message of the peer `id`, and jobs in it that cannot be read
+/// together with the peer to answer.
+fn received(id: &str, msg: crate::p2p_proto::Message) {
+    if let crate::p2p_proto::Message::Job(body) = &msg {
+        if !body.unreadable.is_empty() {
+            paddle::share(UnreadableJobs {
+                peer_id: id.to_owned(),
+                jobs: body.unreadable.clone(),
+            });
+        }
+    }
+    paddle::share(msg);
+}
+
 /// Entry point for new WebRTC connections opening.
 fn on_open(_data_channel: &RtcDataChannel, id: &str) {
     paddle::share(NewPeerEstablishedConnectionMsg(id.to_owned()));
@@ -242,19 +279,33 @@ fn on_open(_data_channel: &RtcDataChannel, id: &str) {
 /// Send a message to all connected peers.
 pub(crate) fn broadcast_async(msg: crate::p2p_proto::Message, size_hint: Option<usize>) {
     let future = async move {
-        let mut buf = if let Some(size) = size_hint {
-            Vec::with_capacity(size)
-        } else {
-            Vec::new()
-        };
-        msg.serialize(&mut buf)
-            .await
-            .expect("failed to serialize message");
+        let buf = serialize(msg, size_hint).await;
         paddle::send::<_, NetworkView>(BroadcastMsg(buf));
     };
     wasm_bindgen_futures::spawn_local(future);
 }
 
+/// Send a message to the peer `id` only.
+pub(crate) fn send_async(id: String, msg: crate::p2p_proto::Message, size_hint: Option<usize>) {
+    let future = async move {
+        let buf = serialize(msg, size_hint).await;
+        paddle::send::<_, NetworkView>(SendMsg(id, buf));
+    };
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+async fn serialize(msg: crate::p2p_proto::Message, size_hint: Option<usize>) -> Vec<u8> {
+    let mut buf = if let Some(size) = size_hint {
+        Vec::with_capacity(size)
+    } else {
+        Vec::new()
+    };
+    msg.serialize(&mut buf)
+        .await
+        .expect("failed to serialize message");
+    buf
+}
+
 fn generate_key() -> String {
     let mut random_bytes = [0; 4];
     web_sys::window()
@@ -3,8 +3,9 @@
 //! This is the "application layer" protocol, building on top of a WebRTC data
 //! channel.
 //!
-//! The receiving side makes use of the Blob API for reconstructing images from
-//! incoming data, instead of reconstructing everything from an array buffer.
+//! Rendered parts are sent as the binary form of `api::RenderResult`, the same
+//! that web workers produce, so the receiver places the image by its job.
+//! Parts of older peers, with a screen area and a PNG, are still read.
 //! Thus, it is not a pure "enum + serde" implementation as in other places.
 
 use std::io::Write;
@@ -17,12 +18,12 @@ use wasm_bindgen_futures::JsFuture;
 use web_sys::Blob;
 
 use crate::render::RenderTask;
-use crate::{ImageData, PngPart};
+use crate::PngPart;
 
 /// Parsed message sent between peers over WebRTC data channels.
 ///
 /// This is the fully parsed representation used outside this module.
-/// The wire format is `MessageHeader` + [fields] + [blob].
+/// The wire format is `MessageHeader` + [fields].
 pub(crate) enum Message {
     // RequestJobs(N)
     // Jobs(N)
@@ -34,24 +35,34 @@ pub(crate) enum Message {
 }
 /// Message header sent between peers over WebRTC data channels.
 ///
-/// This is only the header, which may be followed by fields and maybe a Blob.
+/// This is only the header, which may be followed by fields.
 /// There is no unified body type, depending on the header there will be a
 /// different body.
 #[repr(u8)]
 #[derive(Clone, Copy)]
 enum MessageHeader {
-    /// A rendered output.
+    /// A rendered output, as its screen area and PNG. Only read, from peers
+    /// that do not know `RenderedResult`.
     RenderedPart = 1,
     /// Request for work, as the workers managed on this instance are idle.
     StealWork = 2,
     /// Response to `StealWork`, a list of jobs that can be done by the work stealer.
+    /// Each job is 8 u32s, so only jobs without optional fields fit.
     Job = 3,
     /// Start or stop rendering.
     RenderControl = 4,
     /// Update config options in the UI
     UiUpdate = 5,
+    /// Same as `Job`, with each job prefixed by its number of u32s, so that
+    /// jobs of any encoding version fit. Peers that do not know this message
+    /// reject it instead of misreading the jobs.
+    Jobs = 6,
+    /// A rendered output, as the binary form of `api::RenderResult`. Peers
+    /// that do not know this message reject it instead of misreading it.
+    RenderedResult = 7,
 }
 
+/// Fields before the PNG in a `RenderedPart` message.
 struct RenderedPartBody {
     x: u32,
     y: u32,
@@ -66,6 +77,10 @@ pub(crate) struct StealWorkBody {
 
 pub(crate) struct JobBody {
     pub jobs: Vec<RenderTask>,
+    /// Jobs as received from a peer that this version cannot read, for
+    /// example with required fields added later. They are sent back to the
+    /// peer that handed them out, which renders them instead of losing them.
+    pub unreadable: Vec<Vec<u32>>,
 }
 
 pub(crate) struct RenderControlBody {
@@ -82,28 +97,15 @@ impl Message {
         w.write(&[self.header() as u8])?;
         match self {
             Message::RenderedPart(part) => {
-                if part.img.data.borrow().is_none() {
-                    let result = paddle::load_file(part.img.img.url().unwrap()).await;
-
-                    let bytes = result.map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("loading image data failed: {e:?}"),
-                        )
-                    })?;
-                    *part.img.data.borrow_mut() = Some(bytes);
-                }
-                let borrow = &part.img.data.borrow();
-                let blob_bytes = borrow.as_ref().expect("just filled blob");
-                let body = RenderedPartBody {
-                    x: part.screen_area.pos.x as u32,
-                    y: part.screen_area.pos.y as u32,
-                    pixel_width: part.screen_area.width() as u32,
-                    pixel_height: part.screen_area.height() as u32,
-                    bytes: blob_bytes.len() as u32,
-                };
-                body.serialize(w)?;
-                w.write(&blob_bytes)?;
+                let result = part.result.as_ref().ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "only rendered parts with a result can be sent",
+                    )
+                })?;
+                let mut bytes = vec![];
+                result.encode(&mut bytes);
+                w.write(&bytes)?;
             }
             Message::StealWork(body) => body.serialize(w)?,
             Message::Job(body) => body.serialize(w)?,
@@ -119,20 +121,14 @@ impl Message {
         let header: Option<MessageHeader> = first_byte.get_index(0).try_into().ok();
         match header {
             Some(MessageHeader::RenderedPart) => {
-                let fields_len = 5 * std::mem::size_of::<u32>();
-                let fields_bytes = blob_slice(&blob, 1, fields_len).await?;
-                let body = RenderedPartBody::deserialize(&fields_bytes);
-                let png_blob = blob.slice_with_i32(1 + fields_len as i32)?;
-                // TODO: unregister object?
-                let url = web_sys::Url::create_object_url_with_blob(&png_blob)?;
-                let png = PngPart {
-                    img: ImageData::new_leaky(url),
-                    screen_area: Rectangle::new(
-                        (body.x, body.y),
-                        (body.pixel_width, body.pixel_height),
-                    ),
-                };
-                Ok(Message::RenderedPart(png))
+                let body_blob = blob.slice_with_i32(1)?;
+                let body_bytes = blob_to_array(&body_blob).await?;
+                Ok(Message::RenderedPart(legacy_part(&body_bytes.to_vec())?))
+            }
+            Some(MessageHeader::RenderedResult) => {
+                let body_blob = blob.slice_with_i32(1)?;
+                let body_bytes = blob_to_array(&body_blob).await?;
+                Ok(Message::RenderedPart(rendered_part(&body_bytes.to_vec())?))
             }
             Some(MessageHeader::StealWork) => {
                 let fields_len = std::mem::size_of::<u32>();
@@ -140,10 +136,10 @@ impl Message {
                 let body = StealWorkBody::deserialize(&fields_bytes);
                 Ok(Message::StealWork(body))
             }
-            Some(MessageHeader::Job) => {
+            Some(header @ (MessageHeader::Job | MessageHeader::Jobs)) => {
                 let body_blob = blob.slice_with_i32(1)?;
                 let body_bytes = blob_to_array(&body_blob).await?;
-                let body = JobBody::deserialize(&body_bytes.to_vec());
+                let body = JobBody::deserialize(&body_bytes.to_vec(), header)?;
                 Ok(Message::Job(body))
             }
             Some(MessageHeader::RenderControl) => {
@@ -173,31 +169,12 @@ impl Message {
         let header: Option<MessageHeader> = first_byte.try_into().ok();
         match header {
             Some(MessageHeader::RenderedPart) => {
-                let fields_len = 5 * std::mem::size_of::<u32>();
-                let fields_array = js_sys::Uint8Array::new_with_byte_offset_and_length(
-                    &buffer,
-                    1,
-                    fields_len as u32,
-                );
-                let fields_bytes = fields_array.to_vec();
-                let body = RenderedPartBody::deserialize(&fields_bytes);
-
-                let png_array =
-                    js_sys::Uint8Array::new_with_byte_offset(&buffer, 1 + fields_len as u32);
-                let args = js_sys::Array::new();
-                args.push(&png_array);
-                let png_blob = Blob::new_with_buffer_source_sequence(&args).unwrap();
-
-                // TODO: unregister object?
-                let url = web_sys::Url::create_object_url_with_blob(&png_blob)?;
-                let png = PngPart {
-                    img: ImageData::new_leaky(url),
-                    screen_area: Rectangle::new(
-                        (body.x, body.y),
-                        (body.pixel_width, body.pixel_height),
-                    ),
-                };
-                Ok(Message::RenderedPart(png))
+                let body_array = js_sys::Uint8Array::new_with_byte_offset(&buffer, 1);
+                Ok(Message::RenderedPart(legacy_part(&body_array.to_vec())?))
+            }
+            Some(MessageHeader::RenderedResult) => {
+                let body_array = js_sys::Uint8Array::new_with_byte_offset(&buffer, 1);
+                Ok(Message::RenderedPart(rendered_part(&body_array.to_vec())?))
             }
             Some(MessageHeader::StealWork) => {
                 let fields_len = std::mem::size_of::<u32>();
@@ -209,9 +186,9 @@ impl Message {
                 let body = StealWorkBody::deserialize(&fields_array.to_vec());
                 Ok(Message::StealWork(body))
             }
-            Some(MessageHeader::Job) => {
+            Some(header @ (MessageHeader::Job | MessageHeader::Jobs)) => {
                 let fields_array = js_sys::Uint8Array::new_with_byte_offset(&buffer, 1);
-                let body = JobBody::deserialize(&fields_array.to_vec());
+                let body = JobBody::deserialize(&fields_array.to_vec(), header)?;
                 Ok(Message::Job(body))
             }
             Some(MessageHeader::RenderControl) => {
@@ -235,15 +212,39 @@ impl Message {
 
     fn header(&self) -> MessageHeader {
         match self {
-            Message::RenderedPart(_) => MessageHeader::RenderedPart,
+            Message::RenderedPart(_) => MessageHeader::RenderedResult,
             Message::StealWork(_) => MessageHeader::StealWork,
-            Message::Job(_) => MessageHeader::Job,
+            Message::Job(body) if body.fits_plain() => MessageHeader::Job,
+            Message::Job(_) => MessageHeader::Jobs,
             Message::RenderControl(_) => MessageHeader::RenderControl,
             Message::UiUpdate(_) => MessageHeader::UiUpdate,
         }
     }
 }
 
+/// Decodes a rendered part, placed on screen where its job belongs.
+fn rendered_part(data: &[u8]) -> Result<PngPart, JsValue> {
+    let result = api::RenderResult::decode(data)
+        .map_err(|e| JsValue::from(format!("invalid rendered part: {e}")))?;
+    PngPart::new(result).map_err(|e| JsValue::from(format!("invalid rendered part: {e}")))
+}
+
+/// Decodes a rendered part of a `RenderedPart` message, placed on screen
+/// where the sender says.
+fn legacy_part(data: &[u8]) -> Result<PngPart, JsValue> {
+    if data.len() < RenderedPartBody::LEN {
+        return Err("rendered part ends before its fields".into());
+    }
+    let body = RenderedPartBody::deserialize(&data[..RenderedPartBody::LEN]);
+    let png = data
+        .get(RenderedPartBody::LEN..)
+        .and_then(|rest| rest.get(..body.bytes as usize))
+        .ok_or("rendered part ends before its image")?;
+    let screen_area = Rectangle::new((body.x, body.y), (body.pixel_width, body.pixel_height));
+    PngPart::without_result(png, screen_area)
+        .map_err(|e| JsValue::from(format!("invalid rendered part: {e}")))
+}
+
 async fn blob_slice(blob: &Blob, offset: usize, len: usize) -> Result<Vec<u8>, JsValue> {
     if (blob.size() as usize) < (offset + len) {
         return Err("not enough data".into());
@@ -270,6 +271,8 @@ impl TryFrom<u8> for MessageHeader {
             3 => Self::Job,
             4 => Self::RenderControl,
             5 => Self::UiUpdate,
+            6 => Self::Jobs,
+            7 => Self::RenderedResult,
             _ => return Err(()),
         };
         assert_eq!(result as u8, value);
@@ -278,17 +281,11 @@ impl TryFrom<u8> for MessageHeader {
 }
 
 impl RenderedPartBody {
-    fn serialize(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
-        w.write(&self.x.to_be_bytes())?;
-        w.write(&self.y.to_be_bytes())?;
-        w.write(&self.pixel_width.to_be_bytes())?;
-        w.write(&self.pixel_height.to_be_bytes())?;
-        w.write(&self.bytes.to_be_bytes())?;
-        Ok(())
-    }
+    /// Bytes of the fields, five u32s.
+    const LEN: usize = 20;
 
     fn deserialize(data: &[u8]) -> RenderedPartBody {
-        assert_eq!(data.len(), 20, "RenderedPartBody must be 20 bytes");
+        assert_eq!(data.len(), Self::LEN, "RenderedPartBody must be 20 bytes");
         RenderedPartBody {
             x: u32::from_be_bytes(data[0..4].try_into().unwrap()),
             y: u32::from_be_bytes(data[4..8].try_into().unwrap()),
@@ -314,14 +311,30 @@ impl StealWorkBody {
 }
 
 impl JobBody {
+    /// Number of u32s of each job in a `Job` message.
+    const PLAIN_JOB_LEN: usize = RenderJob::MIN_NUM_FIELDS;
+
+    /// Whether all jobs can be sent as `Job`, which peers of any version read.
+    fn fits_plain(&self) -> bool {
+        self.unreadable.is_empty()
+            && self
+                .jobs
+                .iter()
+                .all(|job| job.marshal().to_vec().len() == Self::PLAIN_JOB_LEN)
+    }
+
+    /// Writes the number of jobs, followed by the jobs. In a `Jobs` message,
+    /// each job is written as its number of u32s followed by the u32s.
     fn serialize(&self, w: &mut impl Write) -> Result<(), std::io::Error> {
-        let num_jobs = self.jobs.len() as u32;
+        let prefixed = !self.fits_plain();
+        let num_jobs = (self.jobs.len() + self.unreadable.len()) as u32;
         w.write(&num_jobs.to_be_bytes())?;
-        for job in &self.jobs {
-            let data: Vec<u8> = job
-                .marshal()
-                .to_vec()
-                .iter()
+        let jobs = self.jobs.iter().map(|job| job.marshal().to_vec());
+        for numbers in jobs.chain(self.unreadable.iter().cloned()) {
+            let len = prefixed.then_some(numbers.len() as u32);
+            let data: Vec<u8> = len
+                .into_iter()
+                .chain(numbers)
                 .flat_map(|num| num.to_be_bytes().into_iter())
                 .collect();
             w.write(&data)?;
@@ -329,24 +342,40 @@ impl JobBody {
         Ok(())
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        assert!(data.len() >= 4, "JobBody must be at least 4 bytes");
-        let num_jobs = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
-
-        assert_eq!(
-            num_jobs * 8 * 4,
-            data.len() - 4,
-            "JobBody must be 8 times a u32 per job"
-        );
-        let numbers = data[4..]
+    /// Reads the body of a `Job` or `Jobs` message. Jobs this version cannot
+    /// read are logged and kept in `unreadable`. A body that is cut short or
+    /// too long is an error.
+    fn deserialize(data: &[u8], header: MessageHeader) -> Result<Self, String> {
+        if data.len() < 4 || data.len() % 4 != 0 {
+            return Err(format!("job body of {} bytes is not u32s", data.len()));
+        }
+        let mut numbers = data
             .chunks_exact(4)
-            .map(|slice| u32::from_be_bytes(slice.try_into().expect("window size must be exact")))
-            .collect::<Vec<_>>();
-        let jobs = numbers
-            .chunks_exact(8)
-            .map(|slice| RenderTask::from(RenderJob::try_from_slice(slice).unwrap()))
-            .collect();
-        Self { jobs }
+            .map(|slice| u32::from_be_bytes(slice.try_into().expect("window size must be exact")));
+        let num_jobs = numbers.next().unwrap_or_default() as usize;
+        let mut jobs = vec![];
+        let mut unreadable = vec![];
+        for index in 0..num_jobs {
+            let len = match header {
+                MessageHeader::Jobs => numbers.next().ok_or("job body ends before all jobs")?,
+                _ => Self::PLAIN_JOB_LEN as u32,
+            } as usize;
+            let job: Vec<u32> = numbers.by_ref().take(len).collect();
+            if job.len() != len {
+                return Err("job body ends in the middle of a job".to_owned());
+            }
+            match RenderJob::try_from_slice(&job) {
+                Ok(job) => jobs.push(RenderTask::from(job)),
+                Err(err) => {
+                    paddle::println!("cannot render job {index} received from a peer: {err}");
+                    unreadable.push(job);
+                }
+            }
+        }
+        if numbers.next().is_some() {
+            return Err("job body has trailing data".to_owned());
+        }
+        Ok(Self { jobs, unreadable })
     }
 }
 
@@ -29,6 +29,12 @@ impl PeerProxy {
         }
     }
 
+    /// Whether work was asked for and not answered yet. `Job` messages that
+    /// arrive otherwise were not handed out.
+    pub(crate) fn request_in_flight(&self) -> bool {
+        self.request_in_flight
+    }
+
     /// indirect paddle event listener
     pub(crate) fn peer_message(&mut self, msg: &Message) {
         match msg {
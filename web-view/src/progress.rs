@@ -27,6 +27,8 @@ pub enum ProgressMade {
     },
     /// Work has been performed by a remote peer.
     Foreign,
+    /// The result of an earlier render was reused.
+    Cached,
 }
 
 pub struct ProgressReset {
@@ -195,7 +197,7 @@ impl RenderProgress {
             ProgressMade::Domestic { time, .. } => {
                 self.total_time += time;
             }
-            ProgressMade::Foreign => (),
+            ProgressMade::Foreign | ProgressMade::Cached => (),
         }
         self.done += 1;
         if self.done == self.total {
@@ -5,7 +5,7 @@ use crate::Main;
 #[derive(Debug, Clone)]
 pub struct RenderTask {
     pub screen_area: paddle::Rectangle,
-    settings: RenderSettings,
+    job: api::RenderJob,
 }
 
 #[derive(Debug, Clone)]
@@ -13,75 +13,53 @@ pub struct RenderSettings {
     pub resolution: (u32, u32),
     pub samples: u32,
     pub recursion: u32,
+    pub integrator: api::IntegratorKind,
+    pub projection: api::ProjectionKind,
+    pub frame: u32,
+    pub filter: api::FilterKind,
+    /// Order in which tiles are handed to workers.
+    pub order: api::TileOrder,
 }
 
 impl RenderTask {
-    pub fn new(screen_area: paddle::Rectangle, settings: RenderSettings) -> Self {
-        Self {
-            screen_area,
-            settings,
-        }
-    }
-
     pub(crate) fn marshal(&self) -> api::RenderJob {
-        let rx = self.settings.resolution.0 as f32 / Main::WIDTH as f32;
-        let ry = self.settings.resolution.1 as f32 / Main::HEIGHT as f32;
-        api::RenderJob::new(
-            (self.screen_area.x() * rx).round() as u32,
-            (self.screen_area.y() * ry).round() as u32,
-            (self.screen_area.width() * rx).round() as u32,
-            (self.screen_area.height() * ry).round() as u32,
-            self.settings.resolution.0,
-            self.settings.resolution.1,
-            self.settings.samples,
-            self.settings.recursion,
-        )
+        self.job.clone()
     }
 
-    pub fn divide(&self, num_tasks: u32) -> Vec<Self> {
-        let width = self.screen_area.width();
-        let height = self.screen_area.height();
-
-        let num_columns = (num_tasks as f32).sqrt().ceil() as u32;
-        let num_rows = (num_tasks as f32 / num_columns as f32).ceil() as u32;
-
-        let task_width = width / num_columns as f32;
-        let task_height = height / num_rows as f32;
-
-        let mut tasks = Vec::new();
-
-        for row in 0..num_rows {
-            for col in 0..num_columns {
-                let x = col as f32 * task_width;
-                let y = row as f32 * task_height;
-                let w = task_width.min(width - x);
-                let h = task_height.min(height - y);
-
-                tasks.push(RenderTask {
-                    screen_area: Rectangle::new((x, y), (w, h)),
-                    settings: self.settings.clone(),
-                });
-            }
-        }
+    /// Splits the frame into at least `num_tasks` tasks, in the order of
+    /// `settings.order`. Tile borders are exact in camera pixels, the screen
+    /// areas are derived from them.
+    pub fn divide(settings: &RenderSettings, num_tasks: u32) -> Vec<Self> {
+        api::TilePlanner::new(settings.frame_job())
+            .with_order(settings.order)
+            .split_into(num_tasks)
+            .into_iter()
+            .map(RenderTask::from)
+            .collect()
+    }
+}
 
-        tasks
+impl RenderSettings {
+    /// A job covering the full frame.
+    fn frame_job(&self) -> api::RenderJob {
+        let (w, h) = self.resolution;
+        api::RenderJob::new(0, 0, w, h, w, h, self.samples, self.recursion)
+            .with_integrator(self.integrator)
+            .with_projection(self.projection)
+            .with_frame(self.frame)
+            .with_filter(self.filter)
     }
 }
 
 impl From<api::RenderJob> for RenderTask {
     fn from(job: api::RenderJob) -> Self {
-        let settings = RenderSettings {
-            resolution: (job.camera_w, job.camera_h),
-            samples: job.n_samples,
-            recursion: job.n_recursion,
-        };
-        let rx = Main::WIDTH as f32 / settings.resolution.0 as f32;
-        let ry = Main::HEIGHT as f32 / settings.resolution.1 as f32;
-        let pos = Vector::new((job.x as f32 * rx).round(), (job.y as f32 * ry).round());
-        let size = Vector::new((job.w as f32 * rx).round(), (job.h as f32 * ry).round());
+        let rx = Main::WIDTH as f32 / job.camera_w as f32;
+        let ry = Main::HEIGHT as f32 / job.camera_h as f32;
+        let pos = Vector::new(job.x as f32 * rx, job.y as f32 * ry);
+        let size = Vector::new(job.w as f32 * rx, job.h as f32 * ry);
         RenderTask {
             screen_area: Rectangle::new(pos, size),
-            settings,
+            job,
         }
     }
 }
@@ -5,15 +5,23 @@ use paddle::{Frame, FrameHandle, Rectangle};
 use crate::p2p_proto::UiUpdateBody;
 use crate::render::{RenderSettings, RenderTask};
 use crate::ui_slider::Slider;
+use crate::workers_view::{BatchSize, DEFAULT_BATCH_SIZE};
 use crate::{
     network, p2p_proto, palette, EnqueueNewRender, Main, RequestNewRender, PADDING, SECONDARY_H,
     SECONDARY_W, SECONDARY_X, SECONDARY_Y,
 };
 
+/// The first render after start-up is a quick ambient occlusion preview.
+const PREVIEW_LEVEL: u32 = 0;
+
+const SLIDER_HEIGHT: u32 = 160;
+
 pub(crate) struct RenderSettingsView {
     preset_level: Option<u32>,
     recursion: Slider<u32>,
     samples: Slider<u32>,
+    /// Tiles per request to HTTP workers, not shared with peers.
+    batch_size: Slider<usize>,
 }
 
 impl Frame for RenderSettingsView {
@@ -30,6 +38,7 @@ impl Frame for RenderSettingsView {
     ) {
         self.recursion.draw(canvas);
         self.samples.draw(canvas);
+        self.batch_size.draw(canvas);
     }
 
     fn pointer(&mut self, _state: &mut Self::State, event: paddle::PointerEvent) {
@@ -40,16 +49,21 @@ impl Frame for RenderSettingsView {
             self.preset_level = None;
             self.sync_settings_to_peers();
         }
+        if self.batch_size.adjust(event) {
+            paddle::share(BatchSize(*self.batch_size.value()));
+        }
     }
 
     fn enter(&mut self, _state: &mut Self::State) {
         self.recursion.active();
         self.samples.active();
+        self.batch_size.active();
     }
 
     fn leave(&mut self, _state: &mut Self::State) {
         self.recursion.inactive();
         self.samples.inactive();
+        self.batch_size.inactive();
     }
 }
 
@@ -59,7 +73,10 @@ impl RenderSettingsView {
         let secondary_color = palette::NEUTRAL;
         let knob_color = palette::NEUTRAL_DARK;
         let mut recursion = Slider::new(
-            Rectangle::new((PADDING, PADDING), (Self::WIDTH - 2 * PADDING, 200)),
+            Rectangle::new(
+                (PADDING, PADDING),
+                (Self::WIDTH - 2 * PADDING, SLIDER_HEIGHT),
+            ),
             "Reflection depth (color)".to_owned(),
             (1..17).collect(),
             main_color,
@@ -68,8 +85,8 @@ impl RenderSettingsView {
         );
         let mut samples = Slider::new(
             Rectangle::new(
-                (PADDING, 2 * PADDING + 200),
-                (Self::WIDTH - 2 * PADDING, 200),
+                (PADDING, 2 * PADDING + SLIDER_HEIGHT),
+                (Self::WIDTH - 2 * PADDING, SLIDER_HEIGHT),
             ),
             "Samples (smoothness)".to_owned(),
             iter::once(1)
@@ -81,13 +98,26 @@ impl RenderSettingsView {
             secondary_color,
             knob_color,
         );
-        let init = RenderSettings::preset(0);
+        let mut batch_size = Slider::new(
+            Rectangle::new(
+                (PADDING, 3 * PADDING + 2 * SLIDER_HEIGHT),
+                (Self::WIDTH - 2 * PADDING, SLIDER_HEIGHT),
+            ),
+            "Tiles per request (HTTP workers)".to_owned(),
+            vec![1, 2, 4, 8, 16, 32, 64],
+            main_color,
+            secondary_color,
+            knob_color,
+        );
+        let init = RenderSettings::preset(PREVIEW_LEVEL);
         samples.set_value(&init.0);
         recursion.set_value(&init.1);
+        batch_size.set_value(&DEFAULT_BATCH_SIZE);
         let this = Self {
-            preset_level: Some(0),
+            preset_level: Some(PREVIEW_LEVEL),
             recursion,
             samples,
+            batch_size,
         };
         let handle = paddle::register_frame_no_state(this, (SECONDARY_X, SECONDARY_Y));
         handle.listen(Self::ping_next_job);
@@ -102,8 +132,7 @@ impl RenderSettingsView {
     fn ping_next_job(&mut self, _: &mut (), _msg: &RequestNewRender) {
         let settings = self.render_settings();
         let num_jobs = settings.proposed_num_jobs();
-        let job = RenderTask::new(Main::area(), settings);
-        let jobs = job.divide(num_jobs);
+        let jobs = RenderTask::divide(&settings, num_jobs);
         paddle::send::<_, Main>(EnqueueNewRender(jobs));
     }
 
@@ -142,10 +171,20 @@ impl RenderSettingsView {
         } else {
             samples /= 4;
         }
+        let integrator = if self.preset_level == Some(PREVIEW_LEVEL) {
+            api::IntegratorKind::AmbientOcclusion
+        } else {
+            api::IntegratorKind::PathTracer
+        };
         RenderSettings {
             resolution,
             samples,
             recursion: *self.recursion.value(),
+            integrator,
+            projection: api::ProjectionKind::Perspective,
+            frame: 0,
+            filter: api::FilterKind::Box,
+            order: api::TileOrder::Spiral,
         }
     }
 
@@ -175,31 +214,35 @@ impl RenderSettings {
         let samples;
         let recursion;
         match level {
-            0 => {
+            PREVIEW_LEVEL => {
                 samples = 1;
-                recursion = 2;
+                recursion = 1;
             }
             1 => {
-                samples = 2;
+                samples = 1;
                 recursion = 2;
             }
             2 => {
+                samples = 2;
+                recursion = 2;
+            }
+            3 => {
                 samples = 4;
                 recursion = 3;
             }
-            3 => {
+            4 => {
                 samples = 8;
                 recursion = 4;
             }
-            4 => {
+            5 => {
                 samples = 16;
                 recursion = 6;
             }
-            5 => {
+            6 => {
                 samples = 32;
                 recursion = 8;
             }
-            6 | _ => {
+            7 | _ => {
                 samples = 256;
                 recursion = 16;
             }
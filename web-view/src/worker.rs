@@ -2,29 +2,60 @@ use js_sys::Uint32Array;
 use paddle::quicksilver_compat::{Color, Shape};
 use paddle::{FloatingText, ImageDesc, Rectangle, TextBoard, Transform};
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 use wasm_bindgen::prelude::Closure;
-use wasm_bindgen::JsCast;
-use web_sys::MessageEvent;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{MessageEvent, WorkerOptions};
 
 use crate::render::RenderTask;
 use crate::workers_view::WorkerView;
 use crate::ImageData;
 
 /// Worker has completed initialization.
-pub(crate) struct WorkerReady(pub usize);
+pub(crate) struct WorkerReady {
+    pub worker_id: usize,
+    /// Announced by HTTP workers, `None` for web workers and HTTP workers
+    /// that predate `/capabilities`.
+    pub capabilities: Option<api::Capabilities>,
+}
 
 /// Worker has completed as task
 pub(crate) struct WorkerResult {
     pub worker_id: usize,
+    pub result: api::RenderResult,
+}
+
+/// Worker has completed some rows of its task.
+pub(crate) struct WorkerPartialResult {
+    pub worker_id: usize,
+    /// First row, counted from the top of the task's image.
+    pub y: u32,
+    pub h: u32,
     pub img: ImageData,
 }
 
+/// Worker could not complete its task.
+pub(crate) struct WorkerFailed {
+    pub worker_id: usize,
+    pub message: String,
+    /// Whether the tasks may succeed when sent again, only if the worker
+    /// could not be reached. Tasks the worker answered with an error would
+    /// fail the same way again.
+    pub retry: bool,
+}
+
 pub(crate) struct PngRenderWorker {
-    current_job: Option<RenderTask>,
+    /// Tasks submitted and not finished yet, more than one for batches.
+    current_jobs: Vec<RenderTask>,
+    /// Tasks of the last submission, which share its time.
+    submitted: u32,
     ready: bool,
-    /// Marks an in-flight job while rendering was stopped. Reset when job finishes.
+    /// Marks in-flight jobs while rendering was stopped. Reset when all of
+    /// them finished.
     interrupted: bool,
     ctx: Box<dyn TaskRenderer>,
+    capabilities: Option<api::Capabilities>,
     displayable: Box<dyn paddle::DisplayPaint>,
     start: chrono::NaiveDateTime,
     prev_time: RefCell<FloatingText>,
@@ -32,12 +63,20 @@ pub(crate) struct PngRenderWorker {
     loading_img: ImageDesc,
 }
 pub(crate) trait TaskRenderer {
-    /// Enqueues a new task that will be executed eventually.
+    /// Enqueues new tasks that will be executed eventually.
     ///
-    /// When the task finishes, it will send a `WorkerResult`.
-    fn submit(&self, task: &RenderTask);
+    /// When a task finishes, it will send a `WorkerResult`.
+    fn submit(&self, tasks: &[RenderTask]);
 }
 
+/// Set once a web worker was asked to render on all cores. Further web
+/// workers render on one thread each, so that their pools do not compete for
+/// the same cores.
+static THREADED_WORKER_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Name of the web worker that starts a thread pool, see `worker.js`.
+const THREADED_WORKER_NAME: &str = "threaded";
+
 pub(crate) struct LocalWorkerContext {
     worker: web_sys::Worker,
     _worker_rx: Closure<dyn FnMut(MessageEvent)>,
@@ -49,29 +88,46 @@ pub(crate) struct RemoteWorkerContext {
 }
 
 impl TaskRenderer for LocalWorkerContext {
-    fn submit(&self, task: &RenderTask) {
-        let vec = task.marshal().to_vec();
-        let array = Uint32Array::new_with_length(vec.len() as u32);
-        array.copy_from(&vec);
+    fn submit(&self, tasks: &[RenderTask]) {
+        for task in tasks {
+            let vec = task.marshal().to_vec();
+            let array = Uint32Array::new_with_length(vec.len() as u32);
+            array.copy_from(&vec);
 
-        self.worker
-            .post_message(&array)
-            .expect("Failed posting job to worker");
+            self.worker
+                .post_message(&array)
+                .expect("Failed posting job to worker");
+        }
     }
 }
 
 impl TaskRenderer for RemoteWorkerContext {
-    fn submit(&self, task: &RenderTask) {
+    fn submit(&self, tasks: &[RenderTask]) {
         // Reading from this URL causes work on the remote, so let's not use it
         // directly. Instead, download the data and build a local url object.
-        let full_url = format!("{}/{}", self.url, task.marshal());
+        let url = self.url.clone();
+        let jobs: Vec<_> = tasks.iter().map(RenderTask::marshal).collect();
         let worker_id = self.worker_id;
         let future = async move {
-            let binary = paddle::load_file(&full_url).await.unwrap();
-            paddle::send::<_, WorkerView>(WorkerResult {
-                img: ImageData::new_from_vec(binary),
-                worker_id,
-            });
+            // a single job uses the plain route, which every worker serves
+            let results = if jobs.len() == 1 {
+                let full_url = format!("{url}/{}", jobs[0]);
+                fetch_result(&full_url).await.map(|result| vec![result])
+            } else {
+                fetch_batch(&format!("{url}/batch"), &api::RenderBatch::new(jobs)).await
+            };
+            match results {
+                Ok(results) => {
+                    for result in results {
+                        paddle::send::<_, WorkerView>(WorkerResult { result, worker_id });
+                    }
+                }
+                Err(err) => paddle::send::<_, WorkerView>(WorkerFailed {
+                    worker_id,
+                    retry: matches!(err, FetchError::Transport(_)),
+                    message: err.to_string(),
+                }),
+            }
         };
         wasm_bindgen_futures::spawn_local(future);
     }
@@ -80,10 +136,40 @@ impl TaskRenderer for RemoteWorkerContext {
 impl RemoteWorkerContext {
     pub(crate) fn new(url: String, worker_id: usize) -> Self {
         // construct this outside to make future independent of `url` lifetime
+        let capabilities_url = format!("{url}/capabilities");
         let ping_url = format!("{url}/ping");
         let future = async move {
+            match fetch_capabilities(&capabilities_url).await {
+                Ok(Some(capabilities)) => {
+                    // any tile of the web-view uses the defaults of these settings
+                    let job = api::RenderJob::new(0, 0, 1, 1, 1, 1, 1, 1);
+                    match capabilities.check_job(&job) {
+                        Ok(()) => paddle::send::<_, WorkerView>(WorkerReady {
+                            worker_id,
+                            capabilities: Some(capabilities),
+                        }),
+                        Err(err) => {
+                            let error_msg = format!("Worker {worker_id} is incompatible: {err}.");
+                            paddle::println!("{}", error_msg);
+                            TextBoard::display_error_message(error_msg).unwrap();
+                        }
+                    }
+                    return;
+                }
+                Err(err) => {
+                    let error_msg = format!("Worker {worker_id} sent invalid capabilities: {err}.");
+                    paddle::println!("{}", error_msg);
+                    TextBoard::display_error_message(error_msg).unwrap();
+                    return;
+                }
+                Ok(None) => {}
+            }
+            // older workers only answer pings
             match paddle::fetch::load_file(&ping_url).await {
-                Ok(s) if s == b"pong" => paddle::send::<_, WorkerView>(WorkerReady(worker_id)),
+                Ok(s) if s == b"pong" => paddle::send::<_, WorkerView>(WorkerReady {
+                    worker_id,
+                    capabilities: None,
+                }),
                 Ok(s) => {
                     TextBoard::display_error_message("Unexpected remote worker response.".into())
                         .unwrap();
@@ -123,9 +209,11 @@ impl PngRenderWorker {
         text.update_fit_strategy(paddle::FitStrategy::Center)
             .unwrap();
         PngRenderWorker {
-            current_job: None,
+            current_jobs: vec![],
+            submitted: 0,
             ready: false,
             ctx,
+            capabilities: None,
             start: paddle::utc_now(),
             displayable,
             prev_time: RefCell::new(text),
@@ -135,27 +223,64 @@ impl PngRenderWorker {
         }
     }
 
-    pub fn accept_task(&mut self, task: RenderTask) {
-        assert!(self.current_job.is_none());
+    pub fn accept_tasks(&mut self, tasks: Vec<RenderTask>) {
+        assert!(self.current_jobs.is_empty());
         self.start = paddle::utc_now();
-        self.ctx.submit(&task);
-        self.current_job = Some(task);
+        self.ctx.submit(&tasks);
+        self.submitted = tasks.len() as u32;
+        self.current_jobs = tasks;
+    }
+
+    pub fn current_tasks(&self) -> &[RenderTask] {
+        &self.current_jobs
     }
 
-    pub fn current_task(&self) -> &Option<RenderTask> {
-        &self.current_job
+    pub fn set_capabilities(&mut self, capabilities: Option<api::Capabilities>) {
+        self.capabilities = capabilities;
     }
 
-    pub fn clear_task(&mut self) -> Option<(RenderTask, std::time::Duration)> {
-        self.current_job.take().map(|job| {
-            (
-                job,
-                paddle::utc_now()
-                    .signed_duration_since(self.start)
-                    .to_std()
-                    .unwrap(),
-            )
-        })
+    /// Tasks to submit at once, at most `preferred`. Workers without
+    /// announced batch support get one at a time.
+    pub fn batch_size(&self, preferred: usize) -> usize {
+        self.capabilities
+            .as_ref()
+            .map_or(1, |capabilities| capabilities.batch_size(preferred))
+    }
+
+    /// Most rays of all tasks submitted at once, as far as it announced.
+    pub fn max_batch_rays(&self) -> u64 {
+        self.capabilities
+            .as_ref()
+            .map_or(u64::MAX, |capabilities| capabilities.limits.max_batch_rays)
+    }
+
+    /// Checks that the worker can render `task`, as far as it announced.
+    pub fn check_task(&self, task: &RenderTask) -> Result<(), api::IncompatibleWorker> {
+        match &self.capabilities {
+            Some(capabilities) => capabilities.check_job(&task.marshal()),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the task of `job`, returned with its share of the time since
+    /// the tasks were submitted. Tasks of a batch arrive together, each gets
+    /// an equal share.
+    pub fn take_task(&mut self, job: &api::RenderJob) -> Option<(RenderTask, std::time::Duration)> {
+        let i = self
+            .current_jobs
+            .iter()
+            .position(|task| task.marshal() == *job)?;
+        let task = self.current_jobs.remove(i);
+        let duration = paddle::utc_now()
+            .signed_duration_since(self.start)
+            .to_std()
+            .unwrap();
+        Some((task, duration / self.submitted.max(1)))
+    }
+
+    /// Removes all tasks.
+    pub fn take_tasks(&mut self) -> Vec<RenderTask> {
+        std::mem::take(&mut self.current_jobs)
     }
 
     pub fn ready(&self) -> bool {
@@ -174,24 +299,28 @@ impl PngRenderWorker {
 
     pub fn clear(&mut self) {
         if !self.interrupted {
-            self.clear_task();
+            self.take_tasks();
             self.prev_time.get_mut().update_text("...");
         }
     }
 
     pub fn interrupt(&mut self) {
-        if self.current_job.is_some() {
+        if !self.current_jobs.is_empty() {
             self.interrupted = true;
         }
     }
 
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+
     pub fn clear_interrupt(&mut self) -> bool {
         std::mem::take(&mut self.interrupted)
     }
 
     /// Display self in the specified area.
     pub fn draw(&self, canvas: &mut paddle::DisplayArea, area: Rectangle, timestamp: f64) {
-        if self.current_job.is_some() {
+        if !self.current_jobs.is_empty() {
             canvas.draw(&area, &Color::WHITE);
             let trans = Transform::translate(area.center())
                 * Transform::rotate(timestamp / 10.0)
@@ -224,19 +353,53 @@ impl PngRenderWorker {
 
 impl LocalWorkerContext {
     fn new(worker_id: usize) -> Self {
-        let worker = web_sys::Worker::new("./worker.js").expect("Failed to create worker");
+        let mut options = WorkerOptions::new();
+        if !THREADED_WORKER_STARTED.swap(true, Ordering::Relaxed) {
+            options.name(THREADED_WORKER_NAME);
+        }
+        let worker = web_sys::Worker::new_with_options("./worker.js", &options)
+            .expect("Failed to create worker");
 
         let rx = move |evt: MessageEvent| {
             if let Ok(array) = evt.data().dyn_into::<js_sys::Uint8Array>() {
-                paddle::send::<_, WorkerView>(WorkerResult {
-                    img: ImageData::new_from_array(array),
-                    worker_id,
-                });
+                match api::RenderResult::decode(&array.to_vec()) {
+                    Ok(result) => paddle::send::<_, WorkerView>(WorkerResult { result, worker_id }),
+                    Err(err) => paddle::send::<_, WorkerView>(WorkerFailed {
+                        worker_id,
+                        message: format!("invalid result: {err}"),
+                        retry: false,
+                    }),
+                }
             } else if let Some(s) = evt.data().as_string() {
                 match s.as_str() {
-                    "ready" => paddle::send::<_, WorkerView>(WorkerReady(worker_id)),
+                    "ready" => paddle::send::<_, WorkerView>(WorkerReady {
+                        worker_id,
+                        capabilities: None,
+                    }),
                     _ => {}
                 }
+            } else if let Some(partial) = partial_result(&evt.data()) {
+                // previews are only shown until the tile is done, a broken
+                // one is skipped
+                let (y, h, array) = partial;
+                match ImageData::new_from_array(array) {
+                    Ok(img) => paddle::send::<_, WorkerView>(WorkerPartialResult {
+                        worker_id,
+                        y,
+                        h,
+                        img,
+                    }),
+                    Err(err) => paddle::println!("Worker {worker_id} sent rows: {err}"),
+                }
+            } else if let Some(message) = js_sys::Reflect::get(&evt.data(), &"error".into())
+                .ok()
+                .and_then(|error| error.as_string())
+            {
+                paddle::send::<_, WorkerView>(WorkerFailed {
+                    worker_id,
+                    message,
+                    retry: false,
+                });
             } else {
                 paddle::println!("Unexpected message type!");
             }
@@ -246,3 +409,87 @@ impl LocalWorkerContext {
         LocalWorkerContext { worker, _worker_rx }
     }
 }
+
+/// Downloads the result of a job from an HTTP worker, with the metadata
+/// in the response headers.
+async fn fetch_result(url: &str) -> Result<api::RenderResult, FetchError> {
+    let (headers, body) = fetch(url, &web_sys::RequestInit::new()).await?;
+    api::RenderResult::from_headers(|name| headers.get(name).ok().flatten(), body)
+        .map_err(|err| FetchError::Answered(format!("invalid result: {err}")))
+}
+
+/// Reads what an HTTP worker supports, `None` if it does not tell. An answer
+/// that cannot be read is an error, not an older worker.
+async fn fetch_capabilities(url: &str) -> Result<Option<api::Capabilities>, serde_json::Error> {
+    let Ok((_, body)) = fetch(url, &web_sys::RequestInit::new()).await else {
+        return Ok(None);
+    };
+    serde_json::from_slice(&body).map(Some)
+}
+
+/// Renders all jobs of `batch` with a single request to an HTTP worker.
+async fn fetch_batch(
+    url: &str,
+    batch: &api::RenderBatch,
+) -> Result<Vec<api::RenderResult>, FetchError> {
+    // a string body is sent as text/plain, which needs no CORS preflight
+    let mut init = web_sys::RequestInit::new();
+    init.method("POST")
+        .body(Some(&JsValue::from_str(&batch.to_string())));
+    let (_, body) = fetch(url, &init).await?;
+    batch
+        .decode_results(&body)
+        .map_err(|err| FetchError::Answered(format!("invalid results: {err}")))
+}
+
+/// A request to an HTTP worker that failed.
+enum FetchError {
+    /// No answer arrived.
+    Transport(String),
+    /// The worker answered with an error status or an unreadable body.
+    Answered(String),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Transport(message) | FetchError::Answered(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Sends a request and reads the full response, failing on any status but
+/// success.
+async fn fetch(
+    url: &str,
+    init: &web_sys::RequestInit,
+) -> Result<(web_sys::Headers, Vec<u8>), FetchError> {
+    let js_error = |err: JsValue| FetchError::Transport(format!("request failed: {err:?}"));
+    let window = web_sys::window().ok_or(FetchError::Transport("no window".to_owned()))?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str_and_init(url, init))
+        .await
+        .and_then(|response| response.dyn_into())
+        .map_err(js_error)?;
+    if !response.ok() {
+        return Err(FetchError::Answered(format!(
+            "HTTP status {}",
+            response.status()
+        )));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok((
+        response.headers(),
+        js_sys::Uint8Array::new(&buffer).to_vec(),
+    ))
+}
+
+/// Reads a `{ y, height, png }` message of the worker.
+fn partial_result(data: &wasm_bindgen::JsValue) -> Option<(u32, u32, js_sys::Uint8Array)> {
+    let get = |key: &str| js_sys::Reflect::get(data, &key.into()).ok();
+    let png = get("png")?.dyn_into::<js_sys::Uint8Array>().ok()?;
+    let y = get("y")?.as_f64()? as u32;
+    let h = get("height")?.as_f64()? as u32;
+    Some((y, h, png))
+}
@@ -1,13 +1,21 @@
+use std::collections::HashSet;
+
+use api::{JobHash, ResultCache};
 use paddle::quicksilver_compat::Color;
 use paddle::{FloatingText, Frame, ImageDesc, Rectangle, TextBoard, UiElement};
 
 use crate::images::Images;
+use crate::job_pool::{self, JobPool};
 use crate::p2p_proto::{JobBody, RenderControlBody};
 use crate::peer_proxy::PeerProxy;
 use crate::progress::RenderProgress;
 use crate::render::RenderTask;
-use crate::worker::{PngRenderWorker, WorkerReady, WorkerResult};
-use crate::{button, network, p2p_proto, progress, PngPart, PADDING, SCREEN_W};
+use crate::worker::{
+    PngRenderWorker, WorkerFailed, WorkerPartialResult, WorkerReady, WorkerResult,
+};
+use crate::{
+    button, network, p2p_proto, progress, PngPart, PngPreview, TileFailed, PADDING, SCREEN_W,
+};
 
 const BACKGROUND: Color = crate::palette::NEUTRAL_DARK;
 const LOCAL_WORKER_COL: Color = crate::palette::MAIN;
@@ -18,13 +26,22 @@ const LEFT_COLUMN_WIDTH: u32 = 550;
 
 const MAX_FERMYON_WORKERS: usize = 1;
 const MAX_WORKERS: usize = 20;
+const _: () = assert!(MAX_WORKERS <= job_pool::MAX_WORKERS);
+/// Tiles kept for re-renders with unchanged settings.
+const CACHED_RESULTS: usize = 2048;
+/// Tiles sent to HTTP workers per request, until changed in the settings.
+pub(crate) const DEFAULT_BATCH_SIZE: usize = 8;
 
 /// Displays the connected workers and allows adding more workers.
 pub(crate) struct WorkerView {
     buttons: Vec<UiElement>,
     workers: Vec<PngRenderWorker>,
     fermyon_workers: usize,
-    job_pool: Vec<RenderTask>,
+    job_pool: JobPool,
+    /// Most tasks submitted to a worker at once, if the worker supports it.
+    batch_size: usize,
+    /// Results of finished jobs, served again without rendering.
+    cache: api::LruCache,
     fermyon_img: ImageDesc,
     worker_img: ImageDesc,
     loading_img: ImageDesc,
@@ -33,6 +50,9 @@ pub(crate) struct WorkerView {
     graphics_init: bool,
 }
 
+/// UI input to change how many tiles are sent per request.
+pub(crate) struct BatchSize(pub usize);
+
 #[derive(Clone, Copy)]
 pub enum AddWorker {
     InBrowser,
@@ -82,7 +102,9 @@ impl WorkerView {
             ],
             workers: vec![],
             fermyon_workers: 0,
-            job_pool: vec![],
+            job_pool: JobPool::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            cache: api::LruCache::new(CACHED_RESULTS),
             fermyon_img: imgs.fermyon,
             worker_img: imgs.worker,
             loading_img: imgs.loading,
@@ -138,10 +160,19 @@ impl Frame for WorkerView {
         if self.job_pool.is_empty() {
             self.peers.steal_work(self.workers.len());
         }
-        for worker in &mut self.workers {
-            if worker.ready() && worker.current_task().is_none() {
-                if let Some(job) = self.job_pool.pop() {
-                    worker.accept_task(job);
+        if let Some(error_msg) = self.job_pool.take_rejection() {
+            paddle::println!("{}", error_msg);
+            TextBoard::display_error_message(error_msg).unwrap();
+        }
+        for (worker_id, worker) in self.workers.iter_mut().enumerate() {
+            if worker.ready() && worker.current_tasks().is_empty() {
+                let tasks = self.job_pool.take(
+                    worker_id,
+                    worker.batch_size(self.batch_size),
+                    worker.max_batch_rays(),
+                );
+                if !tasks.is_empty() {
+                    worker.accept_tasks(tasks);
                 }
             }
         }
@@ -216,33 +247,103 @@ impl WorkerView {
                 self.fermyon_workers += 1;
             }
         }
+        self.job_pool
+            .update_worker(self.workers.len() - 1, &self.workers);
+    }
+
+    /// paddle event listener
+    pub fn set_batch_size(&mut self, _state: &mut (), BatchSize(n): &BatchSize) {
+        self.batch_size = (*n).max(1);
     }
 
     /// paddle event listener
-    pub fn worker_ready(&mut self, _state: &mut (), WorkerReady(worker_id): WorkerReady) {
+    pub fn worker_ready(
+        &mut self,
+        _state: &mut (),
+        WorkerReady {
+            worker_id,
+            capabilities,
+        }: WorkerReady,
+    ) {
+        self.workers[worker_id].set_capabilities(capabilities);
         self.workers[worker_id].set_ready(true);
+        self.job_pool.update_worker(worker_id, &self.workers);
     }
 
     /// paddle event listener
-    pub fn new_jobs(&mut self, _state: &mut (), job_pool: Vec<RenderTask>) {
-        self.job_pool = job_pool;
+    pub fn new_jobs(&mut self, _state: &mut (), mut job_pool: Vec<RenderTask>) {
+        // cached results were shown before, they only fail to show if the
+        // cache is broken, in which case they are rendered again
+        job_pool.retain(|task| {
+            let cached = self.cache.get(&task.marshal());
+            match cached.and_then(|result| PngPart::with_area(result, task.screen_area).ok()) {
+                Some(part) => {
+                    paddle::share(part);
+                    paddle::send::<_, progress::RenderProgress>(progress::ProgressMade::Cached);
+                    false
+                }
+                None => true,
+            }
+        });
+        // jobs are popped from the back, reversing keeps the planned order
+        job_pool.reverse();
+        self.job_pool.clear();
+        self.job_pool.extend(job_pool, &self.workers);
         self.workers.iter_mut().for_each(PngRenderWorker::clear);
     }
 
     /// paddle event listener
-    pub fn job_done(&mut self, _state: &mut (), WorkerResult { worker_id, img }: WorkerResult) {
-        if self.workers[worker_id].clear_interrupt() {
-            self.workers[worker_id].set_ready(true);
-            self.workers[worker_id].clear();
+    pub fn job_done(&mut self, _state: &mut (), WorkerResult { worker_id, result }: WorkerResult) {
+        let worker = &mut self.workers[worker_id];
+        let task = worker.take_task(&result.job);
+        if worker.interrupted() {
+            // results of a stopped render, wait until all tasks are back
+            if worker.current_tasks().is_empty() {
+                worker.clear_interrupt();
+                worker.set_ready(true);
+                worker.clear();
+            }
             return;
         }
-        let (job, duration) = self.workers[worker_id]
-            .clear_task()
-            .expect("result must belong to a job");
-        paddle::share(PngPart {
-            img,
-            screen_area: job.screen_area,
-        });
+        let (job, duration) = match task {
+            Some(task) => task,
+            None => {
+                let error_msg = format!(
+                    "Worker {worker_id} failed: result of a job it was not given, {}",
+                    result.job
+                );
+                paddle::println!("{}", error_msg);
+                TextBoard::display_error_message(error_msg).unwrap();
+                let tasks = worker.take_tasks();
+                worker.set_ready(true);
+                self.job_pool.extend(tasks, &self.workers);
+                return;
+            }
+        };
+        // a wrong or broken result is reported and the task is rendered
+        // again, by whichever worker is ready first
+        let part = result
+            .check_job(&job.marshal())
+            .map_err(|err| err.to_string())
+            .and_then(|()| PngPart::with_area(result, job.screen_area));
+        let part = match part {
+            Ok(part) => part,
+            Err(err) => {
+                let error_msg = format!("Worker {worker_id} failed: {err}");
+                paddle::println!("{}", error_msg);
+                TextBoard::display_error_message(error_msg).unwrap();
+                paddle::share(TileFailed {
+                    screen_area: job.screen_area,
+                });
+                self.job_pool.extend([job], &self.workers);
+                self.workers[worker_id].set_ready(true);
+                return;
+            }
+        };
+        if let Some(result) = &part.result {
+            self.cache.put(result);
+        }
+        paddle::share(part);
         self.workers[worker_id].record_time(duration);
         paddle::send::<_, progress::RenderProgress>(progress::ProgressMade::Domestic {
             worker_id: worker_id,
@@ -252,6 +353,66 @@ impl WorkerView {
         self.workers[worker_id].set_ready(true);
     }
 
+    /// paddle event listener
+    pub fn job_progress(
+        &mut self,
+        _state: &mut (),
+        WorkerPartialResult {
+            worker_id,
+            y,
+            h,
+            img,
+        }: WorkerPartialResult,
+    ) {
+        let worker = &self.workers[worker_id];
+        // only web workers send partial results, one task at a time
+        let task = match worker.current_tasks().first() {
+            Some(task) if !worker.interrupted() => task,
+            _ => return,
+        };
+        let area = task.screen_area;
+        let rows = task.marshal().h.max(1) as f32;
+        paddle::share(PngPreview {
+            img,
+            screen_area: Rectangle::new(
+                (area.x(), area.y() + area.height() * y as f32 / rows),
+                (area.width(), area.height() * h as f32 / rows),
+            ),
+        });
+    }
+
+    /// paddle event listener
+    pub fn job_failed(
+        &mut self,
+        _state: &mut (),
+        WorkerFailed {
+            worker_id,
+            message,
+            retry,
+        }: WorkerFailed,
+    ) {
+        let worker = &mut self.workers[worker_id];
+        let interrupted = worker.clear_interrupt();
+        let tasks = worker.take_tasks();
+        // a worker that fails without a task could not even start
+        worker.set_ready(!tasks.is_empty());
+        for task in &tasks {
+            paddle::share(TileFailed {
+                screen_area: task.screen_area,
+            });
+        }
+        if !interrupted {
+            let error_msg = format!("Worker {worker_id} failed: {message}");
+            paddle::println!("{}", error_msg);
+            TextBoard::display_error_message(error_msg).unwrap();
+            // tasks that did not reach the worker are rendered again, by
+            // whichever worker is ready first, rejected ones are dropped
+            if retry {
+                self.job_pool.extend(tasks, &self.workers);
+            }
+        }
+    }
+
     /// paddle event listener
     pub fn stop(&mut self, _state: &mut (), _msg: &crate::Stop) {
         self.stop_local();
@@ -271,15 +432,37 @@ impl WorkerView {
         match msg {
             p2p_proto::Message::StealWork(body) => {
                 // respond with 0 to N jobs
-                let take_after = self.job_pool.len().saturating_sub(body.num_jobs as usize);
-                let jobs: Vec<_> = self.job_pool.drain(take_after..).collect();
-                let response = p2p_proto::Message::Job(JobBody { jobs });
+                let jobs = self.job_pool.take_any(body.num_jobs as usize);
+                let response = p2p_proto::Message::Job(JobBody {
+                    jobs,
+                    unreadable: vec![],
+                });
                 let size_guess = 1 + body.num_jobs as usize * 4 * 8;
                 // TODO: send response to requesting peer only! Broadcast leads to work multiplication.
                 network::broadcast_async(response, Some(size_guess));
             }
             p2p_proto::Message::Job(msg) => {
-                self.job_pool.extend_from_slice(&msg.jobs);
+                // Job messages are broadcast, several peers may hand out the
+                // same tile. Keep one copy of each.
+                let mut known: HashSet<JobHash> = self
+                    .job_pool
+                    .tasks()
+                    .chain(self.workers.iter().flat_map(|w| w.current_tasks()))
+                    .map(|task| task.marshal().content_hash())
+                    .collect();
+                // other formats than PNG can not be shown, rendering them
+                // would fail on every worker
+                let (shown, unshown): (Vec<_>, Vec<_>) = msg
+                    .jobs
+                    .iter()
+                    .partition(|task| task.marshal().format == api::OutputFormat::Png);
+                for task in unshown {
+                    paddle::println!("dropped job {}, only PNG can be shown", task.marshal());
+                }
+                let new_jobs = shown
+                    .into_iter()
+                    .filter(|task| known.insert(task.marshal().content_hash()));
+                self.job_pool.extend(new_jobs.cloned(), &self.workers);
             }
             p2p_proto::Message::RenderedPart(_) => (),
             p2p_proto::Message::UiUpdate(_) => (),
@@ -292,6 +475,26 @@ impl WorkerView {
         self.peers.peer_message(msg);
     }
 
+    /// paddle event listener: jobs handed out by a peer that this version
+    /// cannot read go back to that peer only, which can still render them.
+    /// Jobs that come in without being asked for are such answers themselves,
+    /// sending them on would pass them back and forth forever.
+    pub(crate) fn unreadable_jobs(&mut self, _state: &mut (), msg: &network::UnreadableJobs) {
+        if !self.peers.request_in_flight() {
+            paddle::println!(
+                "dropped {} jobs sent back by {}, no peer can read them",
+                msg.jobs.len(),
+                msg.peer_id
+            );
+            return;
+        }
+        let response = p2p_proto::Message::Job(JobBody {
+            jobs: vec![],
+            unreadable: msg.jobs.clone(),
+        });
+        network::send_async(msg.peer_id.clone(), response, None);
+    }
+
     /// paddle event listener
     pub(crate) fn new_peer(
         &mut self,
@@ -24,9 +24,19 @@ module.exports = {
                 { from: 'worker.js' },
                 { from: 'clumsy_rt.js' },
                 { from: 'clumsy_rt_bg.wasm' },
+                // optional multi-threaded build, see `make threads`
+                { from: 'threads', to: 'threads', noErrorOnMissing: true },
             ]
         }),
     ],
+    devServer: {
+        // cross-origin isolation, required for SharedArrayBuffer and
+        // therefore for the multi-threaded renderer
+        headers: {
+            'Cross-Origin-Opener-Policy': 'same-origin',
+            'Cross-Origin-Embedder-Policy': 'require-corp',
+        },
+    },
     experiments: {
         asyncWebAssembly: true,
         topLevelAwait: true,
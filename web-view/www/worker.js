@@ -1,19 +1,54 @@
-importScripts('./clumsy_rt.js');
-
-const { render } = wasm_bindgen;
-
-async function run_in_worker() {
+// Loads the multi-threaded build of the ray tracer if the page is
+// cross-origin isolated and the build is present, otherwise the
+// single-threaded one. Both export the same API. Only the worker named
+// "threaded" starts a thread pool, one pool already uses all cores.
+async function load_renderer() {
+    if (self.crossOriginIsolated && self.name === 'threaded') {
+        try {
+            const threaded = await import('./threads/clumsy_rt.js');
+            await threaded.default('./threads/clumsy_rt_bg.wasm');
+            await threaded.initThreadPool(navigator.hardwareConcurrency);
+            return threaded;
+        } catch (err) {
+            console.warn('Multi-threaded rendering unavailable, using a single thread.', err);
+        }
+    }
+    importScripts('./clumsy_rt.js');
     // Load the wasm file by awaiting the Promise returned by `wasm_bindgen`.
     await wasm_bindgen('./clumsy_rt_bg.wasm');
+    return wasm_bindgen;
+}
+
+async function run_in_worker() {
+    const { RenderJob, renderJobRows } = await load_renderer();
     // Set callback to handle messages passed to the worker.
     self.onmessage = async event => {
-        let job = event.data;
-        let png = render(job);
-
-        // Send response back to be handled by callback in main thread.
-        self.postMessage(png);
-    };    
+        let job;
+        let result;
+        try {
+            job = RenderJob.fromArray(event.data);
+            // previews and results are shown as PNG images
+            if (job.format !== 0) {
+                throw new Error(`cannot show output format ${job.format}, only PNG`);
+            }
+            // Partial buffers for a live preview, the full tile follows.
+            result = renderJobRows(job, (y, height, png) => {
+                self.postMessage({ y, height, png }, [png.buffer]);
+            });
+            // Send the image with its job and stats back to the main thread.
+            const bytes = result.toBytes();
+            self.postMessage(bytes, [bytes.buffer]);
+        } catch (err) {
+            self.postMessage({ error: err instanceof Error ? err.message : String(err) });
+        } finally {
+            job?.free();
+            result?.free();
+        }
+    };
     self.postMessage("ready");
 }
 
-run_in_worker();
\ No newline at end of file
+run_in_worker().catch(err => {
+    // without a task, the web-view keeps this worker out of the pool
+    self.postMessage({ error: `Loading the ray tracer failed: ${err}` });
+});